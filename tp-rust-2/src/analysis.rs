@@ -0,0 +1,568 @@
+//! Static control-flow analysis of binaries.
+//!
+//! Jumps are ordinary writes to IP (`loadimm r0 <- #print`,
+//! `move r0 <- r9 if r8 != 0`), so their targets are found by propagating
+//! constant register values along the paths of the program. A write to IP
+//! whose value is not constant, such as the `load r0 <- [r3]` returning
+//! from a subroutine, is an indirect jump: its targets are assumed to be
+//! the constants stored in memory by `store`, i.e. the return addresses
+//! pushed by the callers, which are explored as additional entry points.
+//!
+//! The resulting [Cfg] holds the basic blocks reachable from address 0 and
+//! the [issues](Issue) found on the way. It can be exported to Graphviz
+//! with [Cfg::write_dot].
+
+use crate::{Instruction, Isa, MachineErrorKind};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::io::{self, Write};
+
+/// How control reaches the target of an [Edge].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Execution continues with the next instruction.
+    FallThrough,
+    /// Unconditional jump.
+    Jump,
+    /// Conditional jump, when taken.
+    Branch,
+}
+
+/// Transfer of control to the block starting at `target`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub target: usize,
+    pub kind: EdgeKind,
+}
+
+/// Straight-line sequence of instructions, entered at its first one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    /// Instructions with their addresses.
+    pub instructions: Vec<(usize, Instruction)>,
+    pub successors: Vec<Edge>,
+    /// The block ends with a jump whose target is not constant.
+    pub indirect: bool,
+}
+
+/// Problem found while exploring the program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// These bytes are never executed.
+    Unreachable { start: usize, end: usize },
+    /// The byte at this reachable address is not a valid opcode.
+    InvalidOpcode { address: usize, opcode: u8 },
+    /// The reachable instruction at this address does not fit in the code.
+    Truncated { address: usize },
+    /// Control goes from the instruction at `from` to `target`, which is
+    /// inside the instruction starting at `instruction`.
+    MidInstruction {
+        from: usize,
+        target: usize,
+        instruction: usize,
+    },
+    /// Control goes from the instruction at `from` to `target`, which is
+    /// outside of the code.
+    OutsideCode { from: usize, target: usize },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Issue::Unreachable { start, end } => {
+                write!(f, "{:04}-{:04}: unreachable", start, end - 1)
+            }
+            Issue::InvalidOpcode { address, opcode } => {
+                write!(f, "{:04}: invalid opcode {}", address, opcode)
+            }
+            Issue::Truncated { address } => write!(f, "{:04}: truncated instruction", address),
+            Issue::MidInstruction {
+                from,
+                target,
+                instruction,
+            } => write!(
+                f,
+                "{:04}: jump to {:04} inside the instruction at {:04}",
+                from, target, instruction
+            ),
+            Issue::OutsideCode { from, target } => {
+                write!(f, "{:04}: jump to {} outside of the code", from, target)
+            }
+        }
+    }
+}
+
+/// Control-flow graph of a binary.
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    /// Reachable blocks, indexed by their first address.
+    pub blocks: BTreeMap<usize, Block>,
+    /// Constants stored in memory and used as targets of indirect jumps.
+    pub indirect_targets: BTreeSet<usize>,
+    pub issues: Vec<Issue>,
+}
+
+/// Analyze `code`, loaded at address 0 and entered there, with the base
+/// instruction set.
+pub fn analyze(code: &[u8]) -> Cfg {
+    analyze_for(code, Isa::Base)
+}
+
+/// Similar to [analyze], decoding the instructions of `isa`.
+pub fn analyze_for(code: &[u8], isa: Isa) -> Cfg {
+    let mut explorer = Explorer {
+        code,
+        isa,
+        nodes: BTreeMap::new(),
+        states: HashMap::new(),
+        indirect_targets: BTreeSet::new(),
+        worklist: Vec::new(),
+    };
+    explorer.visit(0, &unknown());
+    while let Some(address) = explorer.worklist.pop() {
+        explorer.explore(address);
+    }
+    explorer.into_cfg()
+}
+
+impl Cfg {
+    /// Block starting at `address`.
+    pub fn block(&self, address: usize) -> Option<&Block> {
+        self.blocks.get(&address)
+    }
+
+    /// Write the graph in the DOT language of Graphviz, naming blocks after
+    /// `labels` when one points to their start.
+    pub fn write_dot<W: Write>(
+        &self,
+        labels: &HashMap<String, usize>,
+        out: &mut W,
+    ) -> io::Result<()> {
+        let mut names: BTreeMap<usize, Vec<&str>> = BTreeMap::new();
+        for (label, &address) in labels {
+            names.entry(address).or_default().push(label);
+        }
+        writeln!(out, "digraph cfg {{")?;
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];")?;
+        for block in self.blocks.values() {
+            let mut title = format!("{:04}", block.start);
+            if let Some(names) = names.get_mut(&block.start) {
+                names.sort_unstable();
+                title = format!("{} <{}>", title, names.join(", "));
+            }
+            let mut label = format!("{}:\\l", title);
+            for (address, instruction) in &block.instructions {
+                label.push_str(&format!("  {:04}   {}\\l", address, instruction));
+            }
+            if block.indirect {
+                label.push_str("  -> ?\\l");
+            }
+            writeln!(out, "    b{} [label=\"{}\"];", block.start, label)?;
+        }
+        for issue in &self.issues {
+            let (address, text) = match issue {
+                Issue::InvalidOpcode { address, .. } | Issue::Truncated { address } => {
+                    (*address, issue.to_string())
+                }
+                Issue::OutsideCode { target, .. } => {
+                    (*target, format!("{}: outside of the code", target))
+                }
+                _ => continue,
+            };
+            writeln!(out, "    b{} [label=\"{}\", color=red];", address, text)?;
+        }
+        for block in self.blocks.values() {
+            for edge in &block.successors {
+                let style = match edge.kind {
+                    EdgeKind::FallThrough => "",
+                    EdgeKind::Jump => " [style=bold]",
+                    EdgeKind::Branch => " [style=dashed]",
+                };
+                writeln!(out, "    b{} -> b{}{};", block.start, edge.target, style)?;
+            }
+        }
+        writeln!(out, "}}")
+    }
+}
+
+/// Known register values, `None` when not constant.
+type State = Vec<Option<u32>>;
+
+fn unknown() -> State {
+    vec![None; 256]
+}
+
+/// What the analysis knows about an explored address.
+#[derive(Debug)]
+enum Node {
+    Instruction {
+        instruction: Instruction,
+        size: usize,
+        successors: Vec<Edge>,
+        indirect: bool,
+    },
+    Invalid(Issue),
+}
+
+impl Node {
+    /// Whether the only successor is the next instruction.
+    fn only_falls_through(&self) -> bool {
+        matches!(
+            self,
+            Node::Instruction { successors, indirect: false, .. } if successors.len() == 1
+        )
+    }
+}
+
+struct Explorer<'a> {
+    code: &'a [u8],
+    isa: Isa,
+    nodes: BTreeMap<usize, Node>,
+    states: HashMap<usize, State>,
+    indirect_targets: BTreeSet<usize>,
+    worklist: Vec<usize>,
+}
+
+impl Explorer<'_> {
+    /// Merge `state` into the state at `address`, scheduling it for
+    /// exploration if it changed.
+    fn visit(&mut self, address: usize, state: &State) {
+        let changed = match self.states.get_mut(&address) {
+            None => {
+                self.states.insert(address, state.clone());
+                true
+            }
+            Some(known) => {
+                let mut changed = false;
+                for (known, value) in known.iter_mut().zip(state) {
+                    if known.is_some() && known != value {
+                        *known = None;
+                        changed = true;
+                    }
+                }
+                changed
+            }
+        };
+        if changed {
+            self.worklist.push(address);
+        }
+    }
+
+    fn explore(&mut self, address: usize) {
+        if address >= self.code.len() {
+            // Reported by the jumping instruction
+            return;
+        }
+        let (instruction, size) = match Instruction::decode_for(&self.code[address..], self.isa) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                let issue = match e.kind {
+                    MachineErrorKind::InvalidOpcode(opcode) => {
+                        Issue::InvalidOpcode { address, opcode }
+                    }
+                    _ => Issue::Truncated { address },
+                };
+                self.nodes.insert(address, Node::Invalid(issue));
+                return;
+            }
+        };
+        let mut state = self.states[&address].clone();
+        let next = (address + size) as u32;
+        let flow = transfer(instruction, next, &mut state);
+        // Constants stored in memory may be return addresses
+        if let Instruction::Store(_, b) = instruction {
+            if let Some(target) = value(&state, b, next).map(|v| v as usize) {
+                if target < self.code.len() && self.indirect_targets.insert(target) {
+                    self.visit(target, &unknown());
+                }
+            }
+        }
+
+        let mut successors = Vec::new();
+        let mut indirect = false;
+        match flow {
+            Flow::Next => successors.push((next, EdgeKind::FallThrough)),
+            Flow::Jump(target) => successors.push((target, EdgeKind::Jump)),
+            Flow::Branch(target) => {
+                successors.push((next, EdgeKind::FallThrough));
+                successors.push((target, EdgeKind::Branch));
+            }
+            Flow::Indirect => indirect = true,
+            Flow::IndirectBranch => {
+                successors.push((next, EdgeKind::FallThrough));
+                indirect = true;
+            }
+            Flow::Exit => (),
+        }
+        let successors: Vec<Edge> = successors
+            .into_iter()
+            .map(|(target, kind)| Edge {
+                target: target as usize,
+                kind,
+            })
+            .collect();
+        for edge in &successors {
+            self.visit(edge.target, &state);
+        }
+        self.nodes.insert(
+            address,
+            Node::Instruction {
+                instruction,
+                size,
+                successors,
+                indirect,
+            },
+        );
+    }
+
+    fn into_cfg(self) -> Cfg {
+        // Only keep what is reachable with the final successors, as
+        // targets found with intermediate states may have been dropped
+        let mut roots = self.indirect_targets.clone();
+        roots.insert(0);
+        let mut reachable = BTreeSet::new();
+        let mut pending: Vec<usize> = roots.iter().copied().collect();
+        while let Some(address) = pending.pop() {
+            if reachable.insert(address) {
+                if let Some(Node::Instruction { successors, .. }) = self.nodes.get(&address) {
+                    pending.extend(successors.iter().map(|edge| edge.target));
+                }
+            }
+        }
+
+        let mut issues = Vec::new();
+        let mut predecessors: HashMap<usize, Vec<(usize, EdgeKind)>> = HashMap::new();
+        for &address in &reachable {
+            match self.nodes.get(&address) {
+                Some(Node::Instruction { successors, .. }) => {
+                    for edge in successors {
+                        predecessors
+                            .entry(edge.target)
+                            .or_default()
+                            .push((address, edge.kind));
+                        if edge.target >= self.code.len() {
+                            issues.push(Issue::OutsideCode {
+                                from: address,
+                                target: edge.target,
+                            });
+                        }
+                    }
+                }
+                Some(Node::Invalid(issue)) => issues.push(issue.clone()),
+                None => (),
+            }
+        }
+
+        // Bytes belonging to reachable instructions
+        let mut covered = BTreeSet::new();
+        for &address in &reachable {
+            match self.nodes.get(&address) {
+                Some(Node::Instruction { size, .. }) => covered.extend(address..address + size),
+                Some(Node::Invalid(Issue::Truncated { .. })) => {
+                    covered.extend(address..self.code.len())
+                }
+                Some(Node::Invalid(_)) => {
+                    covered.insert(address);
+                }
+                None => (),
+            }
+        }
+        for &address in &reachable {
+            if let Some(Node::Instruction { successors, .. }) = self.nodes.get(&address) {
+                for edge in successors
+                    .iter()
+                    .filter(|e| e.kind != EdgeKind::FallThrough)
+                {
+                    let inside = reachable
+                        .range(edge.target.saturating_sub(3)..edge.target)
+                        .find_map(|&start| match self.nodes.get(&start) {
+                            Some(Node::Instruction { size, .. }) if start + size > edge.target => {
+                                Some(start)
+                            }
+                            _ => None,
+                        });
+                    if let Some(instruction) = inside {
+                        issues.push(Issue::MidInstruction {
+                            from: address,
+                            target: edge.target,
+                            instruction,
+                        });
+                    }
+                }
+            }
+        }
+        let mut start = None;
+        for byte in 0..=self.code.len() {
+            match (start, covered.contains(&byte) || byte == self.code.len()) {
+                (None, false) => start = Some(byte),
+                (Some(s), true) => {
+                    issues.push(Issue::Unreachable {
+                        start: s,
+                        end: byte,
+                    });
+                    start = None;
+                }
+                _ => (),
+            }
+        }
+
+        // A block starts at an entry point, at a jump target, or after an
+        // instruction which does not simply fall through
+        let is_leader = |address: usize| {
+            roots.contains(&address)
+                || match predecessors.get(&address) {
+                    Some(from) if from.len() == 1 && from[0].1 == EdgeKind::FallThrough => {
+                        !self.nodes[&from[0].0].only_falls_through()
+                    }
+                    _ => true,
+                }
+        };
+        let mut blocks = BTreeMap::new();
+        for &leader in reachable.iter().filter(|&&a| is_leader(a)) {
+            let mut block = Block {
+                start: leader,
+                instructions: Vec::new(),
+                successors: Vec::new(),
+                indirect: false,
+            };
+            let mut address = leader;
+            while let Some(Node::Instruction {
+                instruction,
+                successors,
+                indirect,
+                ..
+            }) = self.nodes.get(&address)
+            {
+                block.instructions.push((address, *instruction));
+                match successors[..] {
+                    [Edge {
+                        target,
+                        kind: EdgeKind::FallThrough,
+                    }] if !indirect
+                        && !is_leader(target)
+                        && matches!(self.nodes.get(&target), Some(Node::Instruction { .. })) =>
+                    {
+                        address = target;
+                    }
+                    _ => {
+                        block.successors = successors.clone();
+                        block.indirect = *indirect;
+                        break;
+                    }
+                }
+            }
+            if !block.instructions.is_empty() {
+                blocks.insert(leader, block);
+            }
+        }
+
+        issues.sort_by_key(|issue| match issue {
+            Issue::Unreachable { start, .. } => *start,
+            Issue::InvalidOpcode { address, .. } | Issue::Truncated { address } => *address,
+            Issue::MidInstruction { from, .. } | Issue::OutsideCode { from, .. } => *from,
+        });
+        Cfg {
+            blocks,
+            indirect_targets: self.indirect_targets,
+            issues,
+        }
+    }
+}
+
+/// Effect of an instruction on the control flow.
+enum Flow {
+    Next,
+    Jump(u32),
+    /// Jump to the target or continue with the next instruction.
+    Branch(u32),
+    Indirect,
+    /// Jump to an unknown target or continue with the next instruction.
+    IndirectBranch,
+    Exit,
+}
+
+/// Value of `reg` before the instruction, IP pointing at `next`.
+fn value(state: &State, reg: u8, next: u32) -> Option<u32> {
+    match reg {
+        0 => Some(next),
+        _ => state[reg as usize],
+    }
+}
+
+/// Update `state` with the effect of `instruction`, whose next
+/// instruction is at `next`.
+fn transfer(instruction: Instruction, next: u32, state: &mut State) -> Flow {
+    let get = |state: &State, reg: u8| value(state, reg, next);
+    let relative = |offset: i16| next.wrapping_add(offset as i32 as u32);
+    let (a, result) = match instruction {
+        Instruction::Exit | Instruction::ExitWith(_) => return Flow::Exit,
+        Instruction::Jmp(offset) => return Flow::Jump(relative(offset)),
+        Instruction::Jnz(a, offset) | Instruction::Jz(a, offset) => {
+            let jnz = matches!(instruction, Instruction::Jnz(..));
+            return match get(state, a) {
+                Some(value) if (value != 0) == jnz => Flow::Jump(relative(offset)),
+                Some(_) => Flow::Next,
+                None => Flow::Branch(relative(offset)),
+            };
+        }
+        Instruction::MoveIf(a, b, c) => {
+            let value = get(state, b);
+            match get(state, c) {
+                Some(0) => return Flow::Next,
+                Some(_) => (a, value),
+                None if a == 0 => {
+                    return match value {
+                        Some(target) => Flow::Branch(target),
+                        None => Flow::IndirectBranch,
+                    }
+                }
+                // Either the old or the new value
+                None => (a, value.filter(|&v| state[a as usize] == Some(v))),
+            }
+        }
+        Instruction::LoadImm(a, value) => (a, Some(i32::from(value) as u32)),
+        Instruction::Sub(a, b, c) => (a, binary(state, b, c, next, u32::wrapping_sub)),
+        Instruction::Add(a, b, c) => (a, binary(state, b, c, next, u32::wrapping_add)),
+        Instruction::Mul(a, b, c) => (a, binary(state, b, c, next, u32::wrapping_mul)),
+        Instruction::And(a, b, c) => (a, binary(state, b, c, next, |b, c| b & c)),
+        Instruction::Or(a, b, c) => (a, binary(state, b, c, next, |b, c| b | c)),
+        Instruction::Xor(a, b, c) => (a, binary(state, b, c, next, |b, c| b ^ c)),
+        Instruction::In(a, b) | Instruction::InNumber(a, b) => {
+            state[b as usize] = None;
+            if b == 0 {
+                return Flow::Indirect;
+            }
+            (a, None)
+        }
+        Instruction::Load(a, _)
+        | Instruction::LoadByte(a, _)
+        | Instruction::Div(a, _, _)
+        | Instruction::Mod(a, _, _)
+        | Instruction::Shl(a, _, _)
+        | Instruction::Shr(a, _, _)
+        | Instruction::Sar(a, _, _)
+        | Instruction::Cmp(a, _, _) => (a, None),
+        Instruction::Store(..)
+        | Instruction::StoreByte(..)
+        | Instruction::Out(_)
+        | Instruction::OutNumber(_)
+        | Instruction::OutChar(_)
+        | Instruction::OutUnsigned(_)
+        | Instruction::OutHex(_)
+        | Instruction::OutBinary(_) => return Flow::Next,
+    };
+    if a == 0 {
+        match result {
+            Some(target) => Flow::Jump(target),
+            None => Flow::Indirect,
+        }
+    } else {
+        state[a as usize] = result;
+        Flow::Next
+    }
+}
+
+fn binary(state: &State, b: u8, c: u8, next: u32, op: fn(u32, u32) -> u32) -> Option<u32> {
+    Some(op(value(state, b, next)?, value(state, c, next)?))
+}
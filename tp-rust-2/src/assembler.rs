@@ -0,0 +1,432 @@
+//! Two-pass assembler for the textual syntax used in the `.dis` listings.
+//!
+//! Each line holds at most one of:
+//!   - a label definition (`mult_loop:`),
+//!   - an instruction (`loadimm r3 <- #mult_loop`, `store [r2] <- r10`, ...),
+//!   - raw data, either a Python-like byte string (`b'Hello\n'`) or a
+//!     list of bytes (`[0, 0, 0, 0]`).
+//!
+//! Instructions and data may be preceded by their address (`0012`) as
+//! found in the listings, or by `????`. When an address is given it must
+//! match the address computed by the assembler. Everything following a `;`
+//! outside of a string is a comment.
+//!
+//! The instructions of the [extended](crate::Isa::Extended) set are always
+//! accepted. The immediate of a relative branch (`jmp`, `jnz`, `jz`) is an
+//! offset from the next instruction, or a `#label` whose offset is computed
+//! by the assembler.
+//!
+//! The first pass computes the address of every label, the second pass
+//! encodes the instructions and resolves the `#label` immediates.
+
+use crate::Instruction;
+use std::{collections::HashMap, fmt};
+
+/// Errors detected while assembling a program. Line numbers start at 1.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AsmError {
+    /// The line could not be parsed.
+    Syntax { line: usize, text: String },
+    /// A register name is not of the form `rN` with N between 0 and 255.
+    InvalidRegister { line: usize, register: String },
+    /// An immediate value (or label address) does not fit in 16 signed bits.
+    ImmediateOutOfRange { line: usize, value: i64 },
+    /// A `#label` immediate refers to a label which is never defined.
+    UnknownLabel { line: usize, label: String },
+    /// A label is defined more than once.
+    DuplicateLabel { line: usize, label: String },
+    /// The address written in front of the line is not the one computed.
+    AddressMismatch {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsmError::Syntax { line, text } => {
+                write!(f, "line {}: syntax error in `{}`", line, text)
+            }
+            AsmError::InvalidRegister { line, register } => {
+                write!(f, "line {}: invalid register `{}`", line, register)
+            }
+            AsmError::ImmediateOutOfRange { line, value } => {
+                write!(
+                    f,
+                    "line {}: immediate {} does not fit in 16 bits",
+                    line, value
+                )
+            }
+            AsmError::UnknownLabel { line, label } => {
+                write!(f, "line {}: unknown label `{}`", line, label)
+            }
+            AsmError::DuplicateLabel { line, label } => {
+                write!(f, "line {}: label `{}` is defined twice", line, label)
+            }
+            AsmError::AddressMismatch {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: address {:04} given but instruction is at {:04}",
+                line, found, expected
+            ),
+        }
+    }
+}
+
+/// One line of the program once parsed.
+#[derive(Debug)]
+enum Statement {
+    Instruction(Instruction),
+    /// `loadimm rA <- #label`, resolved during the second pass.
+    LoadLabel(u8, String),
+    /// Relative branch to a label, resolved during the second pass.
+    BranchLabel(Instruction, String),
+    Data(Vec<u8>),
+}
+
+impl Statement {
+    /// Number of bytes taken by this statement in memory.
+    fn size(&self) -> usize {
+        match self {
+            Statement::Instruction(instruction) => instruction.size(),
+            Statement::LoadLabel(..) => 4,
+            Statement::BranchLabel(instruction, _) => instruction.size(),
+            Statement::Data(bytes) => bytes.len(),
+        }
+    }
+}
+
+/// Assemble `source` into a memory image suitable for
+/// [Machine::new](crate::Machine::new).
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    assemble_with_labels(source).map(|(code, _)| code)
+}
+
+/// Similar to [assemble], but also return the address of every label
+/// defined in `source`.
+pub fn assemble_with_labels(source: &str) -> Result<(Vec<u8>, HashMap<String, usize>), AsmError> {
+    let (labels, statements) = parse(source)?;
+    let size = statements.iter().map(|(_, _, s)| s.size()).sum();
+
+    // Second pass: encode everything now that labels are known
+    let mut code = Vec::with_capacity(size);
+    for (line, _, statement) in statements {
+        match statement {
+            Statement::Instruction(instruction) => code.extend(instruction.encode()),
+            Statement::LoadLabel(a, label) => {
+                let target = *labels
+                    .get(&label)
+                    .ok_or(AsmError::UnknownLabel { line, label })?;
+                let value = i16::try_from(target).map_err(|_| AsmError::ImmediateOutOfRange {
+                    line,
+                    value: target as i64,
+                })?;
+                code.extend(Instruction::LoadImm(a, value).encode());
+            }
+            Statement::BranchLabel(instruction, label) => {
+                let target = *labels
+                    .get(&label)
+                    .ok_or(AsmError::UnknownLabel { line, label })?;
+                let offset = target as i64 - (code.len() + instruction.size()) as i64;
+                let offset = i16::try_from(offset).map_err(|_| AsmError::ImmediateOutOfRange {
+                    line,
+                    value: offset,
+                })?;
+                code.extend(with_offset(instruction, offset).encode());
+            }
+            Statement::Data(bytes) => code.extend(bytes),
+        }
+    }
+    Ok((code, labels))
+}
+
+/// Address of every instruction of `source`, with the number of the line
+/// defining it. Lines holding data are left out.
+pub fn instruction_lines(source: &str) -> Result<Vec<(usize, usize)>, AsmError> {
+    let (_, statements) = parse(source)?;
+    Ok(statements
+        .into_iter()
+        .filter(|(_, _, statement)| !matches!(statement, Statement::Data(_)))
+        .map(|(line, address, _)| (line, address))
+        .collect())
+}
+
+/// Statements of `source` with their line and address, and labels.
+type Parsed = (HashMap<String, usize>, Vec<(usize, usize, Statement)>);
+
+/// First pass: parse every line and compute label addresses.
+fn parse(source: &str) -> Result<Parsed, AsmError> {
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = 0;
+    for (index, raw) in source.lines().enumerate() {
+        let line = index + 1;
+        let text = strip_comment(raw).trim();
+        if text.is_empty() {
+            continue;
+        }
+        if let Some(label) = text.strip_suffix(':') {
+            if !is_identifier(label) {
+                return Err(syntax(line, text));
+            }
+            if labels.insert(label.to_string(), address).is_some() {
+                return Err(AsmError::DuplicateLabel {
+                    line,
+                    label: label.to_string(),
+                });
+            }
+            continue;
+        }
+        let (given, rest) = split_address(line, text)?;
+        if let Some(found) = given {
+            if found != address {
+                return Err(AsmError::AddressMismatch {
+                    line,
+                    expected: address,
+                    found,
+                });
+            }
+        }
+        let statement = parse_statement(line, rest)?;
+        let size = statement.size();
+        statements.push((line, address, statement));
+        address += size;
+    }
+
+    Ok((labels, statements))
+}
+
+fn syntax(line: usize, text: &str) -> AsmError {
+    AsmError::Syntax {
+        line,
+        text: text.to_string(),
+    }
+}
+
+/// Remove a `;` comment, ignoring semicolons inside byte strings.
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == ';' => return &text[..i],
+            None => (),
+        }
+    }
+    text
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Split the optional address column (`0012` or `????`) from the rest of
+/// the line.
+fn split_address(line: usize, text: &str) -> Result<(Option<usize>, &str), AsmError> {
+    let (first, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    if first == "????" {
+        Ok((None, rest.trim_start()))
+    } else if first.chars().all(|c| c.is_ascii_digit()) {
+        let address = first.parse().map_err(|_| syntax(line, text))?;
+        Ok((Some(address), rest.trim_start()))
+    } else {
+        Ok((None, text))
+    }
+}
+
+fn parse_statement(line: usize, text: &str) -> Result<Statement, AsmError> {
+    if text.starts_with("b'") || text.starts_with("b\"") {
+        return parse_byte_string(line, text).map(Statement::Data);
+    }
+    if text.starts_with('[') {
+        return parse_byte_list(line, text).map(Statement::Data);
+    }
+    let reg = |name: &str| parse_register(line, name);
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    let instruction = match tokens.as_slice() {
+        ["move", a, "<-", b, "if", c, "!=", "0"] => Instruction::MoveIf(reg(a)?, reg(b)?, reg(c)?),
+        ["store", a, "<-", b] => Instruction::Store(reg(strip_brackets(line, a)?)?, reg(b)?),
+        ["load", a, "<-", b] => Instruction::Load(reg(a)?, reg(strip_brackets(line, b)?)?),
+        ["loadimm", a, "<-", imm] => {
+            let text = imm.strip_prefix('#').ok_or_else(|| syntax(line, imm))?;
+            if is_identifier(text) {
+                return Ok(Statement::LoadLabel(reg(a)?, text.to_string()));
+            }
+            Instruction::LoadImm(reg(a)?, parse_immediate(line, imm, text)?)
+        }
+        ["sub", a, "<-", b, "-", c] => Instruction::Sub(reg(a)?, reg(b)?, reg(c)?),
+        ["out", a] => Instruction::Out(reg(a)?),
+        ["exit"] => Instruction::Exit,
+        ["exit", a] => Instruction::ExitWith(reg(a)?),
+        ["out_number", a] => Instruction::OutNumber(reg(a)?),
+        ["in", a, b] => Instruction::In(reg(strip_comma(line, a)?)?, reg(b)?),
+        ["in_number", a, b] => Instruction::InNumber(reg(strip_comma(line, a)?)?, reg(b)?),
+        ["add", a, "<-", b, "+", c] => Instruction::Add(reg(a)?, reg(b)?, reg(c)?),
+        ["mul", a, "<-", b, "*", c] => Instruction::Mul(reg(a)?, reg(b)?, reg(c)?),
+        ["div", a, "<-", b, "/", c] => Instruction::Div(reg(a)?, reg(b)?, reg(c)?),
+        ["mod", a, "<-", b, "%", c] => Instruction::Mod(reg(a)?, reg(b)?, reg(c)?),
+        ["and", a, "<-", b, "&", c] => Instruction::And(reg(a)?, reg(b)?, reg(c)?),
+        ["or", a, "<-", b, "|", c] => Instruction::Or(reg(a)?, reg(b)?, reg(c)?),
+        ["xor", a, "<-", b, "^", c] => Instruction::Xor(reg(a)?, reg(b)?, reg(c)?),
+        ["shl", a, "<-", b, "<<", c] => Instruction::Shl(reg(a)?, reg(b)?, reg(c)?),
+        ["shr", a, "<-", b, ">>", c] => Instruction::Shr(reg(a)?, reg(b)?, reg(c)?),
+        ["sar", a, "<-", b, ">>", c] => Instruction::Sar(reg(a)?, reg(b)?, reg(c)?),
+        ["cmp", a, "<-", b, "<=>", c] => Instruction::Cmp(reg(a)?, reg(b)?, reg(c)?),
+        ["jmp", imm] => return parse_branch(line, Instruction::Jmp(0), imm),
+        ["jnz", a, imm] => {
+            let a = reg(strip_comma(line, a)?)?;
+            return parse_branch(line, Instruction::Jnz(a, 0), imm);
+        }
+        ["jz", a, imm] => {
+            let a = reg(strip_comma(line, a)?)?;
+            return parse_branch(line, Instruction::Jz(a, 0), imm);
+        }
+        ["loadb", a, "<-", b] => Instruction::LoadByte(reg(a)?, reg(strip_brackets(line, b)?)?),
+        ["storeb", a, "<-", b] => Instruction::StoreByte(reg(strip_brackets(line, a)?)?, reg(b)?),
+        ["out_char", a] => Instruction::OutChar(reg(a)?),
+        ["out_unsigned", a] => Instruction::OutUnsigned(reg(a)?),
+        ["out_hex", a] => Instruction::OutHex(reg(a)?),
+        ["out_binary", a] => Instruction::OutBinary(reg(a)?),
+        _ => return Err(syntax(line, text)),
+    };
+    Ok(Statement::Instruction(instruction))
+}
+
+/// Parse the `#offset` or `#label` operand of a relative branch, given
+/// with a zero offset.
+fn parse_branch(line: usize, branch: Instruction, imm: &str) -> Result<Statement, AsmError> {
+    let text = imm.strip_prefix('#').ok_or_else(|| syntax(line, imm))?;
+    if is_identifier(text) {
+        return Ok(Statement::BranchLabel(branch, text.to_string()));
+    }
+    let offset = parse_immediate(line, imm, text)?;
+    Ok(Statement::Instruction(with_offset(branch, offset)))
+}
+
+/// Replace the offset of a relative branch.
+fn with_offset(branch: Instruction, offset: i16) -> Instruction {
+    match branch {
+        Instruction::Jmp(_) => Instruction::Jmp(offset),
+        Instruction::Jnz(a, _) => Instruction::Jnz(a, offset),
+        Instruction::Jz(a, _) => Instruction::Jz(a, offset),
+        _ => unreachable!("not a relative branch"),
+    }
+}
+
+fn parse_register(line: usize, name: &str) -> Result<u8, AsmError> {
+    name.strip_prefix('r')
+        .filter(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+        .and_then(|n| n.parse().ok())
+        .ok_or_else(|| AsmError::InvalidRegister {
+            line,
+            register: name.to_string(),
+        })
+}
+
+fn strip_brackets(line: usize, operand: &str) -> Result<&str, AsmError> {
+    operand
+        .strip_prefix('[')
+        .and_then(|o| o.strip_suffix(']'))
+        .ok_or_else(|| syntax(line, operand))
+}
+
+fn strip_comma(line: usize, operand: &str) -> Result<&str, AsmError> {
+    operand
+        .strip_suffix(',')
+        .ok_or_else(|| syntax(line, operand))
+}
+
+fn parse_immediate(line: usize, operand: &str, text: &str) -> Result<i16, AsmError> {
+    let value = parse_number(text).ok_or_else(|| syntax(line, operand))?;
+    i16::try_from(value).map_err(|_| AsmError::ImmediateOutOfRange { line, value })
+}
+
+/// Parse a decimal or `0x`-prefixed hexadecimal number, possibly negative.
+pub fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = match digits.strip_prefix("0x") {
+        // `from_str_radix` would accept a sign after the prefix
+        Some(hex) if !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+            i64::from_str_radix(hex, 16).ok()?
+        }
+        Some(_) => return None,
+        None if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) => {
+            digits.parse().ok()?
+        }
+        None => return None,
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// Parse a byte string such as `b'Hello, world!\n'` or `b"I'm done!\n"`.
+fn parse_byte_string(line: usize, text: &str) -> Result<Vec<u8>, AsmError> {
+    let quote = text.as_bytes()[1];
+    let body = text[2..]
+        .strip_suffix(quote as char)
+        .ok_or_else(|| syntax(line, text))?;
+    let mut bytes = Vec::with_capacity(body.len());
+    let mut chars = body.bytes();
+    while let Some(b) = chars.next() {
+        if b == quote {
+            return Err(syntax(line, text));
+        }
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        let escaped = match chars.next().ok_or_else(|| syntax(line, text))? {
+            b'n' => b'\n',
+            b't' => b'\t',
+            b'r' => b'\r',
+            b'0' => 0,
+            b'\\' => b'\\',
+            b'\'' => b'\'',
+            b'"' => b'"',
+            b'x' => {
+                let hex = [chars.next(), chars.next()];
+                let hex: Vec<u8> = hex.iter().flatten().copied().collect();
+                std::str::from_utf8(&hex)
+                    .ok()
+                    .filter(|h| h.len() == 2)
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                    .ok_or_else(|| syntax(line, text))?
+            }
+            _ => return Err(syntax(line, text)),
+        };
+        bytes.push(escaped);
+    }
+    Ok(bytes)
+}
+
+/// Parse a list of bytes such as `[0, 0, 0, 0]`.
+fn parse_byte_list(line: usize, text: &str) -> Result<Vec<u8>, AsmError> {
+    let body = text
+        .strip_prefix('[')
+        .and_then(|t| t.strip_suffix(']'))
+        .ok_or_else(|| syntax(line, text))?;
+    if body.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    body.split(',')
+        .map(|item| {
+            parse_number(item.trim())
+                .and_then(|v| u8::try_from(v).ok())
+                .ok_or_else(|| syntax(line, text))
+        })
+        .collect()
+}
@@ -0,0 +1,134 @@
+use crate::{
+    Isa, Machine, MachineError, MachineErrorKind, OutputMode, Permissions, Region, MEMORY_SIZE,
+    NREGS,
+};
+
+/// Builder for machines whose geometry differs from the one given by
+/// [Machine::new].
+#[derive(Debug, Clone)]
+pub struct MachineBuilder {
+    memory_size: usize,
+    nregs: usize,
+    initial_registers: Vec<(usize, u32)>,
+    load_address: usize,
+    entry_point: Option<u32>,
+    program: Vec<u8>,
+    isa: Isa,
+    output_mode: OutputMode,
+    regions: Vec<Region>,
+}
+
+impl Default for MachineBuilder {
+    fn default() -> Self {
+        MachineBuilder {
+            memory_size: MEMORY_SIZE,
+            nregs: NREGS,
+            initial_registers: Vec::new(),
+            load_address: 0,
+            entry_point: None,
+            program: Vec::new(),
+            isa: Isa::Base,
+            output_mode: OutputMode::Latin1,
+            regions: Vec::new(),
+        }
+    }
+}
+
+impl MachineBuilder {
+    /// Start from the default configuration of [Machine::new]: [MEMORY_SIZE]
+    /// bytes of memory, [NREGS] registers, program loaded and started at 0.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Size of the memory in bytes. It cannot exceed the 32-bit address space.
+    pub fn memory_size(mut self, size: usize) -> Self {
+        self.memory_size = size;
+        self
+    }
+
+    /// Number of registers, including IP (register 0).
+    pub fn registers(mut self, count: usize) -> Self {
+        self.nregs = count;
+        self
+    }
+
+    /// Initial value of a register. IP is always set to the
+    /// [entry point](MachineBuilder::entry_point) instead.
+    pub fn register(mut self, reg: usize, value: u32) -> Self {
+        self.initial_registers.push((reg, value));
+        self
+    }
+
+    /// Address at which the program is copied.
+    pub fn load_address(mut self, address: usize) -> Self {
+        self.load_address = address;
+        self
+    }
+
+    /// Initial value of IP. Defaults to the load address.
+    pub fn entry_point(mut self, address: u32) -> Self {
+        self.entry_point = Some(address);
+        self
+    }
+
+    /// Content copied into memory at the load address.
+    pub fn program(mut self, program: &[u8]) -> Self {
+        self.program = program.to_vec();
+        self
+    }
+
+    /// Instruction set understood by the machine.
+    pub fn isa(mut self, isa: Isa) -> Self {
+        self.isa = isa;
+        self
+    }
+
+    /// Encoding of the characters written by `out`, see
+    /// [Machine::set_output_mode].
+    pub fn output_mode(mut self, mode: OutputMode) -> Self {
+        self.output_mode = mode;
+        self
+    }
+
+    /// Protect the addresses from `start` included to `end` excluded, see
+    /// [Machine::protect]. The program is loaded regardless of the
+    /// protection.
+    pub fn protect(mut self, start: usize, end: usize, permissions: Permissions) -> Self {
+        self.regions.push(Region {
+            start,
+            end,
+            permissions,
+        });
+        self
+    }
+
+    /// Create the machine.
+    ///
+    /// Fails with [RegisterOutOfBounds](MachineErrorKind::RegisterOutOfBounds)
+    /// if there is no register or if an initial value is given for a
+    /// register which does not exist, and with
+    /// [MemoryIndexOutOfBounds](MachineErrorKind::MemoryIndexOutOfBounds)
+    /// if the memory is too large or the program does not fit in it.
+    pub fn build(self) -> Result<Machine, MachineError> {
+        if self.nregs == 0 {
+            return Err(MachineErrorKind::RegisterOutOfBounds(0).into());
+        }
+        if self.memory_size > u32::MAX as usize + 1 {
+            return Err(MachineErrorKind::MemoryIndexOutOfBounds(self.memory_size - 1).into());
+        }
+        let mut machine = Machine::with_geometry(self.memory_size, self.nregs);
+        machine.set_isa(self.isa);
+        machine.set_output_mode(self.output_mode);
+        machine.set_mem(self.load_address, &self.program)?;
+        for (reg, value) in self.initial_registers {
+            machine.set_reg(reg, value)?;
+        }
+        let entry_point = self.entry_point.unwrap_or(self.load_address as u32);
+        machine.set_reg(0, entry_point)?;
+        for region in self.regions {
+            machine.protect(region.start, region.end, region.permissions);
+        }
+        Ok(machine)
+    }
+}
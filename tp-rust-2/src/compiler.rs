@@ -0,0 +1,969 @@
+//! Compiler for a tiny imperative language targeting the machine.
+//!
+//! ```text
+//! // Factorials from 1 to 5
+//! fn fact(n) {
+//!     if n <= 1 {
+//!         return 1;
+//!     }
+//!     return n * fact(n - 1);
+//! }
+//!
+//! fn main() {
+//!     var i = 1;
+//!     while i <= 5 {
+//!         print i, "! = ", fact(i);
+//!         i = i + 1;
+//!     }
+//! }
+//! ```
+//!
+//! A program is a list of functions, and runs by calling `main`, which has
+//! no parameter and whose result is the exit status of the program.
+//! Values are 32-bit signed integers with wrapping arithmetic. The
+//! statements are:
+//!   - `var x = expr;`, declaring a variable local to the enclosing block,
+//!   - `x = expr;`,
+//!   - `if expr { ... }`, optionally followed by `else { ... }` or
+//!     `else if ...`, and `while expr { ... }`, where a condition holds when
+//!     it is not zero,
+//!   - `return expr;`, or `return;` returning 0 as falling off the end of
+//!     a function does,
+//!   - `print item, ...;`, writing numbers and string literals, in UTF-8,
+//!     followed by a newline,
+//!   - `expr;`, typically a call.
+//!
+//! Operators by increasing precedence are `||` and `&&` (short-circuiting),
+//! the comparisons `==`, `!=`, `<`, `<=`, `>` and `>=`, then `+` and `-`,
+//! then `*`, `/` and `%`, then the unary `-` and `!`. Comparisons and
+//! logical operators yield 0 or 1. Comments start with `//`.
+//!
+//! The program is compiled to a listing in the syntax of the `.dis` files,
+//! then assembled. The code needs the [extended](crate::Isa::Extended)
+//! instruction set, as told by [ISA]: it starts with an extended
+//! instruction, so that a machine without it fails at once instead of
+//! after some output. The code follows the calling convention of the
+//! existing programs: r2 is the stack pointer, starting at the end of the
+//! memory, a call pushes the return address and jumps with
+//! `loadimm r0 <- #function`, and the function returns by popping it into
+//! r0. The arguments are pushed in order before the return address and
+//! removed by the caller, and the result is returned in r11. r1 is the
+//! frame pointer, r3 a scratch register and r4 to r10 hold intermediate
+//! values, which are saved on the stack across calls.
+//!
+//! Functions keep their name as label, so that the debugger and the
+//! profiler show them. The labels made up by the compiler start with an
+//! underscore, which identifiers of the language cannot.
+
+use crate::assembler::{self, AsmError};
+use crate::{Isa, MEMORY_SIZE};
+use std::collections::HashMap;
+use std::{fmt, mem};
+
+/// Instruction set needed by the compiled programs, to be given to the
+/// machine running them with [Machine::set_isa](crate::Machine::set_isa).
+pub const ISA: Isa = Isa::Extended;
+
+/// Frame pointer.
+const FP: usize = 1;
+/// Register holding the result of a call.
+const RESULT: usize = 11;
+/// Registers holding intermediate values, r4 to r10.
+const FIRST_TEMP: usize = 4;
+const TEMPS: usize = 7;
+
+const KEYWORDS: [&str; 7] = ["fn", "var", "if", "else", "while", "return", "print"];
+
+/// Symbols, those starting with another one first.
+const SYMBOLS: [&str; 21] = [
+    "==", "!=", "<=", ">=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "=", "!", "(", ")", "{",
+    "}", ",", ";",
+];
+
+/// Binary operators by increasing precedence.
+const LEVELS: [&[&str]; 5] = [
+    &["||"],
+    &["&&"],
+    &["==", "!=", "<", "<=", ">", ">="],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// Errors detected while compiling a program. Line numbers start at 1.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CompileError {
+    /// A character which cannot start any token.
+    UnexpectedCharacter {
+        line: usize,
+        character: char,
+    },
+    /// A token was found where something else was expected.
+    UnexpectedToken {
+        line: usize,
+        found: String,
+        expected: String,
+    },
+    /// A string literal is not closed on its line.
+    UnterminatedString {
+        line: usize,
+    },
+    /// A number is malformed or does not fit in 32 bits.
+    InvalidNumber {
+        line: usize,
+        text: String,
+    },
+    UnknownVariable {
+        line: usize,
+        name: String,
+    },
+    /// A variable is declared twice in the same block, or a function has
+    /// two parameters with the same name.
+    DuplicateVariable {
+        line: usize,
+        name: String,
+    },
+    UnknownFunction {
+        line: usize,
+        name: String,
+    },
+    DuplicateFunction {
+        line: usize,
+        name: String,
+    },
+    /// A function is called with a wrong number of arguments.
+    ArgumentCount {
+        line: usize,
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    /// There is no `main` function without parameters.
+    MissingMain,
+    /// An expression needs more intermediate values than registers.
+    ExpressionTooComplex {
+        line: usize,
+    },
+    /// The generated listing could not be assembled, e.g. because the
+    /// program is too large for its addresses to fit in 16 bits.
+    Assembly(AsmError),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::UnexpectedCharacter { line, character } => {
+                write!(f, "line {}: unexpected character `{}`", line, character)
+            }
+            CompileError::UnexpectedToken {
+                line,
+                found,
+                expected,
+            } => write!(f, "line {}: expected {}, found {}", line, expected, found),
+            CompileError::UnterminatedString { line } => {
+                write!(f, "line {}: unterminated string", line)
+            }
+            CompileError::InvalidNumber { line, text } => {
+                write!(f, "line {}: invalid number `{}`", line, text)
+            }
+            CompileError::UnknownVariable { line, name } => {
+                write!(f, "line {}: unknown variable `{}`", line, name)
+            }
+            CompileError::DuplicateVariable { line, name } => {
+                write!(f, "line {}: variable `{}` is declared twice", line, name)
+            }
+            CompileError::UnknownFunction { line, name } => {
+                write!(f, "line {}: unknown function `{}`", line, name)
+            }
+            CompileError::DuplicateFunction { line, name } => {
+                write!(f, "line {}: function `{}` is defined twice", line, name)
+            }
+            CompileError::ArgumentCount {
+                line,
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: function `{}` takes {} arguments but {} were given",
+                line, name, expected, found
+            ),
+            CompileError::MissingMain => write!(f, "no `main` function without parameters"),
+            CompileError::ExpressionTooComplex { line } => {
+                write!(f, "line {}: expression too complex", line)
+            }
+            CompileError::Assembly(e) => write!(f, "generated code: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+/// Compile `source` into a binary for a machine using the instruction set
+/// [ISA].
+pub fn compile(source: &str) -> Result<Vec<u8>, CompileError> {
+    let listing = compile_to_listing(source)?;
+    assembler::assemble(&listing).map_err(CompileError::Assembly)
+}
+
+/// Compile `source` into a listing in the syntax of the `.dis` files,
+/// without addresses.
+pub fn compile_to_listing(source: &str) -> Result<String, CompileError> {
+    let functions = Parser::new(tokenize(source)?).program()?;
+    Generator::default().program(&functions)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(u32),
+    Name(String),
+    Text(String),
+    Symbol(&'static str),
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "`{}`", value),
+            Token::Name(name) => write!(f, "`{}`", name),
+            Token::Text(_) => write!(f, "string"),
+            Token::Symbol(symbol) => write!(f, "`{}`", symbol),
+            Token::End => write!(f, "end of input"),
+        }
+    }
+}
+
+/// Split `source` into tokens with their line, ending with [Token::End].
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, CompileError> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut chars = source.char_indices().peekable();
+    // Index following the longest run of characters satisfying `f`
+    let skip = |chars: &mut std::iter::Peekable<std::str::CharIndices>, f: fn(char) -> bool| {
+        while chars.next_if(|&(_, c)| f(c)).is_some() {}
+        chars.peek().map_or(source.len(), |&(i, _)| i)
+    };
+    while let Some(&(start, c)) = chars.peek() {
+        let rest = &source[start..];
+        let token = if c == '\n' {
+            line += 1;
+            chars.next();
+            continue;
+        } else if c.is_whitespace() {
+            chars.next();
+            continue;
+        } else if rest.starts_with("//") {
+            skip(&mut chars, |c| c != '\n');
+            continue;
+        } else if c.is_ascii_digit() {
+            let text = &source[start..skip(&mut chars, |c| c.is_ascii_alphanumeric())];
+            match text.parse() {
+                Ok(value) => Token::Number(value),
+                Err(_) => {
+                    return Err(CompileError::InvalidNumber {
+                        line,
+                        text: text.to_string(),
+                    })
+                }
+            }
+        } else if c.is_ascii_alphabetic() {
+            let end = skip(&mut chars, |c| c.is_ascii_alphanumeric() || c == '_');
+            Token::Name(source[start..end].to_string())
+        } else if c == '"' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next().map(|(_, c)| c) {
+                    Some('"') => break,
+                    Some('\\') => match chars.next().map(|(_, c)| c) {
+                        Some('n') => text.push('\n'),
+                        Some('t') => text.push('\t'),
+                        Some(c @ ('\\' | '"')) => text.push(c),
+                        Some('\n') | None => return Err(CompileError::UnterminatedString { line }),
+                        Some(c) => {
+                            return Err(CompileError::UnexpectedCharacter { line, character: c })
+                        }
+                    },
+                    Some('\n') | None => return Err(CompileError::UnterminatedString { line }),
+                    Some(c) => text.push(c),
+                }
+            }
+            Token::Text(text)
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            for _ in 0..symbol.len() {
+                chars.next();
+            }
+            Token::Symbol(symbol)
+        } else {
+            return Err(CompileError::UnexpectedCharacter { line, character: c });
+        };
+        tokens.push((line, token));
+    }
+    tokens.push((line, Token::End));
+    Ok(tokens)
+}
+
+struct Function {
+    line: usize,
+    name: String,
+    params: Vec<String>,
+    body: Vec<(usize, Statement)>,
+}
+
+enum Statement {
+    Var(String, Expr),
+    Assign(String, Expr),
+    If(Expr, Vec<(usize, Statement)>, Vec<(usize, Statement)>),
+    While(Expr, Vec<(usize, Statement)>),
+    Return(Option<Expr>),
+    Print(Vec<Item>),
+    Expr(Expr),
+}
+
+enum Item {
+    Expr(Expr),
+    Text(String),
+}
+
+enum Expr {
+    Number(u32),
+    Variable(String),
+    Call(String, Vec<Expr>),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+/// Recursive descent parser, each statement being kept with its line.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+}
+
+impl Parser {
+    fn new(tokens: Vec<(usize, Token)>) -> Self {
+        Parser {
+            tokens,
+            position: 0,
+        }
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position].1
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.position].0
+    }
+
+    /// Skip the next token if it is the given symbol.
+    fn eat(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Token::Symbol(s) if *s == symbol);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    /// Skip the next token if it is the given keyword.
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.peek(), Token::Name(name) if name == keyword);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), CompileError> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("`{}`", symbol)))
+        }
+    }
+
+    /// Next token, which must be a name other than a keyword.
+    fn name(&mut self) -> Result<String, CompileError> {
+        match self.peek() {
+            Token::Name(name) if !KEYWORDS.contains(&name.as_str()) => {
+                let name = name.clone();
+                self.position += 1;
+                Ok(name)
+            }
+            _ => Err(self.unexpected("name")),
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> CompileError {
+        CompileError::UnexpectedToken {
+            line: self.line(),
+            found: self.peek().to_string(),
+            expected: expected.to_string(),
+        }
+    }
+
+    fn program(&mut self) -> Result<Vec<Function>, CompileError> {
+        let mut functions = Vec::new();
+        while *self.peek() != Token::End {
+            let line = self.line();
+            if !self.eat_keyword("fn") {
+                return Err(self.unexpected("`fn`"));
+            }
+            let name = self.name()?;
+            self.expect("(")?;
+            let mut params = Vec::new();
+            if !self.eat(")") {
+                loop {
+                    params.push(self.name()?);
+                    if self.eat(")") {
+                        break;
+                    }
+                    self.expect(",")?;
+                }
+            }
+            let body = self.block()?;
+            functions.push(Function {
+                line,
+                name,
+                params,
+                body,
+            });
+        }
+        Ok(functions)
+    }
+
+    fn block(&mut self) -> Result<Vec<(usize, Statement)>, CompileError> {
+        self.expect("{")?;
+        let mut statements = Vec::new();
+        while !self.eat("}") {
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> Result<(usize, Statement), CompileError> {
+        let line = self.line();
+        let statement = if self.eat_keyword("var") {
+            let name = self.name()?;
+            self.expect("=")?;
+            Statement::Var(name, self.expression()?)
+        } else if self.eat_keyword("if") {
+            return Ok((line, self.if_statement()?));
+        } else if self.eat_keyword("while") {
+            let condition = self.expression()?;
+            return Ok((line, Statement::While(condition, self.block()?)));
+        } else if self.eat_keyword("return") {
+            match self.peek() {
+                Token::Symbol(";") => Statement::Return(None),
+                _ => Statement::Return(Some(self.expression()?)),
+            }
+        } else if self.eat_keyword("print") {
+            let mut items = Vec::new();
+            loop {
+                items.push(match self.peek().clone() {
+                    Token::Text(text) => {
+                        self.position += 1;
+                        Item::Text(text)
+                    }
+                    _ => Item::Expr(self.expression()?),
+                });
+                if !self.eat(",") {
+                    break;
+                }
+            }
+            Statement::Print(items)
+        } else if matches!(
+            self.tokens.get(self.position + 1),
+            Some((_, Token::Symbol("=")))
+        ) {
+            let name = self.name()?;
+            self.position += 1;
+            Statement::Assign(name, self.expression()?)
+        } else {
+            Statement::Expr(self.expression()?)
+        };
+        self.expect(";")?;
+        Ok((line, statement))
+    }
+
+    /// Rest of an `if` statement, following the keyword.
+    fn if_statement(&mut self) -> Result<Statement, CompileError> {
+        let condition = self.expression()?;
+        let then = self.block()?;
+        let otherwise = if !self.eat_keyword("else") {
+            Vec::new()
+        } else if matches!(self.peek(), Token::Name(name) if name == "if") {
+            let line = self.line();
+            self.position += 1;
+            vec![(line, self.if_statement()?)]
+        } else {
+            self.block()?
+        };
+        Ok(Statement::If(condition, then, otherwise))
+    }
+
+    fn expression(&mut self) -> Result<Expr, CompileError> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, CompileError> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(&op) = LEVELS[level]
+            .iter()
+            .find(|&&op| matches!(self.peek(), Token::Symbol(s) if *s == op))
+        {
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        for op in ["-", "!"] {
+            if self.eat(op) {
+                return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, CompileError> {
+        match self.peek() {
+            Token::Number(value) => {
+                let value = *value;
+                self.position += 1;
+                Ok(Expr::Number(value))
+            }
+            Token::Symbol("(") => {
+                self.position += 1;
+                let expr = self.expression()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Token::Name(_) => {
+                let name = self.name()?;
+                if !self.eat("(") {
+                    return Ok(Expr::Variable(name));
+                }
+                let mut args = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        args.push(self.expression()?);
+                        if self.eat(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Ok(Expr::Call(name, args))
+            }
+            _ => Err(self.unexpected("expression")),
+        }
+    }
+}
+
+/// Code generator, writing the listing line by line.
+#[derive(Default)]
+struct Generator {
+    /// Number of parameters of every function.
+    functions: HashMap<String, usize>,
+    lines: Vec<String>,
+    labels: usize,
+    /// Line of the statement being compiled, for errors.
+    line: usize,
+    /// Function being compiled, its variables by block with their offset
+    /// from the frame pointer, and its number of local slots in use and
+    /// needed at most.
+    function: String,
+    scopes: Vec<HashMap<String, i32>>,
+    locals: usize,
+    frame: usize,
+}
+
+impl Generator {
+    fn program(mut self, functions: &[Function]) -> Result<String, CompileError> {
+        for function in functions {
+            let count = function.params.len();
+            if self
+                .functions
+                .insert(function.name.clone(), count)
+                .is_some()
+            {
+                return Err(CompileError::DuplicateFunction {
+                    line: function.line,
+                    name: function.name.clone(),
+                });
+            }
+        }
+        if self.functions.get("main") != Some(&0) {
+            return Err(CompileError::MissingMain);
+        }
+
+        // Fail on the first instruction without the extended instruction
+        // set
+        self.emit("xor r3 <- r3 ^ r3".to_string());
+        self.load_constant(2, MEMORY_SIZE as u32);
+        self.call("main", &[], 0)?;
+        self.emit(format!("exit r{}", RESULT));
+        for function in functions {
+            self.function(function)?;
+        }
+        let mut listing = self.lines.join("\n");
+        listing.push('\n');
+        Ok(listing)
+    }
+
+    fn emit(&mut self, instruction: String) {
+        self.lines.push(format!("    {}", instruction));
+    }
+
+    fn label(&mut self, label: &str) {
+        self.lines.push(format!("{}:", label));
+    }
+
+    fn new_label(&mut self, kind: &str) -> String {
+        self.labels += 1;
+        format!("_{}_{}", kind, self.labels)
+    }
+
+    fn function(&mut self, function: &Function) -> Result<(), CompileError> {
+        self.line = function.line;
+        self.function = function.name.clone();
+        let count = function.params.len();
+        let mut params = HashMap::new();
+        for (i, param) in function.params.iter().enumerate() {
+            // Above the saved frame pointer and the return address, the
+            // last argument being pushed last
+            let offset = 8 + 4 * (count - 1 - i) as i32;
+            if params.insert(param.clone(), offset).is_some() {
+                return Err(CompileError::DuplicateVariable {
+                    line: function.line,
+                    name: param.clone(),
+                });
+            }
+        }
+        self.scopes = vec![params];
+        self.locals = 0;
+        self.frame = 0;
+
+        self.label(&function.name);
+        self.push(FP);
+        self.emit(format!("move r{} <- r2 if r0 != 0", FP));
+        // The frame size is only known once the body is compiled
+        let head = mem::take(&mut self.lines);
+        self.block(&function.body)?;
+        let body = mem::replace(&mut self.lines, head);
+        if self.frame > 0 {
+            // No intermediate value is live when entering the function
+            self.load_constant(FIRST_TEMP, 4 * self.frame as u32);
+            self.emit(format!("sub r2 <- r2 - r{}", FIRST_TEMP));
+        }
+        self.lines.extend(body);
+
+        self.emit(format!("loadimm r{} <- #0", RESULT));
+        self.label(&format!("_{}_end", function.name));
+        self.emit(format!("move r2 <- r{} if r0 != 0", FP));
+        self.pop(FP);
+        self.emit("loadimm r3 <- #-4".to_string());
+        self.emit("sub r2 <- r2 - r3".to_string());
+        self.emit("loadimm r3 <- #4".to_string());
+        self.emit("sub r3 <- r2 - r3".to_string());
+        self.emit("load r0 <- [r3]".to_string());
+        Ok(())
+    }
+
+    fn block(&mut self, statements: &[(usize, Statement)]) -> Result<(), CompileError> {
+        let locals = self.locals;
+        self.scopes.push(HashMap::new());
+        for (line, statement) in statements {
+            self.line = *line;
+            self.statement(statement)?;
+        }
+        self.scopes.pop();
+        self.locals = locals;
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), CompileError> {
+        match statement {
+            Statement::Var(name, value) => {
+                // The value may refer to a variable of an enclosing block
+                // with the same name
+                self.expr(value, 0)?;
+                if self.scopes.last().unwrap().contains_key(name) {
+                    return Err(CompileError::DuplicateVariable {
+                        line: self.line,
+                        name: name.clone(),
+                    });
+                }
+                self.locals += 1;
+                self.frame = self.frame.max(self.locals);
+                let offset = -4 * self.locals as i32;
+                self.scopes.last_mut().unwrap().insert(name.clone(), offset);
+                self.address_of(FIRST_TEMP + 1, offset);
+                self.emit(format!("store [r{}] <- r{}", FIRST_TEMP + 1, FIRST_TEMP));
+            }
+            Statement::Assign(name, value) => {
+                let offset = self.variable(name)?;
+                self.expr(value, 0)?;
+                self.address_of(FIRST_TEMP + 1, offset);
+                self.emit(format!("store [r{}] <- r{}", FIRST_TEMP + 1, FIRST_TEMP));
+            }
+            Statement::If(condition, then, otherwise) => {
+                let else_label = self.new_label("else");
+                self.expr(condition, 0)?;
+                self.emit(format!("jz r{}, #{}", FIRST_TEMP, else_label));
+                self.block(then)?;
+                if otherwise.is_empty() {
+                    self.label(&else_label);
+                } else {
+                    let end = self.new_label("end_if");
+                    self.emit(format!("jmp #{}", end));
+                    self.label(&else_label);
+                    self.block(otherwise)?;
+                    self.label(&end);
+                }
+            }
+            Statement::While(condition, body) => {
+                let start = self.new_label("while");
+                let end = self.new_label("end_while");
+                self.label(&start);
+                self.expr(condition, 0)?;
+                self.emit(format!("jz r{}, #{}", FIRST_TEMP, end));
+                self.block(body)?;
+                self.emit(format!("jmp #{}", start));
+                self.label(&end);
+            }
+            Statement::Return(value) => {
+                match value {
+                    Some(value) => {
+                        self.expr(value, 0)?;
+                        self.emit(format!("move r{} <- r{} if r0 != 0", RESULT, FIRST_TEMP));
+                    }
+                    None => self.emit(format!("loadimm r{} <- #0", RESULT)),
+                }
+                self.emit(format!("jmp #_{}_end", self.function));
+            }
+            Statement::Print(items) => {
+                for item in items {
+                    match item {
+                        Item::Expr(value) => {
+                            self.expr(value, 0)?;
+                            self.emit(format!("out_number r{}", FIRST_TEMP));
+                        }
+                        Item::Text(text) => {
+                            // Only ASCII is written identically by `out` in
+                            // every output mode
+                            for c in text.chars() {
+                                self.load_constant(FIRST_TEMP, c as u32);
+                                if c.is_ascii() {
+                                    self.emit(format!("out r{}", FIRST_TEMP));
+                                } else {
+                                    self.emit(format!("out_char r{}", FIRST_TEMP));
+                                }
+                            }
+                        }
+                    }
+                }
+                self.emit("loadimm r3 <- #10".to_string());
+                self.emit("out r3".to_string());
+            }
+            Statement::Expr(value) => self.expr(value, 0)?,
+        }
+        Ok(())
+    }
+
+    /// Offset of a variable from the frame pointer.
+    fn variable(&self, name: &str) -> Result<i32, CompileError> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+            .ok_or_else(|| CompileError::UnknownVariable {
+                line: self.line,
+                name: name.to_string(),
+            })
+    }
+
+    /// Put into `reg` the address at `offset` from the frame pointer.
+    fn address_of(&mut self, reg: usize, offset: i32) {
+        self.load_constant(reg, offset as u32);
+        self.emit(format!("add r{} <- r{} + r{}", reg, FP, reg));
+    }
+
+    /// Load any 32-bit value, in two halves if it does not fit in the 16
+    /// bits of `loadimm`. Uses r3 in that case, so `reg` cannot be r3.
+    fn load_constant(&mut self, reg: usize, value: u32) {
+        debug_assert_ne!(reg, 3, "r3 is the scratch register");
+        let value = value as i32;
+        if let Ok(imm) = i16::try_from(value) {
+            self.emit(format!("loadimm r{} <- #{}", reg, imm));
+            return;
+        }
+        let low = value as i16;
+        let high = (value.wrapping_sub(low as i32) >> 16) as i16;
+        self.emit(format!("loadimm r{} <- #{}", reg, high));
+        self.emit("loadimm r3 <- #16".to_string());
+        self.emit(format!("shl r{} <- r{} << r3", reg, reg));
+        self.emit(format!("loadimm r3 <- #{}", low));
+        self.emit(format!("add r{} <- r{} + r3", reg, reg));
+    }
+
+    fn push(&mut self, reg: usize) {
+        self.emit("loadimm r3 <- #4".to_string());
+        self.emit("sub r2 <- r2 - r3".to_string());
+        self.emit(format!("store [r2] <- r{}", reg));
+    }
+
+    fn pop(&mut self, reg: usize) {
+        self.emit(format!("load r{} <- [r2]", reg));
+        self.emit("loadimm r3 <- #-4".to_string());
+        self.emit("sub r2 <- r2 - r3".to_string());
+    }
+
+    /// Compute `expr` into the intermediate register of the given depth,
+    /// the registers of lower depths holding values still needed.
+    fn expr(&mut self, expr: &Expr, depth: usize) -> Result<(), CompileError> {
+        if depth >= TEMPS {
+            return Err(CompileError::ExpressionTooComplex { line: self.line });
+        }
+        let r = FIRST_TEMP + depth;
+        match expr {
+            Expr::Number(value) => self.load_constant(r, *value),
+            Expr::Variable(name) => {
+                let offset = self.variable(name)?;
+                self.address_of(r, offset);
+                self.emit(format!("load r{} <- [r{}]", r, r));
+            }
+            Expr::Call(name, args) => self.call(name, args, depth)?,
+            Expr::Unary(op, operand) => {
+                self.expr(operand, depth)?;
+                self.emit("loadimm r3 <- #0".to_string());
+                match *op {
+                    "-" => self.emit(format!("sub r{} <- r3 - r{}", r, r)),
+                    _ => {
+                        self.emit(format!("cmp r{} <- r{} <=> r3", r, r));
+                        self.not_zero(r);
+                        self.emit(format!("xor r{} <- r{} ^ r3", r, r));
+                    }
+                }
+            }
+            Expr::Binary(op @ ("&&" | "||"), left, right) => {
+                // 0 or 1 depending on the right operand if the left one
+                // does not decide
+                let end = self.new_label("end_logic");
+                self.expr(left, depth)?;
+                if *op == "&&" {
+                    self.emit(format!("jz r{}, #{}", r, end));
+                } else {
+                    let right_label = self.new_label("or_right");
+                    self.emit(format!("jz r{}, #{}", r, right_label));
+                    self.emit(format!("loadimm r{} <- #1", r));
+                    self.emit(format!("jmp #{}", end));
+                    self.label(&right_label);
+                }
+                self.expr(right, depth)?;
+                self.emit("loadimm r3 <- #0".to_string());
+                self.emit(format!("cmp r{} <- r{} <=> r3", r, r));
+                self.not_zero(r);
+                self.label(&end);
+            }
+            Expr::Binary(op, left, right) => {
+                self.expr(left, depth)?;
+                self.expr(right, depth + 1)?;
+                let s = r + 1;
+                match *op {
+                    "+" => self.emit(format!("add r{} <- r{} + r{}", r, r, s)),
+                    "-" => self.emit(format!("sub r{} <- r{} - r{}", r, r, s)),
+                    "*" => self.emit(format!("mul r{} <- r{} * r{}", r, r, s)),
+                    "/" => self.emit(format!("div r{} <- r{} / r{}", r, r, s)),
+                    "%" => self.emit(format!("mod r{} <- r{} % r{}", r, r, s)),
+                    "==" | "!=" => {
+                        self.emit(format!("cmp r{} <- r{} <=> r{}", r, r, s));
+                        self.not_zero(r);
+                        if *op == "==" {
+                            self.emit(format!("xor r{} <- r{} ^ r3", r, r));
+                        }
+                    }
+                    _ => {
+                        // a > b and a <= b are computed from b < a
+                        let (a, b) = match *op {
+                            "<" | ">=" => (r, s),
+                            _ => (s, r),
+                        };
+                        self.emit(format!("cmp r{} <- r{} <=> r{}", r, a, b));
+                        self.emit("loadimm r3 <- #31".to_string());
+                        self.emit(format!("shr r{} <- r{} >> r3", r, r));
+                        if matches!(*op, ">=" | "<=") {
+                            self.emit("loadimm r3 <- #1".to_string());
+                            self.emit(format!("xor r{} <- r{} ^ r3", r, r));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Turn the result of `cmp` in `reg` into 1 if it is not 0, leaving 1
+    /// in r3.
+    fn not_zero(&mut self, reg: usize) {
+        self.emit("loadimm r3 <- #1".to_string());
+        self.emit(format!("and r{} <- r{} & r3", reg, reg));
+    }
+
+    /// Call `name`, its result going into the intermediate register of the
+    /// given depth.
+    fn call(&mut self, name: &str, args: &[Expr], depth: usize) -> Result<(), CompileError> {
+        match self.functions.get(name) {
+            None => {
+                return Err(CompileError::UnknownFunction {
+                    line: self.line,
+                    name: name.to_string(),
+                })
+            }
+            Some(&expected) if expected != args.len() => {
+                return Err(CompileError::ArgumentCount {
+                    line: self.line,
+                    name: name.to_string(),
+                    expected,
+                    found: args.len(),
+                })
+            }
+            Some(_) => (),
+        }
+        let live = FIRST_TEMP..FIRST_TEMP + depth;
+        for reg in live.clone() {
+            self.push(reg);
+        }
+        for arg in args {
+            self.expr(arg, depth)?;
+            self.push(FIRST_TEMP + depth);
+        }
+        let back = self.new_label(&format!("return_from_{}", name));
+        self.emit("loadimm r3 <- #4".to_string());
+        self.emit("sub r2 <- r2 - r3".to_string());
+        self.emit(format!("loadimm r3 <- #{}", back));
+        self.emit("store [r2] <- r3".to_string());
+        self.emit(format!("loadimm r0 <- #{}", name));
+        self.label(&back);
+        // The register receiving the result is free until then
+        if !args.is_empty() {
+            let r = FIRST_TEMP + depth;
+            self.load_constant(r, (-4 * args.len() as i32) as u32);
+            self.emit(format!("sub r2 <- r2 - r{}", r));
+        }
+        self.emit(format!(
+            "move r{} <- r{} if r0 != 0",
+            FIRST_TEMP + depth,
+            RESULT
+        ));
+        for reg in live.rev() {
+            self.pop(reg);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+//! Code coverage, recording which instructions have been executed.
+//!
+//! Coverage is collected by the machine itself once enabled with
+//! [Machine::set_coverage], whatever the way it is run. The hits of
+//! several runs, for example of a program tested with various inputs, can
+//! be [merged](Coverage::merge) before annotating the listing of the
+//! program:
+//!
+//! ```text
+//!        1 |   0019   loadimm r0 <- #fact
+//!          | return_from_fact_1:
+//!        1 |   0023   exit
+//!          | mult:
+//!    ##### |   0024   sub r13 <- r1 - r11
+//! ```
+//!
+//! Instructions never executed are marked with `#####`.
+
+use crate::{
+    assembler::{self, AsmError},
+    Machine,
+};
+use std::fmt;
+
+/// Number of executions of the instruction found at each address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    hits: Vec<u64>,
+}
+
+impl Coverage {
+    /// Coverage of a memory of `size` bytes where nothing was executed.
+    pub fn new(size: usize) -> Self {
+        Coverage {
+            hits: vec![0; size],
+        }
+    }
+
+    /// Number of executions of the instruction at `address`.
+    pub fn hits(&self, address: usize) -> u64 {
+        self.hits.get(address).copied().unwrap_or(0)
+    }
+
+    /// Add the hits of `other` to these ones.
+    pub fn merge(&mut self, other: &Coverage) {
+        if self.hits.len() < other.hits.len() {
+            self.hits.resize(other.hits.len(), 0);
+        }
+        for (hits, other) in self.hits.iter_mut().zip(&other.hits) {
+            *hits += other;
+        }
+    }
+
+    /// Annotate `listing`, in the syntax of the `.dis` files, with the
+    /// hits of its instructions.
+    pub fn annotate(&self, listing: &str) -> Result<Report, AsmError> {
+        let mut hits = vec![None; listing.lines().count()];
+        for (line, address) in assembler::instruction_lines(listing)? {
+            hits[line - 1] = Some((address, self.hits(address)));
+        }
+        let lines = listing
+            .lines()
+            .zip(hits)
+            .map(|(text, hits)| (text.to_string(), hits))
+            .collect();
+        Ok(Report { lines })
+    }
+
+    pub(crate) fn hit(&mut self, address: usize) {
+        if let Some(hits) = self.hits.get_mut(address) {
+            *hits += 1;
+        }
+    }
+}
+
+/// Listing annotated with hit counts, as returned by [Coverage::annotate].
+/// It is displayed as the annotated listing followed by a summary.
+#[derive(Debug, Clone)]
+pub struct Report {
+    lines: Vec<(String, Option<(usize, u64)>)>,
+}
+
+impl Report {
+    /// Number of instructions in the listing.
+    pub fn total(&self) -> usize {
+        self.instructions().count()
+    }
+
+    /// Number of instructions executed at least once.
+    pub fn covered(&self) -> usize {
+        self.instructions().filter(|&(_, hits)| hits > 0).count()
+    }
+
+    /// Percentage of instructions executed at least once, 100 for an
+    /// empty listing.
+    pub fn percentage(&self) -> f64 {
+        match self.total() {
+            0 => 100.0,
+            total => 100.0 * self.covered() as f64 / total as f64,
+        }
+    }
+
+    /// Addresses of the instructions never executed.
+    pub fn uncovered(&self) -> Vec<usize> {
+        self.instructions()
+            .filter(|&(_, hits)| hits == 0)
+            .map(|(address, _)| address)
+            .collect()
+    }
+
+    fn instructions(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.lines.iter().filter_map(|(_, hits)| *hits)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (text, hits) in &self.lines {
+            let line = match hits {
+                Some((_, 0)) => format!("{:>8} | {}", "#####", text),
+                Some((_, hits)) => format!("{:>8} | {}", hits, text),
+                None => format!("{:>8} | {}", "", text),
+            };
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        write!(
+            f,
+            "coverage: {}/{} instructions ({:.2}%)",
+            self.covered(),
+            self.total(),
+            self.percentage()
+        )
+    }
+}
+
+impl Machine {
+    /// Start recording which instructions are executed, or stop and forget
+    /// the recorded hits when `enabled` is false. Enabling the coverage
+    /// again keeps the hits recorded so far.
+    pub fn set_coverage(&mut self, enabled: bool) {
+        if !enabled {
+            self.coverage = None;
+        } else if self.coverage.is_none() {
+            self.coverage = Some(Coverage::new(self.memory.len()));
+        }
+    }
+
+    /// Hits recorded since the coverage was enabled, if it is.
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+}
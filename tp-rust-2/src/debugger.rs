@@ -0,0 +1,501 @@
+//! Interactive step debugger built on top of [Machine::step_on].
+//!
+//! The debugger can be driven either through its API ([Debugger::step_on],
+//! [Debugger::continue_on], breakpoints, and the watchpoints of the
+//! [machine](Machine::add_watchpoint)) or through a small command
+//! language read by [Debugger::repl]. Locations are given as decimal or
+//! `0x`-prefixed addresses, or as label names when labels are known.
+
+use crate::{
+    assembler::parse_number, disassembler, Instruction, Machine, MachineError, MachineErrorKind,
+    WatchHit, Watchpoint,
+};
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{self, BufRead, Write},
+};
+
+/// Number of instructions shown before and after IP by `list`.
+const LIST_BEFORE: usize = 3;
+const LIST_AFTER: usize = 6;
+
+/// Number of steps which can be undone, unless the machine was already
+/// configured with another limit.
+const HISTORY_SIZE: usize = 10_000;
+
+const HELP: &str = "\
+commands:
+  break <loc>        (b)  set a breakpoint
+  delete <loc>       (d)  remove a breakpoint
+  watch <loc> [len]  (w)  stop when memory is written (default len 4)
+  watch rN                stop when a register changes
+  rwatch <loc> [len]      stop when memory is read
+  awatch <loc> [len]      stop when memory is read or written
+  unwatch <loc> | rN      remove the watchpoints on <loc> or rN
+  breakpoints        (i)  list breakpoints and watchpoints
+  step [n]           (s)  execute n instructions (default 1)
+  continue           (c)  run until a breakpoint or the end
+  rstep [n]          (rs) undo n instructions (default 1)
+  rewind <loc>       (rw) undo instructions until IP reaches <loc>
+  regs               (r)  show registers
+  mem <loc> [len]    (x)  show memory
+  set rN <value>          modify a register
+  set <loc> <value>       modify a 32-bit word in memory
+  list [loc]         (l)  disassemble around IP or <loc>
+  quit               (q)  leave the debugger
+an empty line repeats the previous command";
+
+/// Reason why the debugger gave control back.
+#[derive(Debug)]
+pub enum Stop {
+    /// The requested instructions were executed.
+    Step,
+    /// IP reached the breakpoint at the given address.
+    Breakpoint(usize),
+    /// The instruction at the given address, which was executed,
+    /// triggered a watchpoint.
+    Watchpoint(usize, WatchHit),
+    /// The program executed an exit instruction.
+    Exit,
+    /// The instruction at the given address failed. IP is reset to this
+    /// address so that the instruction can be retried after fixing the
+    /// machine state.
+    Error(usize, MachineError),
+}
+
+pub struct Debugger {
+    machine: Machine,
+    breakpoints: BTreeSet<usize>,
+    labels: HashMap<String, usize>,
+    exited: bool,
+}
+
+impl Debugger {
+    /// Create a debugger controlling `machine`. The undo journal of the
+    /// machine is enabled if it was not already.
+    pub fn new(mut machine: Machine) -> Self {
+        if machine.history_limit() == 0 {
+            machine.set_history_limit(HISTORY_SIZE);
+        }
+        Debugger {
+            machine,
+            breakpoints: BTreeSet::new(),
+            labels: HashMap::new(),
+            exited: false,
+        }
+    }
+
+    /// Use `labels` (for example coming from
+    /// [assemble_with_labels](crate::assembler::assemble_with_labels)) to
+    /// resolve and display locations.
+    pub fn with_labels(mut self, labels: HashMap<String, usize>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// Reference onto the debugged machine.
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    /// Mutable reference onto the debugged machine.
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    /// Resolve a location given as a label or as an address.
+    pub fn resolve(&self, location: &str) -> Option<usize> {
+        match self.labels.get(location) {
+            Some(&address) => Some(address),
+            None => parse_number(location).and_then(|a| usize::try_from(a).ok()),
+        }
+    }
+
+    /// Add a breakpoint. Returns `false` if it was already set.
+    pub fn add_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    /// Remove a breakpoint. Returns `false` if it was not set.
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    /// Addresses of the current breakpoints, in increasing order.
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Execute one instruction, printing program output on `fd`.
+    pub fn step_on<T: Write>(&mut self, fd: &mut T) -> Stop {
+        if self.exited {
+            return Stop::Exit;
+        }
+        let address = self.ip();
+        match self.machine.step_on(fd) {
+            Ok(false) => Stop::Step,
+            Ok(true) => {
+                self.exited = true;
+                Stop::Exit
+            }
+            Err(MachineError {
+                kind: MachineErrorKind::Watchpoint(hit),
+                ..
+            }) => Stop::Watchpoint(address, hit),
+            Err(e) => {
+                self.machine.set_reg(0, address as u32).unwrap();
+                Stop::Error(address, e)
+            }
+        }
+    }
+
+    /// Execute instructions until a breakpoint is reached, the program
+    /// exits or an error happens. The instruction at IP is always
+    /// executed, even if a breakpoint is set there.
+    pub fn continue_on<T: Write>(&mut self, fd: &mut T) -> Stop {
+        loop {
+            match self.step_on(fd) {
+                Stop::Step if self.breakpoints.contains(&self.ip()) => {
+                    return Stop::Breakpoint(self.ip())
+                }
+                Stop::Step => (),
+                stop => return stop,
+            }
+        }
+    }
+
+    /// Undo the last executed instruction. Returns `false` if there is no
+    /// more history.
+    pub fn step_back(&mut self) -> bool {
+        let undone = self.machine.step_back();
+        if undone {
+            self.exited = false;
+        }
+        undone
+    }
+
+    /// Undo instructions until IP reaches `address`. Returns `false` if the
+    /// history is exhausted before, the machine being then left in the
+    /// oldest recorded state.
+    pub fn run_back_to(&mut self, address: usize) -> bool {
+        let undone = self.machine.history_len() > 0;
+        let reached = self.machine.run_back_to(address);
+        if undone {
+            self.exited = false;
+        }
+        reached
+    }
+
+    /// Read commands from `input` until it is exhausted or `quit` is
+    /// entered. Both the debugger messages and the program output are
+    /// written on `out`.
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, out: &mut W) -> io::Result<()> {
+        let mut lines = input.lines();
+        let mut previous = String::new();
+        loop {
+            write!(out, "(vm) ")?;
+            out.flush()?;
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+            let line = if line.trim().is_empty() {
+                previous.clone()
+            } else {
+                line
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if !self.command(&words, out)? {
+                return Ok(());
+            }
+            previous = line;
+        }
+    }
+
+    /// Execute one command. Returns `false` if the debugger must stop.
+    fn command<W: Write>(&mut self, words: &[&str], out: &mut W) -> io::Result<bool> {
+        match words {
+            [] => (),
+            ["help" | "h"] => writeln!(out, "{}", HELP)?,
+            ["quit" | "q"] => return Ok(false),
+            ["break" | "b", location] => match self.resolve(location) {
+                Some(address) => {
+                    self.add_breakpoint(address);
+                    writeln!(out, "breakpoint at {}", self.describe(address))?;
+                }
+                None => writeln!(out, "unknown location `{}`", location)?,
+            },
+            ["delete" | "d", location] => match self.resolve(location) {
+                Some(address) if self.remove_breakpoint(address) => {
+                    writeln!(out, "breakpoint at {} removed", self.describe(address))?
+                }
+                _ => writeln!(out, "no breakpoint at `{}`", location)?,
+            },
+            ["breakpoints" | "i"] => {
+                for address in self.breakpoints() {
+                    writeln!(out, "breakpoint at {}", self.describe(address))?;
+                }
+                for watchpoint in self.machine.watchpoints() {
+                    writeln!(out, "watchpoint on {}", watchpoint)?;
+                }
+            }
+            [kind @ ("watch" | "w" | "rwatch" | "awatch"), target, len @ ..] if len.len() <= 1 => {
+                self.watch_command(kind, target, len.first().copied(), out)?
+            }
+            ["unwatch", target] => self.unwatch_command(target, out)?,
+            ["step" | "s"] => self.step_command(1, out)?,
+            ["step" | "s", count] => match count.parse() {
+                Ok(count) => self.step_command(count, out)?,
+                Err(_) => writeln!(out, "invalid count `{}`", count)?,
+            },
+            ["continue" | "c"] => {
+                let stop = self.continue_on(out);
+                self.report(stop, out)?;
+            }
+            ["rstep" | "rs"] => self.back_command(1, out)?,
+            ["rstep" | "rs", count] => match count.parse() {
+                Ok(count) => self.back_command(count, out)?,
+                Err(_) => writeln!(out, "invalid count `{}`", count)?,
+            },
+            ["rewind" | "rw", location] => match self.resolve(location) {
+                Some(address) => {
+                    if !self.run_back_to(address) {
+                        write!(out, "no more history, ")?;
+                    }
+                    writeln!(out, "stopped at {}", self.current())?;
+                }
+                None => writeln!(out, "unknown location `{}`", location)?,
+            },
+            ["regs" | "r"] => self.print_regs(out)?,
+            ["mem" | "x", location] => self.mem_command(location, "16", out)?,
+            ["mem" | "x", location, len] => self.mem_command(location, len, out)?,
+            ["set", target, value] => self.set_command(target, value, out)?,
+            ["list" | "l"] => self.list(self.ip(), out)?,
+            ["list" | "l", location] => match self.resolve(location) {
+                Some(address) => self.list(address, out)?,
+                None => writeln!(out, "unknown location `{}`", location)?,
+            },
+            _ => writeln!(out, "unknown command, type `help` for help")?,
+        }
+        Ok(true)
+    }
+
+    fn step_command<W: Write>(&mut self, count: usize, out: &mut W) -> io::Result<()> {
+        let mut stop = Stop::Step;
+        for _ in 0..count {
+            stop = self.step_on(out);
+            if !matches!(stop, Stop::Step) {
+                break;
+            }
+        }
+        self.report(stop, out)
+    }
+
+    fn back_command<W: Write>(&mut self, count: usize, out: &mut W) -> io::Result<()> {
+        for _ in 0..count {
+            if !self.step_back() {
+                write!(out, "no more history, ")?;
+                break;
+            }
+        }
+        writeln!(out, "stopped at {}", self.current())
+    }
+
+    fn watch_command<W: Write>(
+        &mut self,
+        kind: &str,
+        target: &str,
+        len: Option<&str>,
+        out: &mut W,
+    ) -> io::Result<()> {
+        let watchpoint = match (kind, register(target), len) {
+            ("watch" | "w", Some(reg), None) if reg < self.machine.regs().len() => {
+                Watchpoint::Register(reg)
+            }
+            (_, None, _) => {
+                let (start, len) = match (self.resolve(target), len.unwrap_or("4").parse::<usize>())
+                {
+                    (Some(start), Ok(len)) if len > 0 => (start, len),
+                    _ => return writeln!(out, "invalid memory range"),
+                };
+                let end = start.saturating_add(len);
+                match kind {
+                    "rwatch" => Watchpoint::Read { start, end },
+                    "awatch" => Watchpoint::Access { start, end },
+                    _ => Watchpoint::Write { start, end },
+                }
+            }
+            _ => return writeln!(out, "invalid watchpoint `{}`", target),
+        };
+        self.machine.add_watchpoint(watchpoint);
+        writeln!(out, "watchpoint on {}", watchpoint)
+    }
+
+    fn unwatch_command<W: Write>(&mut self, target: &str, out: &mut W) -> io::Result<()> {
+        let watched = |watchpoint: &Watchpoint| match (*watchpoint, register(target)) {
+            (Watchpoint::Register(reg), Some(target)) => reg == target,
+            (
+                Watchpoint::Write { start, .. }
+                | Watchpoint::Read { start, .. }
+                | Watchpoint::Access { start, .. },
+                None,
+            ) => self.resolve(target) == Some(start),
+            _ => false,
+        };
+        let removed: Vec<Watchpoint> = self
+            .machine
+            .watchpoints()
+            .iter()
+            .copied()
+            .filter(watched)
+            .collect();
+        if removed.is_empty() {
+            return writeln!(out, "no watchpoint on `{}`", target);
+        }
+        for watchpoint in removed {
+            self.machine.remove_watchpoint(watchpoint);
+            writeln!(out, "watchpoint on {} removed", watchpoint)?;
+        }
+        Ok(())
+    }
+
+    fn mem_command<W: Write>(&mut self, location: &str, len: &str, out: &mut W) -> io::Result<()> {
+        let (address, len) = match (self.resolve(location), len.parse::<usize>()) {
+            (Some(address), Ok(len)) => (address, len),
+            _ => return writeln!(out, "invalid memory range"),
+        };
+        let memory = self.machine.memory();
+        let end = address.saturating_add(len).min(memory.len());
+        for start in (address..end).step_by(16) {
+            write!(out, "{:04}:", start)?;
+            for byte in &memory[start..end.min(start + 16)] {
+                write!(out, " {:02x}", byte)?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    fn set_command<W: Write>(&mut self, target: &str, value: &str, out: &mut W) -> io::Result<()> {
+        let value = match parse_number(value) {
+            Some(value) if value >= i32::MIN as i64 && value <= u32::MAX as i64 => value as u32,
+            _ => return writeln!(out, "invalid value `{}`", value),
+        };
+        let result = match (register(target), self.resolve(target)) {
+            (Some(reg), _) => self.machine.set_reg(reg, value),
+            (None, Some(address)) => self.machine.set_mem(address, &value.to_le_bytes()),
+            (None, None) => return writeln!(out, "unknown location `{}`", target),
+        };
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => writeln!(out, "cannot set `{}`: {}", target, e),
+        }
+    }
+
+    fn print_regs<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for (reg, value) in self.machine.regs().iter().enumerate() {
+            writeln!(out, "r{:<2} = {:#010x} ({})", reg, value, *value as i32)?;
+        }
+        Ok(())
+    }
+
+    /// Disassemble a few instructions around `address`.
+    fn list<W: Write>(&self, address: usize, out: &mut W) -> io::Result<()> {
+        let memory = self.machine.memory();
+        let (isa, nregs) = (self.machine.isa(), self.machine.regs().len());
+        let before = disassembler::disassemble_range_for(memory, 0, address, isa, nregs);
+        let after = disassembler::disassemble_range_for(memory, address, memory.len(), isa, nregs);
+        let skip = before.len().saturating_sub(LIST_BEFORE);
+        for line in before
+            .iter()
+            .skip(skip)
+            .chain(after.iter().take(LIST_AFTER))
+        {
+            for label in self.labels_at(line.address()) {
+                writeln!(out, "{}:", label)?;
+            }
+            let marker = if line.address() == self.ip() {
+                "=>"
+            } else if self.breakpoints.contains(&line.address()) {
+                " *"
+            } else {
+                "  "
+            };
+            writeln!(out, "{} {}", marker, &line.to_string()[2..])?;
+        }
+        Ok(())
+    }
+
+    fn report<W: Write>(&self, stop: Stop, out: &mut W) -> io::Result<()> {
+        match stop {
+            Stop::Step => writeln!(out, "stopped at {}", self.current())?,
+            Stop::Breakpoint(_) => writeln!(out, "breakpoint reached at {}", self.current())?,
+            Stop::Watchpoint(address, hit) => {
+                writeln!(
+                    out,
+                    "watchpoint on {} hit by {}",
+                    hit.watchpoint,
+                    self.instruction_at(address)
+                )?;
+                writeln!(out, "  {}", hit.event)?;
+                writeln!(out, "stopped at {}", self.current())?;
+            }
+            Stop::Exit => writeln!(
+                out,
+                "program exited with status {}",
+                self.machine.exit_status()
+            )?,
+            Stop::Error(_, e) => writeln!(out, "error: {} at {}", e.kind, self.current())?,
+        }
+        Ok(())
+    }
+
+    /// Current IP with its label and the instruction found there.
+    fn current(&self) -> String {
+        self.instruction_at(self.ip())
+    }
+
+    /// Address with its label and the instruction found there.
+    fn instruction_at(&self, address: usize) -> String {
+        let isa = self.machine.isa();
+        match self
+            .machine
+            .memory()
+            .get(address..)
+            .map(|bytes| Instruction::decode_for(bytes, isa))
+        {
+            Some(Ok((instruction, _))) => format!("{}: {}", self.describe(address), instruction),
+            _ => format!("{}: invalid instruction", self.describe(address)),
+        }
+    }
+
+    /// Address followed by the labels pointing to it, if any.
+    fn describe(&self, address: usize) -> String {
+        let labels = self.labels_at(address);
+        if labels.is_empty() {
+            format!("{:04}", address)
+        } else {
+            format!("{:04} <{}>", address, labels.join(", "))
+        }
+    }
+
+    fn labels_at(&self, address: usize) -> Vec<&str> {
+        let mut labels: Vec<&str> = self
+            .labels
+            .iter()
+            .filter(|(_, &a)| a == address)
+            .map(|(label, _)| label.as_str())
+            .collect();
+        labels.sort_unstable();
+        labels
+    }
+
+    fn ip(&self) -> usize {
+        self.machine.regs()[0] as usize
+    }
+}
+
+/// Register index of a target written as `rN`.
+fn register(target: &str) -> Option<usize> {
+    target.strip_prefix('r').and_then(|n| n.parse().ok())
+}
@@ -0,0 +1,234 @@
+//! Devices mapped onto ranges of the address space.
+//!
+//! A `load` or `store` whose address falls into the range of a device is
+//! forwarded to it instead of reaching the memory. Devices may be mapped
+//! over the memory, shadowing it, or above it. Instructions are always
+//! fetched from the memory.
+//!
+//! The devices provided here are usually mapped at the top of the address
+//! space, which `loadimm` reaches with negative immediates:
+//!
+//! | device           | address                   | immediate |
+//! |------------------|---------------------------|-----------|
+//! | [Console]        | [CONSOLE_ADDRESS]         | `#-256`   |
+//! | [CycleCounter]   | [CYCLE_COUNTER_ADDRESS]   | `#-240`   |
+//! | [Random]         | [RANDOM_ADDRESS]          | `#-224`   |
+
+use crate::{Machine, MachineError, MachineErrorKind};
+use std::io::{self, Read, Write};
+
+/// Usual address of the [Console].
+pub const CONSOLE_ADDRESS: usize = 0xffff_ff00;
+/// Usual address of the [CycleCounter].
+pub const CYCLE_COUNTER_ADDRESS: usize = 0xffff_ff10;
+/// Usual address of the [Random] generator.
+pub const RANDOM_ADDRESS: usize = 0xffff_ff20;
+
+/// Hardware seen by the program through `load` and `store`.
+///
+/// Accesses are 32-bit wide. `offset` is relative to the start of the
+/// range of the device, and `steps` is the number of instructions executed
+/// so far by the machine. A device rejecting an offset returns
+/// [MemoryIndexOutOfBounds](MachineErrorKind::MemoryIndexOutOfBounds)
+/// with this offset, which the machine turns into an absolute address.
+pub trait Device {
+    /// Size in bytes of the range occupied by the device.
+    fn size(&self) -> usize;
+
+    /// Value read by `load` at `offset`.
+    fn load(&mut self, offset: usize, steps: u64) -> Result<u32, MachineErrorKind>;
+
+    /// Effect of `store` of `value` at `offset`.
+    fn store(&mut self, offset: usize, value: u32, steps: u64) -> Result<(), MachineErrorKind>;
+}
+
+/// A device with the range it is mapped onto.
+pub(crate) struct Mapping {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) device: Box<dyn Device>,
+}
+
+impl Mapping {
+    pub(crate) fn contains(&self, address: usize) -> bool {
+        self.start <= address && address < self.end
+    }
+
+    pub(crate) fn load(&mut self, address: usize, steps: u64) -> Result<u32, MachineError> {
+        let start = self.start;
+        self.device
+            .load(address - start, steps)
+            .map_err(|e| Self::absolute(e, start))
+    }
+
+    pub(crate) fn store(
+        &mut self,
+        address: usize,
+        value: u32,
+        steps: u64,
+    ) -> Result<(), MachineError> {
+        let start = self.start;
+        self.device
+            .store(address - start, value, steps)
+            .map_err(|e| Self::absolute(e, start))
+    }
+
+    /// Turn an offset reported by the device into an address.
+    fn absolute(e: MachineErrorKind, start: usize) -> MachineError {
+        match e {
+            MachineErrorKind::MemoryIndexOutOfBounds(offset) => {
+                MachineErrorKind::MemoryIndexOutOfBounds(start + offset).into()
+            }
+            e => e.into(),
+        }
+    }
+}
+
+impl Machine {
+    /// Map `device` at `address`. Fails with
+    /// [AddressAlreadyMapped](MachineErrorKind::AddressAlreadyMapped) if
+    /// its range overlaps the one of another device, and with
+    /// [MemoryIndexOutOfBounds](MachineErrorKind::MemoryIndexOutOfBounds)
+    /// if it goes beyond the 32-bit address space.
+    pub fn map_device(
+        &mut self,
+        address: usize,
+        device: Box<dyn Device>,
+    ) -> Result<(), MachineError> {
+        let end = address
+            .checked_add(device.size())
+            .filter(|&end| end <= u32::MAX as usize + 1)
+            .ok_or(MachineErrorKind::MemoryIndexOutOfBounds(address))?;
+        if let Some(other) = self
+            .devices
+            .iter()
+            .find(|m| m.start < end && address < m.end)
+        {
+            return Err(MachineErrorKind::AddressAlreadyMapped(other.start.max(address)).into());
+        }
+        self.devices.push(Mapping {
+            start: address,
+            end,
+            device,
+        });
+        Ok(())
+    }
+}
+
+/// Byte console: storing writes the low 8 bits of the value to the output,
+/// loading reads one byte from the input, or gives -1 at end of input.
+pub struct Console<R: Read, W: Write> {
+    input: R,
+    output: W,
+}
+
+impl<R: Read, W: Write> Console<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Console { input, output }
+    }
+}
+
+impl Console<io::Stdin, io::Stdout> {
+    /// Console connected to the standard input and output.
+    pub fn stdio() -> Self {
+        Console::new(io::stdin(), io::stdout())
+    }
+}
+
+impl<R: Read, W: Write> Device for Console<R, W> {
+    fn size(&self) -> usize {
+        4
+    }
+
+    fn load(&mut self, _offset: usize, _steps: u64) -> Result<u32, MachineErrorKind> {
+        let mut byte = [0; 1];
+        loop {
+            match self.input.read(&mut byte) {
+                Ok(0) => return Ok(u32::MAX),
+                Ok(_) => return Ok(byte[0] as u32),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(MachineErrorKind::ReadFromInputFailed(e)),
+            }
+        }
+    }
+
+    fn store(&mut self, _offset: usize, value: u32, _steps: u64) -> Result<(), MachineErrorKind> {
+        self.output
+            .write_all(&[value as u8])
+            .and_then(|_| self.output.flush())
+            .map_err(MachineErrorKind::WriteToBufferFailed)
+    }
+}
+
+/// Counter of executed instructions, as two 32-bit words: the low half at
+/// offset 0 and the high half at offset 4. Storing any value restarts the
+/// count from 0.
+/// The count stays at 0 while the machine is back before the restart,
+/// e.g. after stepping back.
+#[derive(Debug, Default)]
+pub struct CycleCounter {
+    origin: u64,
+}
+
+impl CycleCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Device for CycleCounter {
+    fn size(&self) -> usize {
+        8
+    }
+
+    fn load(&mut self, offset: usize, steps: u64) -> Result<u32, MachineErrorKind> {
+        let count = steps.saturating_sub(self.origin);
+        match offset {
+            0 => Ok(count as u32),
+            4 => Ok((count >> 32) as u32),
+            _ => Err(MachineErrorKind::MemoryIndexOutOfBounds(offset)),
+        }
+    }
+
+    fn store(&mut self, _offset: usize, _value: u32, steps: u64) -> Result<(), MachineErrorKind> {
+        self.origin = steps;
+        Ok(())
+    }
+}
+
+/// Pseudo-random generator (xorshift32): every load gives a new number,
+/// storing a value reseeds the generator.
+#[derive(Debug)]
+pub struct Random {
+    state: u32,
+}
+
+impl Random {
+    /// Generator starting from `seed`. A zero seed, which xorshift cannot
+    /// leave, is replaced by a fixed non-zero one.
+    pub fn new(seed: u32) -> Self {
+        Random {
+            state: if seed == 0 { 0x9e37_79b9 } else { seed },
+        }
+    }
+}
+
+impl Device for Random {
+    fn size(&self) -> usize {
+        4
+    }
+
+    fn load(&mut self, _offset: usize, _steps: u64) -> Result<u32, MachineErrorKind> {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        Ok(x)
+    }
+
+    fn store(&mut self, _offset: usize, value: u32, _steps: u64) -> Result<(), MachineErrorKind> {
+        *self = Random::new(value);
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+//! Disassembler producing listings in the `.dis` style.
+//!
+//! Memory is decoded linearly from address 0. Bytes which do not start a
+//! valid instruction (unknown opcode, truncated instruction or register
+//! out of bounds, the machine having [NREGS] registers unless told
+//! otherwise) are grouped into data lines shown as byte strings, so
+//! that the listing can be fed back to the [assembler](crate::assembler).
+
+use crate::{machine::NREGS, Instruction, Isa};
+use std::{
+    fmt,
+    io::{self, Write},
+};
+
+/// Maximum number of bytes shown on a single data line.
+const DATA_LINE_SIZE: usize = 16;
+
+/// One line of a listing.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Line<'a> {
+    /// A valid instruction.
+    Instruction {
+        address: usize,
+        instruction: Instruction,
+    },
+    /// Bytes which are not valid instructions.
+    Data { address: usize, bytes: &'a [u8] },
+}
+
+impl Line<'_> {
+    /// Address of the first byte of the line.
+    pub fn address(&self) -> usize {
+        match self {
+            Line::Instruction { address, .. } | Line::Data { address, .. } => *address,
+        }
+    }
+}
+
+impl fmt::Display for Line<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Line::Instruction {
+                address,
+                instruction,
+            } => write!(f, "  {:04}   {}", address, instruction),
+            Line::Data { address, bytes } => {
+                write!(f, "  {:04}   {}", address, ByteString(bytes))
+            }
+        }
+    }
+}
+
+/// Decode the instruction starting at `address`, or return `None` if the
+/// bytes there are not a valid instruction for the machine.
+pub fn decode_at(memory: &[u8], address: usize) -> Option<(Instruction, usize)> {
+    decode_at_for(memory, address, Isa::Base, NREGS)
+}
+
+/// Similar to [decode_at], accepting the instructions of `isa` on a
+/// machine with `nregs` registers.
+pub fn decode_at_for(
+    memory: &[u8],
+    address: usize,
+    isa: Isa,
+    nregs: usize,
+) -> Option<(Instruction, usize)> {
+    let (instruction, size) = Instruction::decode_for(memory.get(address..)?, isa).ok()?;
+    if instruction
+        .registers()
+        .iter()
+        .all(|&r| (r as usize) < nregs)
+    {
+        Some((instruction, size))
+    } else {
+        None
+    }
+}
+
+/// Disassemble a whole memory image.
+pub fn disassemble(memory: &[u8]) -> Vec<Line<'_>> {
+    disassemble_range(memory, 0, memory.len())
+}
+
+/// Similar to [disassemble], accepting the instructions of `isa` on a
+/// machine with `nregs` registers.
+pub fn disassemble_for(memory: &[u8], isa: Isa, nregs: usize) -> Vec<Line<'_>> {
+    disassemble_range_for(memory, 0, memory.len(), isa, nregs)
+}
+
+/// Disassemble the bytes of `memory` located between `start` (included)
+/// and `end` (excluded). Instructions may not extend past `end`.
+pub fn disassemble_range(memory: &[u8], start: usize, end: usize) -> Vec<Line<'_>> {
+    disassemble_range_for(memory, start, end, Isa::Base, NREGS)
+}
+
+/// Similar to [disassemble_range], accepting the instructions of `isa` on
+/// a machine with `nregs` registers.
+pub fn disassemble_range_for(
+    memory: &[u8],
+    start: usize,
+    end: usize,
+    isa: Isa,
+    nregs: usize,
+) -> Vec<Line<'_>> {
+    let memory = &memory[..end.min(memory.len())];
+    let mut lines = Vec::new();
+    let mut address = start;
+    let mut data_start = None;
+    while address < memory.len() {
+        match decode_at_for(memory, address, isa, nregs) {
+            Some((instruction, size)) => {
+                if let Some(start) = data_start.take() {
+                    push_data(&mut lines, memory, start, address);
+                }
+                lines.push(Line::Instruction {
+                    address,
+                    instruction,
+                });
+                address += size;
+            }
+            None => {
+                data_start.get_or_insert(address);
+                address += 1;
+            }
+        }
+    }
+    if let Some(start) = data_start {
+        push_data(&mut lines, memory, start, memory.len());
+    }
+    lines
+}
+
+/// Write the listing of `memory` on `fd`, one line per instruction.
+pub fn disassemble_on<T: Write>(memory: &[u8], fd: &mut T) -> io::Result<()> {
+    disassemble_on_for(memory, Isa::Base, NREGS, fd)
+}
+
+/// Similar to [disassemble_on], accepting the instructions of `isa` on a
+/// machine with `nregs` registers.
+pub fn disassemble_on_for<T: Write>(
+    memory: &[u8],
+    isa: Isa,
+    nregs: usize,
+    fd: &mut T,
+) -> io::Result<()> {
+    for line in disassemble_for(memory, isa, nregs) {
+        writeln!(fd, "{}", line)?;
+    }
+    Ok(())
+}
+
+fn push_data<'a>(lines: &mut Vec<Line<'a>>, memory: &'a [u8], start: usize, end: usize) {
+    for address in (start..end).step_by(DATA_LINE_SIZE) {
+        let bytes = &memory[address..end.min(address + DATA_LINE_SIZE)];
+        lines.push(Line::Data { address, bytes });
+    }
+}
+
+/// Python-like representation of a byte string, as found in the listings.
+struct ByteString<'a>(&'a [u8]);
+
+impl fmt::Display for ByteString<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let quote = if self.0.contains(&b'\'') && !self.0.contains(&b'"') {
+            '"'
+        } else {
+            '\''
+        };
+        write!(f, "b{}", quote)?;
+        for &b in self.0 {
+            match b {
+                b'\n' => write!(f, "\\n")?,
+                b'\t' => write!(f, "\\t")?,
+                b'\r' => write!(f, "\\r")?,
+                b'\\' => write!(f, "\\\\")?,
+                _ if b as char == quote => write!(f, "\\{}", quote)?,
+                0x20..=0x7e => write!(f, "{}", b as char)?,
+                _ => write!(f, "\\x{:02x}", b)?,
+            }
+        }
+        write!(f, "{}", quote)
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{Access, WatchHit};
+use std::{error, fmt, io};
+
+/// What went wrong during the execution of an instruction.
+#[derive(Debug)]
+pub enum MachineErrorKind {
+    /// The register with this index does not exist.
+    RegisterOutOfBounds(usize),
+    /// This memory address is outside of the machine memory.
+    MemoryIndexOutOfBounds(usize),
+    /// This byte is not a valid opcode.
+    InvalidOpcode(u8),
+    /// This value cannot be converted to a character.
+    NumberConversionToCharNotValid(u32),
+    /// The output could not be written.
+    WriteToBufferFailed(io::Error),
+    /// The input could not be read.
+    ReadFromInputFailed(io::Error),
+    /// The input does not contain a valid number.
+    InvalidNumberInput,
+    /// The step limit was reached after this many steps.
+    StepLimitExceeded(u64),
+    /// The deadline was reached after this many steps.
+    DeadlineExceeded(u64),
+    /// A device is already mapped at this address.
+    AddressAlreadyMapped(usize),
+    /// A `div` or `mod` instruction has a zero divisor.
+    DivisionByZero,
+    /// The protection of this address does not allow this access.
+    ProtectionFault { address: usize, access: Access },
+    /// A watchpoint was triggered by the instruction, which was executed.
+    Watchpoint(WatchHit),
+}
+
+/// Error raised by the machine, with the location of the faulting
+/// instruction when it is known.
+#[derive(Debug)]
+pub struct MachineError {
+    pub kind: MachineErrorKind,
+    /// Address of the faulting instruction.
+    pub ip: Option<usize>,
+    /// Opcode of the faulting instruction, if it could be read.
+    pub opcode: Option<u8>,
+}
+
+impl MachineError {
+    /// Attach the location of the faulting instruction, unless the error
+    /// already has one.
+    pub fn at(mut self, ip: usize, opcode: Option<u8>) -> Self {
+        if self.ip.is_none() {
+            self.ip = Some(ip);
+            self.opcode = opcode;
+        }
+        self
+    }
+}
+
+impl From<MachineErrorKind> for MachineError {
+    fn from(kind: MachineErrorKind) -> Self {
+        MachineError {
+            kind,
+            ip: None,
+            opcode: None,
+        }
+    }
+}
+
+impl fmt::Display for MachineErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MachineErrorKind::RegisterOutOfBounds(reg) => {
+                write!(f, "register r{} does not exist", reg)
+            }
+            MachineErrorKind::MemoryIndexOutOfBounds(address) => {
+                write!(f, "address {} is outside of memory", address)
+            }
+            MachineErrorKind::InvalidOpcode(opcode) => write!(f, "invalid opcode {}", opcode),
+            MachineErrorKind::NumberConversionToCharNotValid(value) => {
+                write!(f, "{:#x} is not a valid character", value)
+            }
+            MachineErrorKind::WriteToBufferFailed(e) => write!(f, "cannot write output: {}", e),
+            MachineErrorKind::ReadFromInputFailed(e) => write!(f, "cannot read input: {}", e),
+            MachineErrorKind::InvalidNumberInput => write!(f, "input is not a valid number"),
+            MachineErrorKind::StepLimitExceeded(steps) => {
+                write!(f, "step limit exceeded after {} steps", steps)
+            }
+            MachineErrorKind::DeadlineExceeded(steps) => {
+                write!(f, "deadline exceeded after {} steps", steps)
+            }
+            MachineErrorKind::AddressAlreadyMapped(address) => {
+                write!(f, "address {} is already mapped to a device", address)
+            }
+            MachineErrorKind::DivisionByZero => write!(f, "division by zero"),
+            MachineErrorKind::ProtectionFault { address, access } => {
+                write!(
+                    f,
+                    "{} access to address {} is not permitted",
+                    access, address
+                )
+            }
+            MachineErrorKind::Watchpoint(hit) => write!(f, "{}", hit),
+        }
+    }
+}
+
+impl fmt::Display for MachineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        match (self.ip, self.opcode) {
+            (Some(ip), Some(opcode)) => write!(f, " at address {} (opcode {})", ip, opcode),
+            (Some(ip), None) => write!(f, " at address {}", ip),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl error::Error for MachineError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.kind {
+            MachineErrorKind::WriteToBufferFailed(e) | MachineErrorKind::ReadFromInputFailed(e) => {
+                Some(e)
+            }
+            _ => None,
+        }
+    }
+}
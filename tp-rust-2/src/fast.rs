@@ -0,0 +1,167 @@
+//! Pre-decoded execution engine.
+//!
+//! [run_fast_with_io](Machine::run_fast_with_io) decodes every instruction
+//! only once and keeps it in a cache indexed by address. Register indices
+//! are checked when the instruction enters the cache, so that the most
+//! common instructions execute without any further check. Writes to memory
+//! invalidate the cached instructions they overlap, so self-modifying
+//! programs behave as with [step_on](Machine::step_on).
+//!
+//! Results, errors, step counts, budgets and the undo journal are
+//! identical to those of the regular interpreter, which still executes
+//! the less frequent instructions.
+
+use crate::{history::Change, machine::IP, Instruction, Machine, MachineError};
+use std::io::{self, Read, Write};
+
+/// Instruction whose registers are known to exist.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Op {
+    MoveIf(usize, usize, usize),
+    Store(usize, usize),
+    Load(usize, usize),
+    LoadImm(usize, u32),
+    Sub(usize, usize, usize),
+    /// Executed by the regular interpreter.
+    Other(Instruction),
+}
+
+/// Cache entry for the instruction starting at some address.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Cached {
+    op: Op,
+    /// Address of the following instruction.
+    next: u32,
+    opcode: u8,
+}
+
+impl Machine {
+    /// Similar to [run](Machine::run), using the pre-decoded engine.
+    pub fn run_fast(&mut self) -> Result<u32, MachineError> {
+        self.run_fast_with_io(&mut io::stdin().lock(), &mut io::stdout().lock())
+    }
+
+    /// Similar to [run_on](Machine::run_on), using the pre-decoded engine.
+    pub fn run_fast_on<T: Write>(&mut self, fd: &mut T) -> Result<u32, MachineError> {
+        self.run_fast_with_io(&mut io::empty(), fd)
+    }
+
+    /// Similar to [run_with_io](Machine::run_with_io), using the
+    /// pre-decoded engine.
+    pub fn run_fast_with_io<R: Read, W: Write>(
+        &mut self,
+        input: &mut R,
+        fd: &mut W,
+    ) -> Result<u32, MachineError> {
+        // Watchpoints are only checked by the regular interpreter
+        if !self.watchpoints.is_empty() {
+            return self.run_with_io(input, fd);
+        }
+        if self.cache.len() != self.memory.len() {
+            self.cache = vec![None; self.memory.len()];
+        }
+        loop {
+            let address = self.registers[IP] as usize;
+            self.check_budget().map_err(|e| e.at(address, None))?;
+            let cached = match self.cache.get(address) {
+                Some(Some(cached)) => *cached,
+                _ => self.predecode(address)?,
+            };
+
+            self.history.begin(self.steps);
+            self.write_reg(IP, cached.next);
+            let result = match cached.op {
+                Op::MoveIf(a, b, c) => {
+                    if self.registers[c] != 0 {
+                        self.write_reg(a, self.registers[b]);
+                    }
+                    Ok(false)
+                }
+                Op::LoadImm(a, value) => {
+                    self.write_reg(a, value);
+                    Ok(false)
+                }
+                Op::Sub(a, b, c) => {
+                    self.write_reg(a, self.registers[b].wrapping_sub(self.registers[c]));
+                    Ok(false)
+                }
+                Op::Load(a, b) => {
+                    let addr = self.registers[b] as usize;
+                    match self.memory.get(addr..addr.wrapping_add(4)) {
+                        Some(word) if self.devices.is_empty() && self.regions.is_empty() => {
+                            let value = u32::from_le_bytes(word.try_into().unwrap());
+                            self.write_reg(a, value);
+                            Ok(false)
+                        }
+                        _ => self.load(a, b),
+                    }
+                }
+                Op::Store(a, b) => {
+                    let addr = self.registers[a] as usize;
+                    match self.memory.get(addr..addr.wrapping_add(4)) {
+                        Some(word) if self.devices.is_empty() && self.regions.is_empty() => {
+                            let old = word.try_into().unwrap();
+                            self.history.record(Change::Memory(addr, old));
+                            let value = self.registers[b].to_le_bytes();
+                            self.memory[addr..addr + 4].copy_from_slice(&value);
+                            self.invalidate(addr, 4);
+                            Ok(false)
+                        }
+                        _ => self.store(a, b),
+                    }
+                }
+                Op::Other(instruction) => self.execute(input, fd, instruction),
+            };
+            match result {
+                Ok(exited) => {
+                    self.steps += 1;
+                    self.history.commit();
+                    if let Some(coverage) = &mut self.coverage {
+                        coverage.hit(address);
+                    }
+                    if exited {
+                        return Ok(self.exit_status());
+                    }
+                }
+                Err(e) => {
+                    self.history.abort();
+                    return Err(e.at(address, Some(cached.opcode)));
+                }
+            }
+        }
+    }
+
+    /// Decode the instruction at `address` and put it into the cache.
+    fn predecode(&mut self, address: usize) -> Result<Cached, MachineError> {
+        let (instruction, size) = self.fetch(address)?;
+        let nregs = self.registers.len();
+        let valid = instruction
+            .registers()
+            .iter()
+            .all(|&r| (r as usize) < nregs);
+        let op = match instruction {
+            _ if !valid => Op::Other(instruction),
+            Instruction::MoveIf(a, b, c) => Op::MoveIf(a as usize, b as usize, c as usize),
+            Instruction::Store(a, b) => Op::Store(a as usize, b as usize),
+            Instruction::Load(a, b) => Op::Load(a as usize, b as usize),
+            Instruction::LoadImm(a, value) => Op::LoadImm(a as usize, value as i32 as u32),
+            Instruction::Sub(a, b, c) => Op::Sub(a as usize, b as usize, c as usize),
+            _ => Op::Other(instruction),
+        };
+        let cached = Cached {
+            op,
+            next: (address + size) as u32,
+            opcode: instruction.opcode(),
+        };
+        self.cache[address] = Some(cached);
+        Ok(cached)
+    }
+
+    /// Write a register known to exist.
+    #[inline]
+    fn write_reg(&mut self, reg: usize, value: u32) {
+        self.history
+            .record(Change::Register(reg, self.registers[reg]));
+        self.registers[reg] = value;
+    }
+}
@@ -0,0 +1,401 @@
+//! Server for the GDB remote serial protocol, to debug programs with a
+//! standard debugger front-end.
+//!
+//! The server is built on top of the [Debugger] and talks to a single
+//! client over any pair of streams, typically a TCP connection or the
+//! standard input and output of a process started by the client
+//! (`target remote | tp-rust-2 gdb prog.bin`). It supports:
+//!
+//! - reading and writing the registers (`g`, `G`, `p`, `P`), sent as 32-bit
+//!   little-endian values, IP being register 0;
+//! - reading and writing the memory (`m`, `M`);
+//! - single-stepping and continuing (`s`, `c`);
+//! - software and hardware breakpoints (`Z0`, `Z1`) and watchpoints
+//!   (`Z2`, `Z3`, `Z4`), the latter being [machine
+//!   watchpoints](Machine::add_watchpoint);
+//! - the no-acknowledgment mode (`QStartNoAckMode`);
+//! - the target description (`qXfer:features:read:target.xml`), listing
+//!   the registers as `r0`, `r1`, etc.
+//!
+//! GDB knows no architecture for this machine, so the description names
+//! none: the client has to rely on the register layout it gives, and
+//! cannot disassemble the program (the `dis` subcommand can).
+//!
+//! Other packets get the empty reply meaning that they are not supported.
+//! Continuing cannot be interrupted by the client: a step limit or a
+//! deadline set on the machine stops it instead. A failed instruction is
+//! reported as a signal (e.g. `SIGSEGV` for a memory access out of bounds)
+//! with IP left on the instruction, as in the [Debugger].
+
+use crate::{
+    debugger::{Debugger, Stop},
+    Machine, MachineErrorKind, WatchEvent, Watchpoint,
+};
+use std::io::{self, BufReader, Read, Write};
+
+/// Signals reported in stop replies.
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGABRT: u8 = 6;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
+const SIGXCPU: u8 = 24;
+
+/// Largest packet the client may send, advertised in `qSupported`.
+const PACKET_SIZE: usize = 0x4000;
+
+pub struct GdbServer {
+    debugger: Debugger,
+    no_ack: bool,
+    swbreak: bool,
+    last_stop: String,
+    last_reply: Vec<u8>,
+}
+
+impl GdbServer {
+    /// Create a server controlling `machine`, stopped before its first
+    /// instruction.
+    pub fn new(machine: Machine) -> Self {
+        GdbServer {
+            debugger: Debugger::new(machine),
+            no_ack: false,
+            swbreak: false,
+            last_stop: format!("S{:02x}", SIGTRAP),
+            last_reply: Vec::new(),
+        }
+    }
+
+    /// Reference onto the debugged machine.
+    pub fn machine(&self) -> &Machine {
+        self.debugger.machine()
+    }
+
+    /// Mutable reference onto the debugged machine.
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        self.debugger.machine_mut()
+    }
+
+    /// Answer the packets read from `input` on `output` until the client
+    /// kills the program, detaches or closes the connection. The program
+    /// output is written on `fd`.
+    pub fn serve<R: Read, W: Write, T: Write>(
+        &mut self,
+        input: R,
+        output: &mut W,
+        fd: &mut T,
+    ) -> io::Result<()> {
+        let mut input = BufReader::new(input).bytes();
+        while let Some(packet) = self.read_packet(&mut input, output)? {
+            match packet.as_str() {
+                "k" => break,
+                "D" => return self.send(output, "OK"),
+                _ => {
+                    let reply = self.reply(&packet, fd);
+                    self.send(output, &reply)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the next packet, acknowledging it unless acknowledgments were
+    /// disabled. Returns `None` at the end of the input.
+    fn read_packet<I, W>(&mut self, input: &mut I, output: &mut W) -> io::Result<Option<String>>
+    where
+        I: Iterator<Item = io::Result<u8>>,
+        W: Write,
+    {
+        loop {
+            // Skip acknowledgments and interrupt requests until a packet
+            // starts, sending the last reply again if the client asks to
+            match input.next().transpose()? {
+                None => return Ok(None),
+                Some(b'$') => (),
+                Some(b'-') if !self.no_ack => {
+                    output.write_all(&self.last_reply)?;
+                    output.flush()?;
+                    continue;
+                }
+                Some(_) => continue,
+            }
+            let mut data = Vec::new();
+            loop {
+                match input.next().transpose()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut checksum = [0; 2];
+            for digit in &mut checksum {
+                match input.next().transpose()? {
+                    None => return Ok(None),
+                    Some(byte) => *digit = byte,
+                }
+            }
+            if !self.no_ack {
+                let valid = std::str::from_utf8(&checksum)
+                    .ok()
+                    .and_then(|c| u8::from_str_radix(c, 16).ok())
+                    == Some(checksum_of(&data));
+                output.write_all(if valid { b"+" } else { b"-" })?;
+                output.flush()?;
+                if !valid {
+                    continue;
+                }
+            }
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    /// Send `reply` as a packet, escaping the characters reserved by the
+    /// protocol.
+    fn send<W: Write>(&mut self, output: &mut W, reply: &str) -> io::Result<()> {
+        let mut data = Vec::with_capacity(reply.len());
+        for byte in reply.bytes() {
+            match byte {
+                b'$' | b'#' | b'}' | b'*' => data.extend([b'}', byte ^ 0x20]),
+                _ => data.push(byte),
+            }
+        }
+        let mut packet = vec![b'$'];
+        packet.extend(&data);
+        packet.extend(format!("#{:02x}", checksum_of(&data)).bytes());
+        output.write_all(&packet)?;
+        output.flush()?;
+        self.last_reply = packet;
+        Ok(())
+    }
+
+    /// Execute the command of `packet` and return the reply to send.
+    fn reply<T: Write>(&mut self, packet: &str, fd: &mut T) -> String {
+        let mut chars = packet.chars();
+        let command = chars.next();
+        let args = chars.as_str();
+        let reply = match command {
+            Some('?') => Some(self.last_stop.clone()),
+            Some('g') => Some(self.read_registers()),
+            Some('G') => self.write_registers(args),
+            Some('p') => self.read_register(args),
+            Some('P') => self.write_register(args),
+            Some('m') => self.read_memory(args),
+            Some('M') => self.write_memory(args),
+            Some(command @ ('s' | 'c')) => self.resume(command, args, fd),
+            Some(command @ ('Z' | 'z')) => self.point(command == 'Z', args),
+            Some('H') => Some("OK".to_string()),
+            Some('q') if args.starts_with("Supported") => {
+                self.swbreak = args.contains("swbreak+");
+                Some(format!(
+                    "PacketSize={:x};QStartNoAckMode+;swbreak+;hwbreak+;qXfer:features:read+",
+                    PACKET_SIZE
+                ))
+            }
+            Some('q') if args == "Attached" => Some("1".to_string()),
+            Some('q') if args.starts_with("Xfer:features:read:") => {
+                self.read_features(&args["Xfer:features:read:".len()..])
+            }
+            Some('Q') if args == "StartNoAckMode" => {
+                // This packet is still acknowledged, the next ones are not
+                self.no_ack = true;
+                Some("OK".to_string())
+            }
+            _ => Some(String::new()),
+        };
+        reply.unwrap_or_else(|| "E01".to_string())
+    }
+
+    fn read_registers(&self) -> String {
+        self.machine()
+            .regs()
+            .iter()
+            .map(|value| encode_hex(&value.to_le_bytes()))
+            .collect()
+    }
+
+    fn write_registers(&mut self, args: &str) -> Option<String> {
+        let bytes = decode_hex(args)?;
+        if bytes.len() != 4 * self.machine().regs().len() {
+            return None;
+        }
+        for (reg, value) in bytes.chunks(4).enumerate() {
+            let value = u32::from_le_bytes(value.try_into().unwrap());
+            self.machine_mut().set_reg(reg, value).ok()?;
+        }
+        Some("OK".to_string())
+    }
+
+    fn read_register(&self, args: &str) -> Option<String> {
+        let reg = usize::from_str_radix(args, 16).ok()?;
+        let value = self.machine().regs().get(reg)?;
+        Some(encode_hex(&value.to_le_bytes()))
+    }
+
+    fn write_register(&mut self, args: &str) -> Option<String> {
+        let (reg, value) = args.split_once('=')?;
+        let reg = usize::from_str_radix(reg, 16).ok()?;
+        let value: [u8; 4] = decode_hex(value)?.try_into().ok()?;
+        self.machine_mut()
+            .set_reg(reg, u32::from_le_bytes(value))
+            .ok()?;
+        Some("OK".to_string())
+    }
+
+    /// Describe the registers to the client, the only annex being
+    /// `target.xml`.
+    fn read_features(&self, args: &str) -> Option<String> {
+        let (annex, range) = args.split_once(':')?;
+        if annex != "target.xml" {
+            return None;
+        }
+        let (offset, len) = parse_range(range)?;
+        let mut xml = String::from(concat!(
+            r#"<?xml version="1.0"?>"#,
+            r#"<!DOCTYPE target SYSTEM "gdb-target.dtd">"#,
+            r#"<target version="1.0"><feature name="org.tp-rust-2.core">"#,
+        ));
+        for reg in 0..self.machine().regs().len() {
+            let kind = if reg == 0 { "code_ptr" } else { "uint32" };
+            xml += &format!(r#"<reg name="r{}" bitsize="32" type="{}"/>"#, reg, kind);
+        }
+        xml += "</feature></target>";
+        // `m` means that more data follows, `l` that this is the last part
+        let part = &xml[offset.min(xml.len())..];
+        Some(if part.len() > len {
+            format!("m{}", &part[..len])
+        } else {
+            format!("l{}", part)
+        })
+    }
+
+    /// Read memory, the reply being shorter than requested when the range
+    /// goes past the end of the memory.
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (address, len) = parse_range(args)?;
+        let memory = self.machine().memory();
+        let bytes = memory.get(address..)?;
+        if len > 0 && bytes.is_empty() {
+            return None;
+        }
+        Some(encode_hex(&bytes[..len.min(bytes.len())]))
+    }
+
+    fn write_memory(&mut self, args: &str) -> Option<String> {
+        let (range, data) = args.split_once(':')?;
+        let (address, len) = parse_range(range)?;
+        let bytes = decode_hex(data)?;
+        if bytes.len() != len {
+            return None;
+        }
+        self.machine_mut().set_mem(address, &bytes).ok()?;
+        Some("OK".to_string())
+    }
+
+    /// Step or continue, from the address given in `args` if any.
+    fn resume<T: Write>(&mut self, command: char, args: &str, fd: &mut T) -> Option<String> {
+        if !args.is_empty() {
+            let address = u32::from_str_radix(args, 16).ok()?;
+            self.machine_mut().set_reg(0, address).ok()?;
+        }
+        let stop = match command {
+            's' => self.debugger.step_on(fd),
+            _ => self.debugger.continue_on(fd),
+        };
+        self.last_stop = self.stop_reply(&stop);
+        Some(self.last_stop.clone())
+    }
+
+    fn stop_reply(&self, stop: &Stop) -> String {
+        let signal = match stop {
+            Stop::Step => SIGTRAP,
+            Stop::Breakpoint(_) if self.swbreak => return format!("T{:02x}swbreak:;", SIGTRAP),
+            Stop::Breakpoint(_) => SIGTRAP,
+            Stop::Watchpoint(_, hit) => {
+                let kind = match hit.watchpoint {
+                    Watchpoint::Write { .. } => "watch",
+                    Watchpoint::Read { .. } => "rwatch",
+                    Watchpoint::Access { .. } => "awatch",
+                    Watchpoint::Register(_) => "",
+                };
+                match hit.event {
+                    WatchEvent::Read { address, .. } | WatchEvent::Write { address, .. } => {
+                        return format!("T{:02x}{}:{:x};", SIGTRAP, kind, address)
+                    }
+                    // Register changes have no stop reason in the protocol
+                    WatchEvent::Register { .. } => SIGTRAP,
+                }
+            }
+            // Only the low byte of the status fits in the reply
+            Stop::Exit => return format!("W{:02x}", self.debugger.machine().exit_status() & 0xff),
+            Stop::Error(_, e) => {
+                match e.kind {
+                    MachineErrorKind::RegisterOutOfBounds(_)
+                    | MachineErrorKind::InvalidOpcode(_) => SIGILL,
+                    MachineErrorKind::MemoryIndexOutOfBounds(_)
+                    | MachineErrorKind::ProtectionFault { .. } => SIGSEGV,
+                    MachineErrorKind::DivisionByZero => SIGFPE,
+                    MachineErrorKind::StepLimitExceeded(_)
+                    | MachineErrorKind::DeadlineExceeded(_) => SIGXCPU,
+                    _ => SIGABRT,
+                }
+            }
+        };
+        format!("S{:02x}", signal)
+    }
+
+    /// Insert or remove a breakpoint or a watchpoint.
+    fn point(&mut self, insert: bool, args: &str) -> Option<String> {
+        let mut fields = args.split(',');
+        let kind = fields.next()?;
+        let start = usize::from_str_radix(fields.next()?, 16).ok()?;
+        let len = usize::from_str_radix(fields.next()?, 16).ok()?;
+        let end = start.saturating_add(len);
+        let watchpoint = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.debugger.add_breakpoint(start);
+                } else {
+                    self.debugger.remove_breakpoint(start);
+                }
+                return Some("OK".to_string());
+            }
+            "2" => Watchpoint::Write { start, end },
+            "3" => Watchpoint::Read { start, end },
+            "4" => Watchpoint::Access { start, end },
+            _ => return Some(String::new()),
+        };
+        if insert {
+            self.machine_mut().add_watchpoint(watchpoint);
+        } else {
+            self.machine_mut().remove_watchpoint(watchpoint);
+        }
+        Some("OK".to_string())
+    }
+}
+
+/// Modulo 256 sum of the bytes of a packet.
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Parse an `address,length` pair.
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (address, len) = text.split_once(',')?;
+    Some((
+        usize::from_str_radix(address, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
@@ -0,0 +1,92 @@
+//! Undo journal used to execute a program backwards.
+//!
+//! While a step executes, the previous value of every register and memory
+//! word it overwrites, and of the exit status, is recorded. Undoing the
+//! step writes those values back in reverse order. Only the last `limit`
+//! steps are kept.
+
+use std::collections::VecDeque;
+
+/// A value overwritten by an instruction.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Change {
+    /// Register and its previous value.
+    Register(usize, u32),
+    /// Address and previous content of a memory word.
+    Memory(usize, [u8; 4]),
+    /// Address and previous content of a byte.
+    Byte(usize, u8),
+    /// Previous exit status, replaced by an exit instruction.
+    ExitStatus(u32),
+}
+
+/// Changes made by one step.
+#[derive(Debug, Default)]
+pub(crate) struct Entry {
+    /// Number of steps executed before this one.
+    pub(crate) steps: u64,
+    pub(crate) changes: Vec<Change>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct History {
+    limit: usize,
+    entries: VecDeque<Entry>,
+    /// Entry of the step being executed, if recording is enabled.
+    current: Option<Entry>,
+}
+
+impl History {
+    pub(crate) fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Keep at most `limit` steps, dropping the oldest ones if needed.
+    pub(crate) fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        while self.entries.len() > limit {
+            self.entries.pop_front();
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Start recording the changes of a step.
+    pub(crate) fn begin(&mut self, steps: u64) {
+        if self.limit > 0 {
+            self.current = Some(Entry {
+                steps,
+                changes: Vec::new(),
+            });
+        }
+    }
+
+    /// Record a change if a step is being recorded.
+    pub(crate) fn record(&mut self, change: Change) {
+        if let Some(entry) = &mut self.current {
+            entry.changes.push(change);
+        }
+    }
+
+    /// Keep the changes of the step which just completed.
+    pub(crate) fn commit(&mut self) {
+        if let Some(entry) = self.current.take() {
+            if self.entries.len() == self.limit {
+                self.entries.pop_front();
+            }
+            self.entries.push_back(entry);
+        }
+    }
+
+    /// Forget the changes of a step which failed.
+    pub(crate) fn abort(&mut self) {
+        self.current = None;
+    }
+
+    /// Remove the most recent step.
+    pub(crate) fn pop(&mut self) -> Option<Entry> {
+        self.entries.pop_back()
+    }
+}
@@ -0,0 +1,371 @@
+use crate::{MachineError, MachineErrorKind};
+use std::fmt;
+
+/// Instruction set understood by a machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Isa {
+    /// Opcodes 1 to 10.
+    #[default]
+    Base,
+    /// Opcodes 1 to 31: the base set plus arithmetic, logic, comparison,
+    /// relative branches, byte accesses, Unicode output, unsigned,
+    /// hexadecimal and binary number output, and exit with a status.
+    Extended,
+}
+
+impl Isa {
+    /// Whether `opcode` belongs to this instruction set.
+    pub fn supports(self, opcode: u8) -> bool {
+        match self {
+            Isa::Base => (1..=10).contains(&opcode),
+            Isa::Extended => (1..=31).contains(&opcode),
+        }
+    }
+}
+
+/// One instruction of the machine. Register operands are kept as the raw
+/// bytes found in memory; their validity is checked upon execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// `move rA <- rB if rC != 0` (opcode 1)
+    MoveIf(u8, u8, u8),
+    /// `store [rA] <- rB` (opcode 2)
+    Store(u8, u8),
+    /// `load rA <- [rB]` (opcode 3)
+    Load(u8, u8),
+    /// `loadimm rA <- #value` (opcode 4)
+    LoadImm(u8, i16),
+    /// `sub rA <- rB - rC` (opcode 5)
+    Sub(u8, u8, u8),
+    /// `out rA` (opcode 6)
+    Out(u8),
+    /// `exit` (opcode 7)
+    Exit,
+    /// `out_number rA` (opcode 8)
+    OutNumber(u8),
+    /// `in rA, rB` (opcode 9), rB being set to 1 at end of input
+    In(u8, u8),
+    /// `in_number rA, rB` (opcode 10), rB being set to 1 at end of input
+    InNumber(u8, u8),
+    /// `add rA <- rB + rC` (opcode 11, extended)
+    Add(u8, u8, u8),
+    /// `mul rA <- rB * rC` (opcode 12, extended)
+    Mul(u8, u8, u8),
+    /// `div rA <- rB / rC` (opcode 13, extended), signed
+    Div(u8, u8, u8),
+    /// `mod rA <- rB % rC` (opcode 14, extended), signed
+    Mod(u8, u8, u8),
+    /// `and rA <- rB & rC` (opcode 15, extended)
+    And(u8, u8, u8),
+    /// `or rA <- rB | rC` (opcode 16, extended)
+    Or(u8, u8, u8),
+    /// `xor rA <- rB ^ rC` (opcode 17, extended)
+    Xor(u8, u8, u8),
+    /// `shl rA <- rB << rC` (opcode 18, extended)
+    Shl(u8, u8, u8),
+    /// `shr rA <- rB >> rC` (opcode 19, extended), logical
+    Shr(u8, u8, u8),
+    /// `sar rA <- rB >> rC` (opcode 20, extended), arithmetic
+    Sar(u8, u8, u8),
+    /// `cmp rA <- rB <=> rC` (opcode 21, extended): -1, 0 or 1 depending
+    /// on the signed comparison of rB and rC
+    Cmp(u8, u8, u8),
+    /// `jmp #offset` (opcode 22, extended), relative to the next instruction
+    Jmp(i16),
+    /// `jnz rA, #offset` (opcode 23, extended), taken if rA != 0
+    Jnz(u8, i16),
+    /// `jz rA, #offset` (opcode 24, extended), taken if rA == 0
+    Jz(u8, i16),
+    /// `loadb rA <- [rB]` (opcode 25, extended), zero-extended
+    LoadByte(u8, u8),
+    /// `storeb [rA] <- rB` (opcode 26, extended), low 8 bits of rB
+    StoreByte(u8, u8),
+    /// `out_char rA` (opcode 27, extended), Unicode scalar value of rA
+    /// encoded in UTF-8
+    OutChar(u8),
+    /// `out_unsigned rA` (opcode 28, extended), in decimal
+    OutUnsigned(u8),
+    /// `out_hex rA` (opcode 29, extended), in lowercase hexadecimal without
+    /// prefix
+    OutHex(u8),
+    /// `out_binary rA` (opcode 30, extended), in binary without prefix
+    OutBinary(u8),
+    /// `exit rA` (opcode 31, extended), with rA as exit status
+    ExitWith(u8),
+}
+
+impl Instruction {
+    /// Decode the base instruction located at the beginning of `bytes`.
+    ///
+    /// Returns the instruction and its size in bytes. An unknown opcode
+    /// gives [InvalidOpcode](MachineErrorKind::InvalidOpcode), and an
+    /// instruction which does not fit in `bytes` gives
+    /// [MemoryIndexOutOfBounds](MachineErrorKind::MemoryIndexOutOfBounds)
+    /// with the offset of the first missing byte.
+    pub fn decode(bytes: &[u8]) -> Result<(Instruction, usize), MachineError> {
+        Self::decode_for(bytes, Isa::Base)
+    }
+
+    /// Similar to [decode](Instruction::decode), accepting the opcodes of
+    /// `isa`.
+    pub fn decode_for(bytes: &[u8], isa: Isa) -> Result<(Instruction, usize), MachineError> {
+        let opcode = *bytes
+            .first()
+            .ok_or(MachineErrorKind::MemoryIndexOutOfBounds(0))?;
+        let size = Self::size_of(opcode)
+            .filter(|_| isa.supports(opcode))
+            .ok_or(MachineErrorKind::InvalidOpcode(opcode))?;
+        let b = bytes
+            .get(..size)
+            .ok_or(MachineErrorKind::MemoryIndexOutOfBounds(bytes.len()))?;
+        let instruction = match opcode {
+            1 => Instruction::MoveIf(b[1], b[2], b[3]),
+            2 => Instruction::Store(b[1], b[2]),
+            3 => Instruction::Load(b[1], b[2]),
+            4 => Instruction::LoadImm(b[1], i16::from_le_bytes([b[2], b[3]])),
+            5 => Instruction::Sub(b[1], b[2], b[3]),
+            6 => Instruction::Out(b[1]),
+            7 => Instruction::Exit,
+            8 => Instruction::OutNumber(b[1]),
+            9 => Instruction::In(b[1], b[2]),
+            10 => Instruction::InNumber(b[1], b[2]),
+            11 => Instruction::Add(b[1], b[2], b[3]),
+            12 => Instruction::Mul(b[1], b[2], b[3]),
+            13 => Instruction::Div(b[1], b[2], b[3]),
+            14 => Instruction::Mod(b[1], b[2], b[3]),
+            15 => Instruction::And(b[1], b[2], b[3]),
+            16 => Instruction::Or(b[1], b[2], b[3]),
+            17 => Instruction::Xor(b[1], b[2], b[3]),
+            18 => Instruction::Shl(b[1], b[2], b[3]),
+            19 => Instruction::Shr(b[1], b[2], b[3]),
+            20 => Instruction::Sar(b[1], b[2], b[3]),
+            21 => Instruction::Cmp(b[1], b[2], b[3]),
+            22 => Instruction::Jmp(i16::from_le_bytes([b[1], b[2]])),
+            23 => Instruction::Jnz(b[1], i16::from_le_bytes([b[2], b[3]])),
+            24 => Instruction::Jz(b[1], i16::from_le_bytes([b[2], b[3]])),
+            25 => Instruction::LoadByte(b[1], b[2]),
+            26 => Instruction::StoreByte(b[1], b[2]),
+            27 => Instruction::OutChar(b[1]),
+            28 => Instruction::OutUnsigned(b[1]),
+            29 => Instruction::OutHex(b[1]),
+            30 => Instruction::OutBinary(b[1]),
+            31 => Instruction::ExitWith(b[1]),
+            _ => return Err(MachineErrorKind::InvalidOpcode(opcode).into()),
+        };
+        Ok((instruction, size))
+    }
+
+    /// Encode the instruction into its binary form.
+    pub fn encode(&self) -> Vec<u8> {
+        let opcode = self.opcode();
+        match *self {
+            Instruction::MoveIf(a, b, c) => vec![1, a, b, c],
+            Instruction::Store(a, b) => vec![2, a, b],
+            Instruction::Load(a, b) => vec![3, a, b],
+            Instruction::LoadImm(a, value) => {
+                let [l, h] = value.to_le_bytes();
+                vec![4, a, l, h]
+            }
+            Instruction::Sub(a, b, c) => vec![5, a, b, c],
+            Instruction::Out(a) => vec![6, a],
+            Instruction::Exit => vec![7],
+            Instruction::OutNumber(a) => vec![8, a],
+            Instruction::In(a, b) => vec![9, a, b],
+            Instruction::InNumber(a, b) => vec![10, a, b],
+            Instruction::Add(a, b, c)
+            | Instruction::Mul(a, b, c)
+            | Instruction::Div(a, b, c)
+            | Instruction::Mod(a, b, c)
+            | Instruction::And(a, b, c)
+            | Instruction::Or(a, b, c)
+            | Instruction::Xor(a, b, c)
+            | Instruction::Shl(a, b, c)
+            | Instruction::Shr(a, b, c)
+            | Instruction::Sar(a, b, c)
+            | Instruction::Cmp(a, b, c) => vec![opcode, a, b, c],
+            Instruction::Jmp(offset) => {
+                let [l, h] = offset.to_le_bytes();
+                vec![22, l, h]
+            }
+            Instruction::Jnz(a, offset) | Instruction::Jz(a, offset) => {
+                let [l, h] = offset.to_le_bytes();
+                vec![opcode, a, l, h]
+            }
+            Instruction::LoadByte(a, b) => vec![25, a, b],
+            Instruction::StoreByte(a, b) => vec![26, a, b],
+            Instruction::OutChar(a) => vec![27, a],
+            Instruction::OutUnsigned(a) => vec![28, a],
+            Instruction::OutHex(a) => vec![29, a],
+            Instruction::OutBinary(a) => vec![30, a],
+            Instruction::ExitWith(a) => vec![31, a],
+        }
+    }
+
+    /// Opcode of the instruction.
+    pub fn opcode(&self) -> u8 {
+        match self {
+            Instruction::MoveIf(..) => 1,
+            Instruction::Store(..) => 2,
+            Instruction::Load(..) => 3,
+            Instruction::LoadImm(..) => 4,
+            Instruction::Sub(..) => 5,
+            Instruction::Out(_) => 6,
+            Instruction::Exit => 7,
+            Instruction::OutNumber(_) => 8,
+            Instruction::In(..) => 9,
+            Instruction::InNumber(..) => 10,
+            Instruction::Add(..) => 11,
+            Instruction::Mul(..) => 12,
+            Instruction::Div(..) => 13,
+            Instruction::Mod(..) => 14,
+            Instruction::And(..) => 15,
+            Instruction::Or(..) => 16,
+            Instruction::Xor(..) => 17,
+            Instruction::Shl(..) => 18,
+            Instruction::Shr(..) => 19,
+            Instruction::Sar(..) => 20,
+            Instruction::Cmp(..) => 21,
+            Instruction::Jmp(_) => 22,
+            Instruction::Jnz(..) => 23,
+            Instruction::Jz(..) => 24,
+            Instruction::LoadByte(..) => 25,
+            Instruction::StoreByte(..) => 26,
+            Instruction::OutChar(_) => 27,
+            Instruction::OutUnsigned(_) => 28,
+            Instruction::OutHex(_) => 29,
+            Instruction::OutBinary(_) => 30,
+            Instruction::ExitWith(_) => 31,
+        }
+    }
+
+    /// Size in bytes of the encoded instruction.
+    pub fn size(&self) -> usize {
+        Self::size_of(self.opcode()).unwrap()
+    }
+
+    /// Size in bytes of the instructions using `opcode`, if it is valid in
+    /// any instruction set.
+    pub fn size_of(opcode: u8) -> Option<usize> {
+        match opcode {
+            1 | 4 | 5 | 11..=21 | 23 | 24 => Some(4),
+            2 | 3 | 9 | 10 | 22 | 25 | 26 => Some(3),
+            6 | 8 | 27..=31 => Some(2),
+            7 => Some(1),
+            _ => None,
+        }
+    }
+
+    /// Mnemonic of the instructions using `opcode`, if it is valid in any
+    /// instruction set.
+    pub fn mnemonic_of(opcode: u8) -> Option<&'static str> {
+        let mnemonic = match opcode {
+            1 => "move",
+            2 => "store",
+            3 => "load",
+            4 => "loadimm",
+            5 => "sub",
+            6 => "out",
+            7 | 31 => "exit",
+            8 => "out_number",
+            9 => "in",
+            10 => "in_number",
+            11 => "add",
+            12 => "mul",
+            13 => "div",
+            14 => "mod",
+            15 => "and",
+            16 => "or",
+            17 => "xor",
+            18 => "shl",
+            19 => "shr",
+            20 => "sar",
+            21 => "cmp",
+            22 => "jmp",
+            23 => "jnz",
+            24 => "jz",
+            25 => "loadb",
+            26 => "storeb",
+            27 => "out_char",
+            28 => "out_unsigned",
+            29 => "out_hex",
+            30 => "out_binary",
+            _ => return None,
+        };
+        Some(mnemonic)
+    }
+
+    /// Registers used by the instruction, either as source or destination.
+    pub fn registers(&self) -> Vec<u8> {
+        match *self {
+            Instruction::MoveIf(a, b, c)
+            | Instruction::Sub(a, b, c)
+            | Instruction::Add(a, b, c)
+            | Instruction::Mul(a, b, c)
+            | Instruction::Div(a, b, c)
+            | Instruction::Mod(a, b, c)
+            | Instruction::And(a, b, c)
+            | Instruction::Or(a, b, c)
+            | Instruction::Xor(a, b, c)
+            | Instruction::Shl(a, b, c)
+            | Instruction::Shr(a, b, c)
+            | Instruction::Sar(a, b, c)
+            | Instruction::Cmp(a, b, c) => vec![a, b, c],
+            Instruction::Store(a, b)
+            | Instruction::Load(a, b)
+            | Instruction::In(a, b)
+            | Instruction::InNumber(a, b)
+            | Instruction::LoadByte(a, b)
+            | Instruction::StoreByte(a, b) => vec![a, b],
+            Instruction::LoadImm(a, _)
+            | Instruction::Out(a)
+            | Instruction::OutNumber(a)
+            | Instruction::OutChar(a)
+            | Instruction::OutUnsigned(a)
+            | Instruction::OutHex(a)
+            | Instruction::OutBinary(a)
+            | Instruction::ExitWith(a)
+            | Instruction::Jnz(a, _)
+            | Instruction::Jz(a, _) => vec![a],
+            Instruction::Exit | Instruction::Jmp(_) => vec![],
+        }
+    }
+}
+
+/// Textual form of the instruction, as found in the `.dis` listings.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::MoveIf(a, b, c) => write!(f, "move r{} <- r{} if r{} != 0", a, b, c),
+            Instruction::Store(a, b) => write!(f, "store [r{}] <- r{}", a, b),
+            Instruction::Load(a, b) => write!(f, "load r{} <- [r{}]", a, b),
+            Instruction::LoadImm(a, value) => write!(f, "loadimm r{} <- #{}", a, value),
+            Instruction::Sub(a, b, c) => write!(f, "sub r{} <- r{} - r{}", a, b, c),
+            Instruction::Out(a) => write!(f, "out r{}", a),
+            Instruction::Exit => write!(f, "exit"),
+            Instruction::OutNumber(a) => write!(f, "out_number r{}", a),
+            Instruction::In(a, b) => write!(f, "in r{}, r{}", a, b),
+            Instruction::InNumber(a, b) => write!(f, "in_number r{}, r{}", a, b),
+            Instruction::Add(a, b, c) => write!(f, "add r{} <- r{} + r{}", a, b, c),
+            Instruction::Mul(a, b, c) => write!(f, "mul r{} <- r{} * r{}", a, b, c),
+            Instruction::Div(a, b, c) => write!(f, "div r{} <- r{} / r{}", a, b, c),
+            Instruction::Mod(a, b, c) => write!(f, "mod r{} <- r{} % r{}", a, b, c),
+            Instruction::And(a, b, c) => write!(f, "and r{} <- r{} & r{}", a, b, c),
+            Instruction::Or(a, b, c) => write!(f, "or r{} <- r{} | r{}", a, b, c),
+            Instruction::Xor(a, b, c) => write!(f, "xor r{} <- r{} ^ r{}", a, b, c),
+            Instruction::Shl(a, b, c) => write!(f, "shl r{} <- r{} << r{}", a, b, c),
+            Instruction::Shr(a, b, c) => write!(f, "shr r{} <- r{} >> r{}", a, b, c),
+            Instruction::Sar(a, b, c) => write!(f, "sar r{} <- r{} >> r{}", a, b, c),
+            Instruction::Cmp(a, b, c) => write!(f, "cmp r{} <- r{} <=> r{}", a, b, c),
+            Instruction::Jmp(offset) => write!(f, "jmp #{}", offset),
+            Instruction::Jnz(a, offset) => write!(f, "jnz r{}, #{}", a, offset),
+            Instruction::Jz(a, offset) => write!(f, "jz r{}, #{}", a, offset),
+            Instruction::LoadByte(a, b) => write!(f, "loadb r{} <- [r{}]", a, b),
+            Instruction::StoreByte(a, b) => write!(f, "storeb [r{}] <- r{}", a, b),
+            Instruction::OutChar(a) => write!(f, "out_char r{}", a),
+            Instruction::OutUnsigned(a) => write!(f, "out_unsigned r{}", a),
+            Instruction::OutHex(a) => write!(f, "out_hex r{}", a),
+            Instruction::OutBinary(a) => write!(f, "out_binary r{}", a),
+            Instruction::ExitWith(a) => write!(f, "exit r{}", a),
+        }
+    }
+}
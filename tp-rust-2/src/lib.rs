@@ -1,3 +1,27 @@
+pub mod analysis;
+pub mod assembler;
+mod builder;
+pub mod compiler;
+pub mod coverage;
+pub mod debugger;
+pub mod device;
+pub mod disassembler;
+mod error;
+mod fast;
+pub mod gdb;
+mod history;
+mod instruction;
 mod machine;
+pub mod profiler;
+mod protection;
+mod snapshot;
+mod trace;
+mod watch;
 
+pub use builder::*;
+pub use error::*;
+pub use instruction::*;
 pub use machine::*;
+pub use protection::*;
+pub use snapshot::*;
+pub use watch::*;
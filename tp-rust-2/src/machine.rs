@@ -1,12 +1,35 @@
-use std::{io::{self, Write, Stdout}, num::Wrapping};
+use crate::{
+    coverage::Coverage,
+    device::Mapping,
+    fast::Cached,
+    history::{Change, History},
+    Access, Instruction, Isa, MachineError, MachineErrorKind, Region, WatchHit, Watchpoint,
+};
+use std::{io::{self, Read, Write}, num::Wrapping, time::Instant};
 
 
 
 
-const MEMORY_SIZE: usize = 4096;
-const NREGS: usize = 16;
+/// Default memory size and number of registers, used by [Machine::new].
+pub const MEMORY_SIZE: usize = 4096;
+pub const NREGS: usize = 16;
 
-const IP: usize = 0;
+pub(crate) const IP: usize = 0;
+
+/// How `out` writes the low 8 bits of its register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    /// As the Latin-1 character with this code, encoded in UTF-8, so that
+    /// 0xE9 is written as the two bytes of `é`.
+    #[default]
+    Latin1,
+    /// As a single byte, unchanged.
+    Raw,
+}
+
+/// The deadline is only checked every so many steps, as reading the
+/// clock is much slower than executing an instruction.
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
 
 
 pub struct Machine {
@@ -14,29 +37,56 @@ pub struct Machine {
 
     // memory block
     // little endian
-    memory: [u8; MEMORY_SIZE],
+    pub(crate) memory: Vec<u8>,
 
     // registers block
     // big endian
-    registers: [u32; NREGS],
+    pub(crate) registers: Vec<u32>,
 
-}
+    // number of instructions executed so far
+    pub(crate) steps: u64,
 
-#[derive(Debug)]
-pub enum MachineError {
-    // Entries to represent errors!
-    RegisterOutOfBounds,
-    MemoryIndexOutOfBounds,
-    InvalidOpcode,
-    NumberConversionToCharNotValid,
-    WriteToBufferFailed,
-}
+    // instruction set accepted by the decoder
+    isa: Isa,
+
+    // encoding of the characters written by `out`
+    output_mode: OutputMode,
+
+    // execution budget, checked before each instruction
+    step_limit: Option<u64>,
+    deadline: Option<Instant>,
+
+    // undo journal of the last steps
+    pub(crate) history: History,
+
+    // devices mapped onto the address space
+    pub(crate) devices: Vec<Mapping>,
+
+    // instructions pre-decoded by the fast engine, indexed by address
+    pub(crate) cache: Vec<Option<Cached>>,
+
+    // executions per address, when coverage is enabled
+    pub(crate) coverage: Option<Coverage>,
+
+    // protected regions of the address space
+    pub(crate) regions: Vec<Region>,
 
+    // watchpoints, and the one triggered by the current instruction
+    pub(crate) watchpoints: Vec<Watchpoint>,
+    pub(crate) watch_hit: Option<WatchHit>,
+
+    // status given by the last exit instruction
+    exit_status: u32,
+
+}
 
 impl Machine {
     /// Create a new machine in its reset state. The `memory` parameter will
     /// be copied at the beginning of the machine memory.
     ///
+    /// The machine has [MEMORY_SIZE] bytes of memory and [NREGS] registers,
+    /// use [MachineBuilder](crate::MachineBuilder) for other configurations.
+    ///
     /// # Panics
     /// This function panics when `memory` is larger than the machine memory.
     pub fn new(memory: &[u8]) -> Self {
@@ -47,7 +97,7 @@ impl Machine {
 
             _ =>
             {
-                let mut new_machine: Machine = { Machine {memory: [0;MEMORY_SIZE], registers: [0;NREGS] } };
+                let mut new_machine = Machine::with_geometry(MEMORY_SIZE, NREGS);
                 new_machine.memory[0..mem_size].copy_from_slice(memory);
                 new_machine
             }
@@ -55,17 +105,120 @@ impl Machine {
 
     }
 
+    /// Create a machine with all memory and registers set to 0.
+    pub(crate) fn with_geometry(memory_size: usize, nregs: usize) -> Self {
+        Machine {
+            memory: vec![0; memory_size],
+            registers: vec![0; nregs],
+            steps: 0,
+            isa: Isa::Base,
+            output_mode: OutputMode::Latin1,
+            step_limit: None,
+            deadline: None,
+            history: History::default(),
+            devices: Vec::new(),
+            cache: Vec::new(),
+            coverage: None,
+            regions: Vec::new(),
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            exit_status: 0,
+        }
+    }
+
     /// Run until the program terminates or until an error happens.
     /// If output instructions are run, they print on `fd`.
-    pub fn run_on<T: Write>(&mut self, fd: &mut T) -> Result<(), MachineError> {
-        while !self.step_on(fd)? {}
-        Ok(())
+    /// Input instructions see the end of input.
+    ///
+    /// In case of success, the exit status of the program is returned.
+    pub fn run_on<T: Write>(&mut self, fd: &mut T) -> Result<u32, MachineError> {
+        self.run_with_io(&mut io::empty(), fd)
     }
 
     /// Run until the program terminates or until an error happens.
+    /// If input instructions are run, they read from `input`.
+    /// If output instructions are run, they print on `fd`.
+    pub fn run_with_io<R: Read, W: Write>(&mut self, input: &mut R, fd: &mut W) -> Result<u32, MachineError> {
+        while !self.step_with_io(input, fd)? {}
+        Ok(self.exit_status)
+    }
+
+    /// Run until the program terminates or until an error happens.
+    /// If input instructions are run, they read from standard input.
     /// If output instructions are run, they print on standard output.
-    pub fn run(&mut self) -> Result<(), MachineError> {
-        self.run_on(&mut io::stdout().lock())
+    ///
+    /// In case of success, the exit status of the program is returned: the
+    /// value of the register given to `exit rA`, or 0 for `exit`, the only
+    /// exit instruction of the base instruction set.
+    pub fn run(&mut self) -> Result<u32, MachineError> {
+        self.run_with_io(&mut io::stdin().lock(), &mut io::stdout().lock())
+    }
+
+    /// Similar to [run](Machine::run), but execute at most `max_steps`
+    /// instructions. If the program has not terminated by then,
+    /// [StepLimitExceeded](MachineErrorKind::StepLimitExceeded) is returned.
+    pub fn run_with_limit(&mut self, max_steps: u64) -> Result<u32, MachineError> {
+        self.run_with_limit_on(&mut io::stdout().lock(), max_steps)
+    }
+
+    /// Similar to [run_with_limit](Machine::run_with_limit).
+    /// If output instructions are run, they print on `fd`.
+    pub fn run_with_limit_on<T: Write>(&mut self, fd: &mut T, max_steps: u64) -> Result<u32, MachineError> {
+        let previous = self.step_limit;
+        self.step_limit = Some(self.steps.saturating_add(max_steps));
+        let result = self.run_on(fd);
+        self.step_limit = previous;
+        result
+    }
+
+    /// Similar to [run](Machine::run), but stop when `deadline` is reached.
+    /// If the program has not terminated by then,
+    /// [DeadlineExceeded](MachineErrorKind::DeadlineExceeded) is returned.
+    pub fn run_with_deadline(&mut self, deadline: Instant) -> Result<u32, MachineError> {
+        self.run_with_deadline_on(&mut io::stdout().lock(), deadline)
+    }
+
+    /// Similar to [run_with_deadline](Machine::run_with_deadline).
+    /// If output instructions are run, they print on `fd`.
+    pub fn run_with_deadline_on<T: Write>(&mut self, fd: &mut T, deadline: Instant) -> Result<u32, MachineError> {
+        let previous = self.deadline;
+        self.deadline = Some(deadline);
+        let result = self.run_on(fd);
+        self.deadline = previous;
+        result
+    }
+
+    /// Exit status given by the last exit instruction executed, 0 if
+    /// there is none.
+    pub fn exit_status(&self) -> u32 {
+        self.exit_status
+    }
+
+    /// Reset the exit status, e.g. when restoring a snapshot.
+    pub(crate) fn set_exit_status(&mut self, status: u32) {
+        self.exit_status = status;
+    }
+
+    /// Number of instructions executed so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Reset the number of executed instructions, e.g. when restoring a snapshot.
+    pub(crate) fn set_steps(&mut self, steps: u64) {
+        self.steps = steps;
+    }
+
+    /// Limit the total number of instructions executed by the machine,
+    /// whichever way it is run. `None` removes the limit.
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.step_limit = limit;
+    }
+
+    /// Stop the machine when `deadline` is reached, whichever way it is
+    /// run. `None` removes the deadline.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
     }
 
     /// Execute the next instruction by doing the following steps:
@@ -80,100 +233,126 @@ impl Machine {
     /// In case of success, `true` is returned if the program is
     /// terminated (upon encountering an exit instruction), or
     /// `false` if the execution must continue.
+    ///
+    /// Input instructions see the end of input.
     pub fn step_on<T: Write>(&mut self, fd: &mut T) -> Result<bool, MachineError> {
-        // My implementation, YET TO BE TESTED
+        self.step_with_io(&mut io::empty(), fd)
+    }
+
+    /// Similar to [step_on](Machine::step_on).
+    /// If input instructions are run, they read from `input`.
+    pub fn step_with_io<R: Read, W: Write>(&mut self, input: &mut R, fd: &mut W) -> Result<bool, MachineError> {
+        // Check that the execution budget is not exhausted
+        let address = self.registers[IP] as usize;
+        self.check_budget().map_err(|e| e.at(address, None))?;
+
+        let (instruction, size) = self.fetch(address)?;
+        let opcode = instruction.opcode();
+        let watched = if self.watchpoints.is_empty() { Vec::new() } else { self.watched_registers() };
+        self.history.begin(self.steps);
+        self.set_reg(IP, (address + size) as u32)?;
+        let result = self.execute(input, fd, instruction).map_err(|e| e.at(address, Some(opcode)));
+        if result.is_ok() {
+            self.steps += 1;
+            self.history.commit();
+            if let Some(coverage) = &mut self.coverage {
+                coverage.hit(address);
+            }
+        } else {
+            self.history.abort();
+            self.watch_hit = None;
+        }
+        // The program is not stopped once it has exited
+        if matches!(result, Ok(false)) && !self.watchpoints.is_empty() {
+            self.check_watchpoints(&watched).map_err(|kind| MachineError::from(kind).at(address, Some(opcode)))?;
+        }
+        result
+    }
 
+    /// Decode the instruction at `address`, reporting errors with their
+    /// absolute address.
+    pub(crate) fn fetch(&self, address: usize) -> Result<(Instruction, usize), MachineError> {
         // Check if IP is inside the memory
-        if self.registers[0] < MEMORY_SIZE as u32
-        {
+        if address >= self.memory.len() {
+            return Err(MachineError::from(MachineErrorKind::MemoryIndexOutOfBounds(address)).at(address, None));
+        }
 
+        let opcode = self.memory[address];
+        self.check_access(address, 1, Access::Execute).map_err(|e| e.at(address, Some(opcode)))?;
+        let (instruction, size) = Instruction::decode_for(&self.memory[address..], self.isa).map_err(|e| {
+            let e = match e.kind {
+                // Decoding reports offsets relative to the instruction
+                MachineErrorKind::MemoryIndexOutOfBounds(offset) => {
+                    MachineErrorKind::MemoryIndexOutOfBounds(address + offset).into()
+                }
+                _ => e,
+            };
+            e.at(address, Some(opcode))
+        })?;
+        self.check_access(address, size, Access::Execute).map_err(|e| e.at(address, Some(opcode)))?;
+        Ok((instruction, size))
+    }
 
-            let address: u32 = self.registers[0];
-            let opcode: u8 = self.memory[address as usize];
-
-            match opcode {
-
-                // Opcode move if
-                1 => 
-                {
-                    self.set_reg(0, address + 4)?;
-                    let reg_a = self.memory[(address + 1) as usize] as usize;
-                    let reg_b = self.memory[(address + 2) as usize] as usize;
-                    let reg_c = self.memory[(address + 3) as usize] as usize;
-                    self.move_if(reg_a, reg_b, reg_c)
-                },
-
-                // Opcode store
-                2 =>
-                {
-                    self.set_reg(0, address + 3)?;
-                    let reg_a = self.memory[(address + 1) as usize] as usize;
-                    let reg_b = self.memory[(address + 2) as usize] as usize;
-                    self.store(reg_a,reg_b)
-                },
-                
-                // Opcode load
-                3 =>
-                {
-                    self.set_reg(0, address + 3)?;
-                    let reg_a = self.memory[(address + 1) as usize] as usize;
-                    let reg_b = self.memory[(address + 2) as usize] as usize;
-                    self.load(reg_a,reg_b)
-                },
-
-                // Opcode loadimm
-                4 =>
-                {
-                    self.set_reg(0, address + 4)?;
-                    let reg_a = self.memory[(address + 1) as usize] as usize;
-                    let l: u8 = self.memory[(address + 2) as usize];
-                    let h: u8 = self.memory[(address + 3) as usize];
-                    self.loadimm(reg_a,l,h)
-                },
-
-                // Opcode sub
-                5 =>
-                {
-                    self.set_reg(0, address + 4)?;
-                    let reg_a = self.memory[(address + 1) as usize] as usize;
-                    let reg_b= self.memory[(address + 2) as usize] as usize;
-                    let reg_c = self.memory[(address + 3) as usize] as usize;
-                    self.sub(reg_a,reg_b,reg_c)
-                },
-
-                // Opcode out
-                6 =>
-                {
-                    self.set_reg(0, address + 2)?;
-                    let reg_a = self.memory[(address + 1) as usize] as usize;
-                    self.out(fd, reg_a)
-                },
-
-
-                // Opcode exit
-                7 =>
-                {
-                    self.set_reg(0, address + 1)?;
-                    Ok(true)
-                },
-
-
-                // Opcode out number
-                8 =>
-                {
-                    self.set_reg(0, address + 2)?;
-                    let reg_a = self.memory[(address + 1) as usize] as usize;
-                    self.out_number(fd, reg_a)
-                },
-
-
-
-                _ => Err(MachineError::InvalidOpcode)
+    /// Check the step limit and the deadline.
+    pub(crate) fn check_budget(&self) -> Result<(), MachineError> {
+        if matches!(self.step_limit, Some(limit) if self.steps >= limit) {
+            return Err(MachineErrorKind::StepLimitExceeded(self.steps).into());
+        }
+        if let Some(deadline) = self.deadline {
+            if self.steps.is_multiple_of(DEADLINE_CHECK_INTERVAL) && Instant::now() >= deadline {
+                return Err(MachineErrorKind::DeadlineExceeded(self.steps).into());
             }
         }
-        else
-        {
-            Err(MachineError::MemoryIndexOutOfBounds)
+        Ok(())
+    }
+
+    /// Execute an already decoded instruction. IP must already point
+    /// after the instruction.
+    pub(crate) fn execute<R: Read, W: Write>(&mut self, input: &mut R, fd: &mut W, instruction: Instruction) -> Result<bool, MachineError> {
+        match instruction {
+            Instruction::MoveIf(a, b, c) => self.move_if(a as usize, b as usize, c as usize),
+            Instruction::Store(a, b) => self.store(a as usize, b as usize),
+            Instruction::Load(a, b) => self.load(a as usize, b as usize),
+            Instruction::LoadImm(a, value) => self.loadimm(a as usize, value),
+            Instruction::Sub(a, b, c) => self.sub(a as usize, b as usize, c as usize),
+            Instruction::Out(a) => self.out(fd, a as usize),
+            Instruction::Exit => self.exit(None),
+            Instruction::ExitWith(a) => self.exit(Some(a as usize)),
+            Instruction::OutNumber(a) => self.out_number(fd, a as usize),
+            Instruction::In(a, b) => self.input(input, a as usize, b as usize),
+            Instruction::InNumber(a, b) => self.input_number(input, a as usize, b as usize),
+            Instruction::Add(a, b, c) => self.binary(a as usize, b as usize, c as usize, |b, c| Ok(b.wrapping_add(c))),
+            Instruction::Mul(a, b, c) => self.binary(a as usize, b as usize, c as usize, |b, c| Ok(b.wrapping_mul(c))),
+            Instruction::Div(a, b, c) => self.binary(a as usize, b as usize, c as usize, |b, c| match c {
+                0 => Err(MachineErrorKind::DivisionByZero),
+                _ => Ok((b as i32).wrapping_div(c as i32) as u32),
+            }),
+            Instruction::Mod(a, b, c) => self.binary(a as usize, b as usize, c as usize, |b, c| match c {
+                0 => Err(MachineErrorKind::DivisionByZero),
+                _ => Ok((b as i32).wrapping_rem(c as i32) as u32),
+            }),
+            Instruction::And(a, b, c) => self.binary(a as usize, b as usize, c as usize, |b, c| Ok(b & c)),
+            Instruction::Or(a, b, c) => self.binary(a as usize, b as usize, c as usize, |b, c| Ok(b | c)),
+            Instruction::Xor(a, b, c) => self.binary(a as usize, b as usize, c as usize, |b, c| Ok(b ^ c)),
+            Instruction::Shl(a, b, c) => self.binary(a as usize, b as usize, c as usize, |b, c| Ok(b.checked_shl(c).unwrap_or(0))),
+            Instruction::Shr(a, b, c) => self.binary(a as usize, b as usize, c as usize, |b, c| Ok(b.checked_shr(c).unwrap_or(0))),
+            Instruction::Sar(a, b, c) => self.binary(a as usize, b as usize, c as usize, |b, c| Ok(((b as i32) >> c.min(31)) as u32)),
+            Instruction::Cmp(a, b, c) => self.binary(a as usize, b as usize, c as usize, |b, c| Ok((b as i32).cmp(&(c as i32)) as i32 as u32)),
+            Instruction::Jmp(offset) => self.branch(offset, true),
+            Instruction::Jnz(a, offset) => {
+                self.check_register_in_bounds(a as usize)?;
+                self.branch(offset, self.registers[a as usize] != 0)
+            }
+            Instruction::Jz(a, offset) => {
+                self.check_register_in_bounds(a as usize)?;
+                self.branch(offset, self.registers[a as usize] == 0)
+            }
+            Instruction::LoadByte(a, b) => self.load_byte(a as usize, b as usize),
+            Instruction::StoreByte(a, b) => self.store_byte(a as usize, b as usize),
+            Instruction::OutChar(a) => self.out_char(fd, a as usize),
+            Instruction::OutUnsigned(a) => self.out_unsigned(fd, a as usize, 10),
+            Instruction::OutHex(a) => self.out_unsigned(fd, a as usize, 16),
+            Instruction::OutBinary(a) => self.out_unsigned(fd, a as usize, 2),
         }
     }
 
@@ -190,8 +369,8 @@ impl Machine {
     /// otherwise do nothing.
     fn move_if(&mut self, _reg_a: usize, _reg_b: usize , _reg_c: usize ) -> Result<bool, MachineError>
     {
-        Self::check_register_in_bounds(_reg_b)?;
-        Self::check_register_in_bounds(_reg_c)?;
+        self.check_register_in_bounds(_reg_b)?;
+        self.check_register_in_bounds(_reg_c)?;
         let reg_b = self.registers[_reg_b];
         let reg_c = self.registers[_reg_c];
         if reg_c != 0 {
@@ -203,20 +382,32 @@ impl Machine {
     /// store function
     /// regA regB: store the content of register regB into the memory 
     /// starting at address pointed by register regA using little-endian representation.
-    fn store (&mut self, _reg_a: usize, _reg_b: usize) -> Result<bool, MachineError>
+    /// Stores to a device are forwarded to it and are not journaled.
+    pub(crate) fn store (&mut self, _reg_a: usize, _reg_b: usize) -> Result<bool, MachineError>
     {
-        Self::check_register_in_bounds(_reg_a)?;
-        Self::check_register_in_bounds(_reg_b)?;
+        self.check_register_in_bounds(_reg_a)?;
+        self.check_register_in_bounds(_reg_b)?;
         let reg_a = self.registers[_reg_a] as usize;
+        self.check_access(reg_a, 4, Access::Write)?;
+        if let Some(device) = self.devices.iter_mut().find(|d| d.contains(reg_a)) {
+            device.store(reg_a, self.registers[_reg_b], self.steps)?;
+            return Ok(false);
+        }
         let value: [u8;4] = self.registers[_reg_b].to_le_bytes();
-        if reg_a + 3 < MEMORY_SIZE
+        if reg_a + 3 < self.memory.len()
         {
+            let old = <[u8; 4]>::try_from(&self.memory[reg_a..=reg_a+3]).unwrap();
+            self.history.record(Change::Memory(reg_a, old));
             self.memory[reg_a..=reg_a+3].copy_from_slice(&value[..]);
+            self.invalidate(reg_a, 4);
+            if !self.watchpoints.is_empty() {
+                self.watch_memory(reg_a, 4, true, u32::from_le_bytes(old), self.registers[_reg_b]);
+            }
             Ok(false)
         }
         else
         {
-            Err(MachineError::MemoryIndexOutOfBounds)
+            Err(MachineErrorKind::MemoryIndexOutOfBounds(reg_a).into())
         }
     }
 
@@ -224,21 +415,30 @@ impl Machine {
     /// load function
     /// regA regB: load the 32-bit content from memory at address pointed by register regB 
     /// into register regA using little-endian representation.
-    fn load (&mut self, _reg_a: usize, _reg_b: usize) -> Result<bool, MachineError>
+    pub(crate) fn load (&mut self, _reg_a: usize, _reg_b: usize) -> Result<bool, MachineError>
     {
-        Self::check_register_in_bounds(_reg_a)?;
-        Self::check_register_in_bounds(_reg_b)?;
+        self.check_register_in_bounds(_reg_a)?;
+        self.check_register_in_bounds(_reg_b)?;
         let addr = self.registers[_reg_b] as usize;
-        if addr + 3 < MEMORY_SIZE
+        self.check_access(addr, 4, Access::Read)?;
+        if let Some(device) = self.devices.iter_mut().find(|d| d.contains(addr)) {
+            let value = device.load(addr, self.steps)?;
+            self.set_reg(_reg_a, value)?;
+            return Ok(false);
+        }
+        if addr + 3 < self.memory.len()
         {   
             let reg:[u8;4] = <[u8; 4]>::try_from(&self.memory[addr..=addr+3]).unwrap();
             let value = u32::from_le_bytes(reg);
             self.set_reg(_reg_a, value )?;
+            if !self.watchpoints.is_empty() {
+                self.watch_memory(addr, 4, false, value, value);
+            }
             Ok(false)
         }
         else
         {
-            Err(MachineError::MemoryIndexOutOfBounds)
+            Err(MachineErrorKind::MemoryIndexOutOfBounds(addr).into())
         }
     }
 
@@ -247,9 +447,9 @@ impl Machine {
     /// Function loadimm.
     /// regA L H: interpret H and L respectively as the high-order and the low-order bytes 
     /// of a 16-bit signed value, sign-extend it to 32 bits, and store it into register regA.
-    fn loadimm(&mut self, _reg_a: usize, l: u8 , h: u8) -> Result<bool, MachineError>
+    /// The decoded 16-bit value is given in `value`.
+    fn loadimm(&mut self, _reg_a: usize, value: i16) -> Result<bool, MachineError>
     {
-        let value = ((h as i16) << 8) | l as i16;
         let extvalue: i32 = i32::from(value);
         self.set_reg(_reg_a, extvalue as u32)?;
         Ok(false)
@@ -263,8 +463,8 @@ impl Machine {
     /// For example, 0 - 1 returns 0xffffffff, and 0 - 0xffffffff returns 1.
     fn sub(&mut self, _reg_a: usize, _reg_b: usize , _reg_c: usize) -> Result<bool, MachineError>
     {
-        Self::check_register_in_bounds(_reg_b)?;
-        Self::check_register_in_bounds(_reg_c)?;
+        self.check_register_in_bounds(_reg_b)?;
+        self.check_register_in_bounds(_reg_c)?;
         let reg_b = Wrapping(self.registers[_reg_b]);
         let reg_c = Wrapping(self.registers[_reg_c]);
         let substraction = (reg_b - reg_c).0;
@@ -274,34 +474,56 @@ impl Machine {
         }
         else
         {
-            Err(MachineError::RegisterOutOfBounds)
+            Err(MachineErrorKind::RegisterOutOfBounds(_reg_a).into())
         }
     }
 
 
     /// Function out.
     /// regA: output the character whose unicode value 
-    /// is stored in the 8 low bits of register regA.
+    /// is stored in the 8 low bits of register regA,
+    /// or this byte as is in raw output mode.
     fn out<T: Write>(&mut self, fd: &mut T, _reg_a: usize) -> Result<bool, MachineError>
     {
-        Self::check_register_in_bounds(_reg_a)?;
+        self.check_register_in_bounds(_reg_a)?;
+        let value: u32 = self.registers[_reg_a];
+        if self.output_mode == OutputMode::Raw
+        {
+            return match fd.write_all(&[value as u8])
+            {
+                Ok(()) => Ok(false),
+                Err(e) => Err(MachineErrorKind::WriteToBufferFailed(e).into()),
+            };
+        }
+        self.write_char(fd, value & 0xFF)
+    }
+
+    /// Function out char.
+    /// regA: output the character whose unicode value
+    /// is stored in register regA, encoded in UTF-8.
+    fn out_char<T: Write>(&mut self, fd: &mut T, _reg_a: usize) -> Result<bool, MachineError>
+    {
+        self.check_register_in_bounds(_reg_a)?;
         let value: u32 = self.registers[_reg_a];
-        if let Some(c) = std::char::from_u32(value & 0xFF)
+        self.write_char(fd, value)
+    }
+
+    /// Write the character whose unicode value is `value`, encoded in UTF-8.
+    fn write_char<T: Write>(&mut self, fd: &mut T, value: u32) -> Result<bool, MachineError>
+    {
+        if let Some(c) = std::char::from_u32(value)
         {
             let mut encodedval: [u8;4] = [0;4];
             let buf = c.encode_utf8(&mut encodedval).as_bytes();
-            if fd.write_all(buf).is_ok()
+            match fd.write_all(buf)
             {
-                Ok(false)
-            }
-            else
-            {
-                Err(MachineError::WriteToBufferFailed)
+                Ok(()) => Ok(false),
+                Err(e) => Err(MachineErrorKind::WriteToBufferFailed(e).into()),
             }
         }
         else
         {
-            Err(MachineError::NumberConversionToCharNotValid)
+            Err(MachineErrorKind::NumberConversionToCharNotValid(value).into())
         }
     }
 
@@ -311,17 +533,208 @@ impl Machine {
     /// regA: output the signed number stored in register regA in decimal.
     fn out_number<T: Write>(&mut self, fd: &mut T, _reg_a: usize) -> Result<bool, MachineError>
     {
-        Self::check_register_in_bounds(_reg_a)?;
+        self.check_register_in_bounds(_reg_a)?;
         let value = self.registers[_reg_a] as i32;
-        if write!(fd,"{}", value).is_ok()
+        match write!(fd,"{}", value)
         {
-            Ok(false)
+            Ok(()) => Ok(false),
+            Err(e) => Err(MachineErrorKind::WriteToBufferFailed(e).into()),
         }
-        else
+
+    }
+
+    /// Function out unsigned.
+    /// regA: output the unsigned number stored in register regA
+    /// in the given radix (2, 10 or 16), without prefix.
+    fn out_unsigned<T: Write>(&mut self, fd: &mut T, _reg_a: usize, radix: u32) -> Result<bool, MachineError>
+    {
+        self.check_register_in_bounds(_reg_a)?;
+        let value = self.registers[_reg_a];
+        let result = match radix
+        {
+            2 => write!(fd, "{:b}", value),
+            16 => write!(fd, "{:x}", value),
+            _ => write!(fd, "{}", value),
+        };
+        match result
         {
-            Err(MachineError::WriteToBufferFailed)
+            Ok(()) => Ok(false),
+            Err(e) => Err(MachineErrorKind::WriteToBufferFailed(e).into()),
+        }
+    }
+
+
+
+    /// Function exit.
+    /// regA: terminate the program with the value of register regA as
+    /// exit status, or with 0 when there is no register.
+    fn exit(&mut self, _reg_a: Option<usize>) -> Result<bool, MachineError>
+    {
+        let status = match _reg_a
+        {
+            Some(reg) =>
+            {
+                self.check_register_in_bounds(reg)?;
+                self.registers[reg]
+            }
+            None => 0,
+        };
+        self.history.record(Change::ExitStatus(self.exit_status));
+        self.exit_status = status;
+        Ok(true)
+    }
+
+
+
+    /// Function in.
+    /// regA regB: read one byte from the input and store it into register regA.
+    /// At end of input, regA is set to 0 and regB to 1, otherwise regB is set to 0.
+    fn input<R: Read>(&mut self, input: &mut R, _reg_a: usize, _reg_b: usize) -> Result<bool, MachineError>
+    {
+        self.check_register_in_bounds(_reg_a)?;
+        self.check_register_in_bounds(_reg_b)?;
+        let (value, eof) = match Self::read_byte(input)? {
+            Some(byte) => (byte as u32, 0),
+            None => (0, 1),
+        };
+        self.set_reg(_reg_a, value)?;
+        self.set_reg(_reg_b, eof)?;
+        Ok(false)
+    }
+
+
+    /// Function in number.
+    /// regA regB: skip whitespace, then read a signed decimal number from the input
+    /// and store it into register regA. The character following the number is consumed.
+    /// At end of input, regA is set to 0 and regB to 1, otherwise regB is set to 0.
+    fn input_number<R: Read>(&mut self, input: &mut R, _reg_a: usize, _reg_b: usize) -> Result<bool, MachineError>
+    {
+        self.check_register_in_bounds(_reg_a)?;
+        self.check_register_in_bounds(_reg_b)?;
+        let mut next = Self::read_byte(input)?;
+        while matches!(next, Some(c) if c.is_ascii_whitespace()) {
+            next = Self::read_byte(input)?;
+        }
+        if next.is_none() {
+            self.set_reg(_reg_a, 0)?;
+            self.set_reg(_reg_b, 1)?;
+            return Ok(false);
+        }
+        let negative = next == Some(b'-');
+        if negative {
+            next = Self::read_byte(input)?;
+        }
+        let mut value: Option<i64> = None;
+        while let Some(digit @ b'0'..=b'9') = next {
+            value = value
+                .unwrap_or(0)
+                .checked_mul(10)
+                .and_then(|v| v.checked_add((digit - b'0') as i64))
+                .filter(|&v| v <= u32::MAX as i64);
+            if value.is_none() {
+                return Err(MachineErrorKind::InvalidNumberInput.into());
+            }
+            next = Self::read_byte(input)?;
+        }
+        let value = match value {
+            Some(v) if negative && v > -(i32::MIN as i64) => return Err(MachineErrorKind::InvalidNumberInput.into()),
+            Some(v) if negative => -v,
+            Some(v) => v,
+            None => return Err(MachineErrorKind::InvalidNumberInput.into()),
+        };
+        self.set_reg(_reg_a, value as u32)?;
+        self.set_reg(_reg_b, 0)?;
+        Ok(false)
+    }
+
+
+    /// Extended arithmetic, logic and comparison instructions.
+    /// regA regB regC: store `op(regB, regC)` into register regA.
+    fn binary(&mut self, _reg_a: usize, _reg_b: usize, _reg_c: usize, op: fn(u32, u32) -> Result<u32, MachineErrorKind>) -> Result<bool, MachineError>
+    {
+        self.check_register_in_bounds(_reg_b)?;
+        self.check_register_in_bounds(_reg_c)?;
+        let value = op(self.registers[_reg_b], self.registers[_reg_c])?;
+        self.set_reg(_reg_a, value)?;
+        Ok(false)
+    }
+
+
+    /// Relative branch.
+    /// Add `offset` to IP, which already points after the branch, if `taken`.
+    fn branch(&mut self, offset: i16, taken: bool) -> Result<bool, MachineError>
+    {
+        if taken {
+            let target = self.registers[IP].wrapping_add(offset as i32 as u32);
+            self.set_reg(IP, target)?;
+        }
+        Ok(false)
+    }
+
+
+    /// Function load byte.
+    /// regA regB: load the byte at address pointed by register regB into register regA,
+    /// zero-extended.
+    fn load_byte(&mut self, _reg_a: usize, _reg_b: usize) -> Result<bool, MachineError>
+    {
+        self.check_register_in_bounds(_reg_a)?;
+        self.check_register_in_bounds(_reg_b)?;
+        let addr = self.registers[_reg_b] as usize;
+        self.check_access(addr, 1, Access::Read)?;
+        let value = match self.devices.iter_mut().find(|d| d.contains(addr)) {
+            Some(device) => device.load(addr, self.steps)? & 0xff,
+            None => {
+                let value = *self.memory.get(addr).ok_or(MachineErrorKind::MemoryIndexOutOfBounds(addr))? as u32;
+                if !self.watchpoints.is_empty() {
+                    self.watch_memory(addr, 1, false, value, value);
+                }
+                value
+            }
+        };
+        self.set_reg(_reg_a, value)?;
+        Ok(false)
+    }
+
+
+    /// Function store byte.
+    /// regA regB: store the 8 low bits of register regB at address pointed by register regA.
+    fn store_byte(&mut self, _reg_a: usize, _reg_b: usize) -> Result<bool, MachineError>
+    {
+        self.check_register_in_bounds(_reg_a)?;
+        self.check_register_in_bounds(_reg_b)?;
+        let addr = self.registers[_reg_a] as usize;
+        let value = self.registers[_reg_b] & 0xff;
+        self.check_access(addr, 1, Access::Write)?;
+        if let Some(device) = self.devices.iter_mut().find(|d| d.contains(addr)) {
+            device.store(addr, value, self.steps)?;
+            return Ok(false);
         }
+        match self.memory.get(addr) {
+            Some(&old) => {
+                self.history.record(Change::Byte(addr, old));
+                self.memory[addr] = value as u8;
+                self.invalidate(addr, 1);
+                if !self.watchpoints.is_empty() {
+                    self.watch_memory(addr, 1, true, old as u32, value);
+                }
+                Ok(false)
+            }
+            None => Err(MachineErrorKind::MemoryIndexOutOfBounds(addr).into()),
+        }
+    }
+
 
+    /// Read one byte from `input`, or `None` at end of input.
+    fn read_byte<R: Read>(input: &mut R) -> Result<Option<u8>, MachineError> {
+        let mut byte = [0; 1];
+        loop {
+            match input.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(MachineErrorKind::ReadFromInputFailed(e).into()),
+            }
+        }
     }
 
 
@@ -340,7 +753,8 @@ impl Machine {
     /// Sets a register to the given value.
     pub fn set_reg(&mut self, reg: usize, value: u32) -> Result<(), MachineError> {
 
-        Self::check_register_in_bounds(reg)?;
+        self.check_register_in_bounds(reg)?;
+        self.history.record(Change::Register(reg, self.registers[reg]));
         self.registers[reg] = value;
         Ok(())
 
@@ -351,12 +765,115 @@ impl Machine {
         &self.memory[..]
     }
 
+    /// Copies `bytes` into the machine memory starting at `address`.
+    pub fn set_mem(&mut self, address: usize, bytes: &[u8]) -> Result<(), MachineError> {
+        match address.checked_add(bytes.len()) {
+            Some(end) if end <= self.memory.len() => {
+                self.memory[address..end].copy_from_slice(bytes);
+                self.invalidate(address, bytes.len());
+                Ok(())
+            }
+            _ => Err(MachineErrorKind::MemoryIndexOutOfBounds(address).into()),
+        }
+    }
+
+    /// Instruction set accepted by the machine.
+    pub fn isa(&self) -> Isa {
+        self.isa
+    }
+
+    /// Select the instruction set. The base opcodes behave identically in
+    /// both sets.
+    pub fn set_isa(&mut self, isa: Isa) {
+        self.isa = isa;
+        self.cache.clear();
+    }
+
+    /// Encoding of the characters written by `out`.
+    pub fn output_mode(&self) -> OutputMode {
+        self.output_mode
+    }
+
+    /// Select how `out` writes characters. `out_char` always writes UTF-8.
+    pub fn set_output_mode(&mut self, mode: OutputMode) {
+        self.output_mode = mode;
+    }
+
+    /// Keep the changes made by the last `limit` steps so that they can be
+    /// undone with [step_back](Machine::step_back). A limit of 0, the
+    /// default, disables the journal.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history.set_limit(limit);
+    }
+
+    /// Maximum number of steps kept in the journal.
+    pub fn history_limit(&self) -> usize {
+        self.history.limit()
+    }
+
+    /// Number of steps which can currently be undone.
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Undo the last step, restoring the registers, memory and step count
+    /// as they were before it. Returns `false` if the journal is empty.
+    ///
+    /// Changes made through [set_reg](Machine::set_reg) or
+    /// [set_mem](Machine::set_mem) between steps are not journaled and
+    /// are therefore not undone.
+    pub fn step_back(&mut self) -> bool {
+        let entry = match self.history.pop() {
+            Some(entry) => entry,
+            None => return false,
+        };
+        for change in entry.changes.into_iter().rev() {
+            match change {
+                Change::Register(reg, value) => self.registers[reg] = value,
+                Change::Memory(address, bytes) => {
+                    self.memory[address..address + 4].copy_from_slice(&bytes);
+                    self.invalidate(address, 4);
+                }
+                Change::Byte(address, byte) => {
+                    self.memory[address] = byte;
+                    self.invalidate(address, 1);
+                }
+                Change::ExitStatus(status) => self.exit_status = status,
+            }
+        }
+        self.steps = entry.steps;
+        true
+    }
+
+    /// Undo steps until IP is equal to `address`, at least one step being
+    /// undone. Returns `false` if the journal is exhausted before, in which
+    /// case the machine is left in the oldest journaled state.
+    pub fn run_back_to(&mut self, address: usize) -> bool {
+        while self.step_back() {
+            if self.registers[IP] as usize == address {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Forget the pre-decoded instructions overlapping the `len` bytes
+    /// written at `address`. Instructions are at most 4 bytes long.
+    pub(crate) fn invalidate(&mut self, address: usize, len: usize) {
+        if !self.cache.is_empty() {
+            let end = (address + len).min(self.cache.len());
+            for entry in &mut self.cache[address.saturating_sub(3)..end] {
+                *entry = None;
+            }
+        }
+    }
+
     /// Function to check if registers are in bounds
-    fn check_register_in_bounds(reg: usize) -> Result<(), MachineError> {
-        if reg < 16 {
+    fn check_register_in_bounds(&self, reg: usize) -> Result<(), MachineError> {
+        if reg < self.registers.len() {
             Ok(())
         } else {
-            Err(MachineError::RegisterOutOfBounds)
+            Err(MachineErrorKind::RegisterOutOfBounds(reg).into())
         }
     }
 
@@ -1,19 +1,694 @@
-use interpreter::{Machine, MachineError};
+use interpreter::{
+    analysis, assembler, compiler,
+    debugger::Debugger,
+    device::{self, Console, CycleCounter, Random},
+    disassembler,
+    gdb::GdbServer,
+    profiler::Profile,
+    Instruction, Isa, Machine, MachineBuilder, MachineError, MachineErrorKind, OutputMode, Region,
+    MEMORY_SIZE, NREGS,
+};
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, BufWriter, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-fn main() -> Result<(), MachineError> {
-    // Take a filename as argument on the command line
-    let filename = std::env::args().nth(1).unwrap();
+const USAGE: &str = "\
+usage: tp-rust-2 <command> [<options>] <arguments>
 
-    // Read content to buffer
-    let mut fs = File::open(&filename).unwrap();
-    let mut buffer = Vec::new();
-    fs.read_to_end(&mut buffer).unwrap();
+Commands:
+  run [<options>] <program.bin>    run a program, the default when the
+                                   command is omitted
+  trace [<options>] <program.bin>  run a program, writing the execution
+                                   trace on standard error
+  debug [--extended] [--reg rN=<value>]... <program.bin>
+                                   run a program under the debugger
+  gdb [--extended] [--port <n>] <program.bin>
+                                   serve the GDB remote protocol on
+                                   standard input and output, or on a port
+  asm <input.dis> [output.bin]     assemble a listing
+  dis [--extended] <program.bin>   print the listing of a program
+  cfg [--extended] [--dot] <program.bin>
+                                   report the control-flow issues of a
+                                   program, or print its graph
+  compile <program.src> [output.bin]
+                                   compile a program of the tiny language,
+                                   to be run with --extended
+  help                             print this message
 
-    // Create a machine with this memory content
-    let mut machine = Machine::new(&buffer);
+Options of run and trace:
+  --reg rN=<value>       set register rN before running, may be repeated
+  --max-steps <n>        stop after n instructions
+  --timeout <secs>       stop after the given number of seconds
+  --output <file>        write the program output to the file instead of
+                         standard output
+  --dump-regs            print the registers on standard error at exit
+  --dump-mem <s>:<e>     print the memory from address s to e on standard
+                         error at exit, may be repeated
+  --load-state <file>    resume from a snapshot instead of a program
+  --save-state <file>    write a snapshot when the machine stops
+  --trace                write the execution trace on standard error
+  --fast                 use the pre-decoded execution engine
+  --coverage             write the listing next to the program annotated
+                         with hit counts on standard error
+  --profile              write an execution profile on standard error,
+                         using the labels of the listing next to the
+                         program if there is one; only one of --trace,
+                         --fast and --profile may be given
+  --extended             enable the extended instruction set
+  --raw-output           write the low byte of the register as is with
+                         `out` instead of encoding it in UTF-8
+  --devices              map the console, cycle counter and random
+                         generator at their usual addresses
+  --region <s>:<e>:<p>   restrict the accesses from address s to e to the
+                         permissions p (e.g. `r-x`), may be repeated
+
+Exit status:
+  0-255 status of the program, modulo 256: rA for `exit rA`, and 0 for
+        `exit`, the only way to stop without the extended instruction set;
+        the statuses below are reserved and may not be used by programs
+  64    invalid command line
+  65    a file could not be read or written, a source has errors, or the
+        program exited with a reserved status
+  80    register out of bounds        87    step limit exceeded
+  81    memory index out of bounds    88    deadline exceeded
+  82    invalid opcode                89    address already mapped
+  83    invalid character             90    division by zero
+  84    output failed                 91    protection fault
+  85    input failed                  92    watchpoint
+  86    invalid number input
+";
+
+/// Exit statuses of the command itself, which programs may not use so
+/// that both cannot be confused. The machine errors have those of
+/// [exit_code].
+const EXIT_USAGE: i32 = 64;
+const EXIT_FAILURE: i32 = 65;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => {
+            eprint!("{}", USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    };
+    match command {
+        "run" => run(rest, false),
+        "trace" => run(rest, true),
+        "debug" => debug(rest),
+        "gdb" => gdb(rest),
+        "asm" => assemble(rest),
+        "dis" => disassemble(rest),
+        "cfg" => control_flow(rest),
+        "compile" => compile(rest),
+        "help" | "--help" | "-h" => print!("{}", USAGE),
+        // Without a command, the arguments are those of `run`
+        _ => run(&args, false),
+    }
+}
+
+/// Value given to a register with `--reg rN=<value>`, the value being
+/// anything from `i32::MIN` to `u32::MAX`.
+struct RegisterValue {
+    reg: usize,
+    value: u32,
+}
+
+impl FromStr for RegisterValue {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (reg, value) = s.split_once('=').ok_or(())?;
+        let reg = reg.strip_prefix('r').ok_or(())?.parse().map_err(|_| ())?;
+        let value = match assembler::parse_number(value) {
+            Some(value) if value >= i32::MIN as i64 && value <= u32::MAX as i64 => value as u32,
+            _ => return Err(()),
+        };
+        Ok(RegisterValue { reg, value })
+    }
+}
+
+/// Range of addresses from `start` included to `end` excluded, given as
+/// `<start>:<end>` to `--dump-mem`.
+struct MemoryRange {
+    start: usize,
+    end: usize,
+}
+
+impl FromStr for MemoryRange {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let address = |text: &str| {
+            assembler::parse_number(text)
+                .and_then(|a| usize::try_from(a).ok())
+                .ok_or(())
+        };
+        let (start, end) = s.split_once(':').ok_or(())?;
+        Ok(MemoryRange {
+            start: address(start)?,
+            end: address(end)?,
+        })
+    }
+}
+
+/// Run a program with the options of `run`, tracing it if `trace` is set,
+/// and exit with the status of the program, or the one matching the error
+/// which stopped the machine.
+fn run(args: &[String], mut trace: bool) {
+    let mut fast = false;
+    let mut profile = false;
+    let mut coverage = false;
+    let mut devices = false;
+    let mut dump_regs = false;
+    let mut dumps: Vec<MemoryRange> = Vec::new();
+    let mut presets: Vec<RegisterValue> = Vec::new();
+    let mut regions: Vec<Region> = Vec::new();
+    // Unless given, those of a snapshot are kept
+    let mut isa = None;
+    let mut output_mode = None;
+    let mut max_steps = None;
+    let mut timeout = None;
+    let mut output_file: Option<PathBuf> = None;
+    let mut load_state: Option<PathBuf> = None;
+    let mut save_state: Option<PathBuf> = None;
+    let mut args = args.iter();
+    let filename = loop {
+        match args.next().map(String::as_str) {
+            Some("--trace") => trace = true,
+            Some("--fast") => fast = true,
+            Some("--profile") => profile = true,
+            Some("--coverage") => coverage = true,
+            Some("--devices") => devices = true,
+            Some("--dump-regs") => dump_regs = true,
+            Some("--extended") => isa = Some(Isa::Extended),
+            Some("--raw-output") => output_mode = Some(OutputMode::Raw),
+            Some("--reg") => presets.push(option_value(args.next(), "--reg")),
+            Some("--dump-mem") => dumps.push(option_value(args.next(), "--dump-mem")),
+            Some("--region") => regions.push(option_value(args.next(), "--region")),
+            Some("--max-steps") => {
+                max_steps = Some(option_value::<u64>(args.next(), "--max-steps"))
+            }
+            Some("--timeout") => {
+                let secs: f64 = option_value(args.next(), "--timeout");
+                timeout = Some(Duration::try_from_secs_f64(secs).unwrap_or_else(|_| {
+                    eprintln!("--timeout expects a valid value");
+                    process::exit(EXIT_USAGE);
+                }));
+            }
+            Some("--output") => output_file = Some(option_value(args.next(), "--output")),
+            Some("--load-state") => load_state = Some(option_value(args.next(), "--load-state")),
+            Some("--save-state") => save_state = Some(option_value(args.next(), "--save-state")),
+            Some(option) if option.starts_with("--") => {
+                eprintln!("unknown option {}", option);
+                process::exit(EXIT_USAGE);
+            }
+            filename => break filename,
+        }
+    };
+    if args.next().is_some() {
+        eprintln!("unexpected arguments after the program");
+        process::exit(EXIT_USAGE);
+    }
+    if [trace, fast, profile].iter().filter(|&&set| set).count() > 1 {
+        eprintln!("--trace, --fast and --profile cannot be combined");
+        process::exit(EXIT_USAGE);
+    }
+
+    let mut machine = match (filename, load_state) {
+        // Create a machine with the program as memory content
+        (Some(filename), None) => load_program(filename, isa.unwrap_or_default()),
+        // Resume a machine from its snapshot
+        (None, Some(state)) => {
+            let snapshot = read_file(&state);
+            Machine::restore(&snapshot).unwrap_or_else(|e| {
+                eprintln!("{}: {}", state.display(), e);
+                process::exit(EXIT_FAILURE);
+            })
+        }
+        _ => {
+            eprintln!(
+                "usage: tp-rust-2 {} [<options>] (<program.bin> | --load-state <file>)",
+                if trace { "trace" } else { "run" }
+            );
+            eprintln!("run `tp-rust-2 help` for the list of options");
+            process::exit(EXIT_USAGE);
+        }
+    };
+    if let Some(isa) = isa {
+        machine.set_isa(isa);
+    }
+    if let Some(output_mode) = output_mode {
+        machine.set_output_mode(output_mode);
+    }
+    machine.set_coverage(coverage);
+    if devices {
+        map_devices(&mut machine);
+    }
+    for region in regions {
+        machine.protect(region.start, region.end, region.permissions);
+    }
+    preset_registers(&mut machine, &presets);
+    // The step limit counts the instructions executed by this run only
+    machine.set_step_limit(max_steps.map(|n| machine.steps().saturating_add(n)));
+    machine.set_deadline(timeout.map(|timeout| Instant::now() + timeout));
+
+    let mut output: Box<dyn Write> = match &output_file {
+        Some(path) => Box::new(BufWriter::new(File::create(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path.display(), e);
+            process::exit(EXIT_FAILURE);
+        }))),
+        None => Box::new(io::stdout().lock()),
+    };
+    let input = &mut io::stdin().lock();
 
     // Run the machine until the end
-    machine.run()
+    let mut counts = Profile::new();
+    let result = if trace {
+        machine.run_traced_with_io(input, &mut output, &mut io::stderr().lock())
+    } else if fast {
+        machine.run_fast_with_io(input, &mut output)
+    } else if profile {
+        machine.run_profiled_with_io(input, &mut output, &mut counts)
+    } else {
+        machine.run_with_io(input, &mut output)
+    };
+    if let Err(e) = output.flush() {
+        let name = output_file.as_deref().unwrap_or(Path::new("stdout"));
+        eprintln!("{}: {}", name.display(), e);
+        process::exit(EXIT_FAILURE);
+    }
+    if profile {
+        let labels = filename
+            .map(|f| labels_for(Path::new(f)))
+            .unwrap_or_default();
+        check_written(
+            counts.report_on(&machine, &labels, &mut io::stderr().lock()),
+            "stderr",
+        );
+    }
+    if coverage {
+        report_coverage(&machine, filename);
+    }
+    if dump_regs {
+        dump_registers(&machine);
+    }
+    for range in &dumps {
+        dump_memory(&machine, range);
+    }
+    if let Some(state) = save_state {
+        if let Err(e) = std::fs::write(&state, machine.snapshot()) {
+            eprintln!("{}: {}", state.display(), e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+    match result {
+        Ok(status) => {
+            let code = (status & 0xff) as i32;
+            if matches!(code, EXIT_USAGE | EXIT_FAILURE | 80..=92) {
+                eprintln!("error: exit status {} is reserved by the command", status);
+                process::exit(EXIT_FAILURE);
+            }
+            process::exit(code)
+        }
+        Err(e) => {
+            report(&machine, &e);
+            process::exit(exit_code(&e));
+        }
+    }
+}
+
+/// Exit status of the process when the machine stops on `error`.
+fn exit_code(error: &MachineError) -> i32 {
+    match error.kind {
+        MachineErrorKind::RegisterOutOfBounds(_) => 80,
+        MachineErrorKind::MemoryIndexOutOfBounds(_) => 81,
+        MachineErrorKind::InvalidOpcode(_) => 82,
+        MachineErrorKind::NumberConversionToCharNotValid(_) => 83,
+        MachineErrorKind::WriteToBufferFailed(_) => 84,
+        MachineErrorKind::ReadFromInputFailed(_) => 85,
+        MachineErrorKind::InvalidNumberInput => 86,
+        MachineErrorKind::StepLimitExceeded(_) => 87,
+        MachineErrorKind::DeadlineExceeded(_) => 88,
+        MachineErrorKind::AddressAlreadyMapped(_) => 89,
+        MachineErrorKind::DivisionByZero => 90,
+        MachineErrorKind::ProtectionFault { .. } => 91,
+        MachineErrorKind::Watchpoint(_) => 92,
+    }
+}
+
+/// Read the whole file at `path`, exiting if it cannot be read.
+fn read_file<P: AsRef<Path>>(path: P) -> Vec<u8> {
+    std::fs::read(&path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path.as_ref().display(), e);
+        process::exit(EXIT_FAILURE);
+    })
+}
+
+/// Create a machine with the program at `path` as memory content and the
+/// instruction set `isa`, exiting if the program cannot be read or does
+/// not fit in the memory.
+fn load_program(path: &str, isa: Isa) -> Machine {
+    let code = read_file(path);
+    MachineBuilder::new()
+        .isa(isa)
+        .program(&code)
+        .build()
+        .unwrap_or_else(|_| {
+            eprintln!(
+                "{}: program of {} bytes does not fit in the {} bytes of memory",
+                path,
+                code.len(),
+                MEMORY_SIZE
+            );
+            process::exit(EXIT_FAILURE);
+        })
+}
+
+/// Exit if writing the output of a command to `name` failed. The
+/// diagnostic is lost if standard error is the one which failed.
+fn check_written(result: io::Result<()>, name: &str) {
+    if let Err(e) = result {
+        let _ = writeln!(io::stderr(), "{}: {}", name, e);
+        process::exit(EXIT_FAILURE);
+    }
+}
+
+/// Set the registers given with `--reg`, exiting if one does not exist.
+fn preset_registers(machine: &mut Machine, presets: &[RegisterValue]) {
+    for preset in presets {
+        if let Err(e) = machine.set_reg(preset.reg, preset.value) {
+            eprintln!("--reg: {}", e);
+            process::exit(EXIT_USAGE);
+        }
+    }
+}
+
+/// Print the registers on standard error, as the debugger does.
+fn dump_registers(machine: &Machine) {
+    for (reg, value) in machine.regs().iter().enumerate() {
+        eprintln!("r{:<2} = {:#010x} ({})", reg, value, *value as i32);
+    }
+}
+
+/// Print the bytes of `range` on standard error, 16 per line, as the
+/// debugger does. The range is clipped to the memory.
+fn dump_memory(machine: &Machine, range: &MemoryRange) {
+    let memory = machine.memory();
+    let end = range.end.min(memory.len());
+    for start in (range.start..end).step_by(16) {
+        let bytes: String = memory[start..end.min(start + 16)]
+            .iter()
+            .map(|byte| format!(" {:02x}", byte))
+            .collect();
+        eprintln!("{:04}:{}", start, bytes);
+    }
+}
+
+/// Parse the value of a command line option, exiting if it is invalid.
+fn option_value<T: std::str::FromStr>(value: Option<&String>, option: &str) -> T {
+    match value.map(|v| v.parse()) {
+        Some(Ok(value)) => value,
+        _ => {
+            eprintln!("{} expects a valid value", option);
+            process::exit(EXIT_USAGE);
+        }
+    }
+}
+
+/// Map the standard devices, the random generator being seeded from the clock.
+fn map_devices(machine: &mut Machine) {
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    machine
+        .map_device(device::CONSOLE_ADDRESS, Box::new(Console::stdio()))
+        .unwrap();
+    machine
+        .map_device(device::CYCLE_COUNTER_ADDRESS, Box::new(CycleCounter::new()))
+        .unwrap();
+    machine
+        .map_device(device::RANDOM_ADDRESS, Box::new(Random::new(seed)))
+        .unwrap();
+}
+
+/// Print the listing of `program` annotated with the hits recorded by
+/// `machine` on standard error.
+fn report_coverage(machine: &Machine, program: Option<&str>) {
+    let listing = match program.map(|p| Path::new(p).with_extension("dis")) {
+        Some(listing) => listing,
+        None => {
+            eprintln!("--coverage needs the listing of a program");
+            process::exit(EXIT_USAGE);
+        }
+    };
+    let source = std::fs::read_to_string(&listing).unwrap_or_else(|e| {
+        eprintln!("{}: {}", listing.display(), e);
+        process::exit(EXIT_FAILURE);
+    });
+    match machine.coverage().unwrap().annotate(&source) {
+        Ok(report) => eprintln!("{}", report),
+        Err(e) => {
+            eprintln!("{}: {}", listing.display(), e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Print a diagnostic for `error`, showing the faulting instruction.
+fn report(machine: &Machine, error: &MachineError) {
+    eprintln!("error: {}", error);
+    if let Some(ip) = error.ip {
+        let decoded = machine
+            .memory()
+            .get(ip..)
+            .map(|bytes| Instruction::decode_for(bytes, machine.isa()));
+        if let Some(Ok((instruction, _))) = decoded {
+            eprintln!("  {:04}   {}", ip, instruction);
+        }
+    }
+    if let MachineErrorKind::InvalidOpcode(opcode) = error.kind {
+        if !machine.isa().supports(opcode) && Isa::Extended.supports(opcode) {
+            eprintln!(
+                "  opcode {} (`{}`) belongs to the extended instruction set, run with --extended",
+                opcode,
+                Instruction::mnemonic_of(opcode).unwrap()
+            );
+        }
+    }
+}
+
+/// Assemble the listing given as first argument into the file given as
+/// second argument, or next to the listing with a `.bin` extension.
+fn assemble(args: &[String]) {
+    let input = match args.first() {
+        Some(input) => input,
+        None => {
+            eprintln!("usage: tp-rust-2 asm <input.dis> [output.bin]");
+            process::exit(EXIT_USAGE);
+        }
+    };
+    let output = match args.get(1) {
+        Some(output) => output.into(),
+        None => Path::new(input).with_extension("bin"),
+    };
+    let source = std::fs::read_to_string(input).unwrap_or_else(|e| {
+        eprintln!("{}: {}", input, e);
+        process::exit(EXIT_FAILURE);
+    });
+    let code = assembler::assemble(&source).unwrap_or_else(|e| {
+        eprintln!("{}: {}", input, e);
+        process::exit(EXIT_FAILURE);
+    });
+    if let Err(e) = std::fs::write(&output, code) {
+        eprintln!("{}: {}", output.display(), e);
+        process::exit(EXIT_FAILURE);
+    }
+}
+
+/// Compile the program given as first argument into the file given as
+/// second argument, or next to the program with a `.bin` extension. The
+/// generated listing is written next to the binary with a `.dis`
+/// extension, providing the labels to the debugger and the profiler.
+fn compile(args: &[String]) {
+    let input = match args.first() {
+        Some(input) => input,
+        None => {
+            eprintln!("usage: tp-rust-2 compile <program.src> [output.bin]");
+            process::exit(EXIT_USAGE);
+        }
+    };
+    let output = match args.get(1) {
+        Some(output) => output.into(),
+        None => Path::new(input).with_extension("bin"),
+    };
+    let source = std::fs::read_to_string(input).unwrap_or_else(|e| {
+        eprintln!("{}: {}", input, e);
+        process::exit(EXIT_FAILURE);
+    });
+    let listing = compiler::compile_to_listing(&source).unwrap_or_else(|e| {
+        eprintln!("{}: {}", input, e);
+        process::exit(EXIT_FAILURE);
+    });
+    let code = assembler::assemble(&listing).unwrap_or_else(|e| {
+        eprintln!("{}: generated code: {}", input, e);
+        process::exit(EXIT_FAILURE);
+    });
+    let listing_path = output.with_extension("dis");
+    for (path, content) in [(&output, code), (&listing_path, listing.into_bytes())] {
+        if let Err(e) = std::fs::write(path, content) {
+            eprintln!("{}: {}", path.display(), e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+    if compiler::ISA == Isa::Extended {
+        eprintln!(
+            "{}: uses the extended instruction set, run it with --extended",
+            output.display()
+        );
+    }
+}
+
+/// Print the listing of the binary given as first argument, possibly
+/// preceded by `--extended`.
+fn disassemble(args: &[String]) {
+    let (isa, args) = isa_option(args);
+    let input = match args.first() {
+        Some(input) => input,
+        None => {
+            eprintln!("usage: tp-rust-2 dis [--extended] <program.bin>");
+            process::exit(EXIT_USAGE);
+        }
+    };
+    let code = read_file(input);
+    check_written(
+        disassembler::disassemble_on_for(&code, isa, NREGS, &mut io::stdout().lock()),
+        "stdout",
+    );
+}
+
+/// Run the binary given as last argument, possibly preceded by
+/// `--extended` and `--reg rN=<value>` options, under the debugger. Labels
+/// are taken from the listing with the same name and a `.dis` extension,
+/// if there is one.
+fn debug(args: &[String]) {
+    let mut isa = Isa::Base;
+    let mut presets: Vec<RegisterValue> = Vec::new();
+    let mut args = args.iter();
+    let input = loop {
+        match args.next().map(String::as_str) {
+            Some("--extended") => isa = Isa::Extended,
+            Some("--reg") => presets.push(option_value(args.next(), "--reg")),
+            Some(input) if !input.starts_with("--") => break input,
+            _ => {
+                eprintln!(
+                    "usage: tp-rust-2 debug [--extended] [--reg rN=<value>]... <program.bin>"
+                );
+                process::exit(EXIT_USAGE);
+            }
+        }
+    };
+    let labels = labels_for(Path::new(input));
+    let mut machine = load_program(input, isa);
+    preset_registers(&mut machine, &presets);
+    let mut debugger = Debugger::new(machine).with_labels(labels);
+    check_written(
+        debugger.repl(io::stdin().lock(), &mut io::stdout().lock()),
+        "stdout",
+    );
+}
+
+/// Run the binary given as last argument, possibly preceded by
+/// `--extended` and `--port <n>`, under a GDB remote protocol server. The
+/// client is served on standard input and output, the program output going
+/// to standard error, or on the first connection to the TCP port.
+fn gdb(args: &[String]) {
+    let (isa, args) = isa_option(args);
+    let (port, args) = match args.first().map(String::as_str) {
+        Some("--port") => (
+            Some(option_value::<u16>(args.get(1), "--port")),
+            &args[2.min(args.len())..],
+        ),
+        _ => (None, args),
+    };
+    let input = match args.first() {
+        Some(input) => input,
+        None => {
+            eprintln!("usage: tp-rust-2 gdb [--extended] [--port <n>] <program.bin>");
+            process::exit(EXIT_USAGE);
+        }
+    };
+    let mut server = GdbServer::new(load_program(input, isa));
+    let result = match port {
+        Some(port) => TcpListener::bind(("127.0.0.1", port))
+            .and_then(|listener| {
+                eprintln!("listening on {}", listener.local_addr()?);
+                listener.accept()
+            })
+            .and_then(|(mut stream, _)| {
+                server.serve(stream.try_clone()?, &mut stream, &mut io::stdout().lock())
+            }),
+        None => server.serve(
+            io::stdin().lock(),
+            &mut io::stdout().lock(),
+            &mut io::stderr().lock(),
+        ),
+    };
+    if let Err(e) = result {
+        eprintln!("gdb: {}", e);
+        process::exit(EXIT_FAILURE);
+    }
+}
+
+/// Analyze the binary given as last argument, possibly preceded by
+/// `--extended` and `--dot`, and print the issues found or, with `--dot`,
+/// the control-flow graph in the DOT language.
+fn control_flow(args: &[String]) {
+    let (isa, args) = isa_option(args);
+    let (dot, args) = match args.first().map(String::as_str) {
+        Some("--dot") => (true, &args[1..]),
+        _ => (false, args),
+    };
+    let input = match args.first() {
+        Some(input) => input,
+        None => {
+            eprintln!("usage: tp-rust-2 cfg [--extended] [--dot] <program.bin>");
+            process::exit(EXIT_USAGE);
+        }
+    };
+    let code = read_file(input);
+    let cfg = analysis::analyze_for(&code, isa);
+    if dot {
+        let labels = labels_for(Path::new(input));
+        check_written(cfg.write_dot(&labels, &mut io::stdout().lock()), "stdout");
+    } else {
+        let mut out = io::stdout().lock();
+        for issue in &cfg.issues {
+            check_written(writeln!(out, "{}", issue), "stdout");
+        }
+    }
+}
+
+/// Labels of the listing with the same name as `program` and a `.dis`
+/// extension, or no label if there is no such listing.
+fn labels_for(program: &Path) -> HashMap<String, usize> {
+    std::fs::read_to_string(program.with_extension("dis"))
+        .ok()
+        .and_then(|source| assembler::assemble_with_labels(&source).ok())
+        .map(|(_, labels)| labels)
+        .unwrap_or_default()
+}
+
+/// Strip a leading `--extended` option from `args`.
+fn isa_option(args: &[String]) -> (Isa, &[String]) {
+    match args.first().map(String::as_str) {
+        Some("--extended") => (Isa::Extended, &args[1..]),
+        _ => (Isa::Base, args),
+    }
 }
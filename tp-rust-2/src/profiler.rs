@@ -0,0 +1,261 @@
+//! Execution profile, counting how many times each instruction runs.
+//!
+//! A [Profile] is filled by [Machine::run_profiled] and its variants. It
+//! counts executions per address and per opcode and estimates how many
+//! times each subroutine was called. With the labels of the program (for
+//! example taken from its listing by
+//! [assemble_with_labels](crate::assembler::assemble_with_labels)),
+//! counts can also be aggregated per label, every address belonging to the
+//! closest label before it.
+//!
+//! Calls cannot be told apart from other jumps by the instruction set, so
+//! they are recognized from the usual calling sequence: a jump is counted
+//! as a call of its target when the address following the jump is the
+//! last value written by `store`, i.e. the return address just pushed.
+
+use crate::{Instruction, Machine, MachineError, IP};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read, Write};
+
+/// Number of addresses shown in the hot spots section of the report.
+const HOT_SPOTS: usize = 10;
+
+/// Execution counts gathered while running a program.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    steps: u64,
+    addresses: BTreeMap<usize, u64>,
+    opcodes: BTreeMap<u8, u64>,
+    calls: BTreeMap<usize, u64>,
+    last_stored: Option<u32>,
+}
+
+impl Profile {
+    /// Create an empty profile.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of instructions executed.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Number of executions of the instruction at `address`.
+    pub fn count(&self, address: usize) -> u64 {
+        self.addresses.get(&address).copied().unwrap_or(0)
+    }
+
+    /// Number of executed instructions using `opcode`.
+    pub fn opcode_count(&self, opcode: u8) -> u64 {
+        self.opcodes.get(&opcode).copied().unwrap_or(0)
+    }
+
+    /// Estimated number of calls of the subroutine starting at `address`.
+    pub fn calls(&self, address: usize) -> u64 {
+        self.calls.get(&address).copied().unwrap_or(0)
+    }
+
+    /// Executed addresses with their counts, most executed first.
+    pub fn hot_spots(&self) -> Vec<(usize, u64)> {
+        let mut spots: Vec<(usize, u64)> = self.addresses.iter().map(|(&a, &n)| (a, n)).collect();
+        spots.sort_by(|(a1, n1), (a2, n2)| n2.cmp(n1).then(a1.cmp(a2)));
+        spots
+    }
+
+    /// Counts aggregated per label, most executed first. Addresses located
+    /// before the first label are gathered under `(no label)`.
+    pub fn by_label(&self, labels: &HashMap<String, usize>) -> Vec<(String, u64)> {
+        let symbols = Symbols::new(labels);
+        let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+        for (&address, &count) in &self.addresses {
+            let label = symbols.enclosing(address).unwrap_or("(no label)");
+            *counts.entry(label.to_string()).or_default() += count;
+        }
+        sorted(counts)
+    }
+
+    /// Estimated calls per subroutine, most called first. Subroutines
+    /// without a label are named after their address.
+    pub fn calls_by_label(&self, labels: &HashMap<String, usize>) -> Vec<(String, u64)> {
+        let symbols = Symbols::new(labels);
+        let counts = self
+            .calls
+            .iter()
+            .map(|(&address, &count)| (symbols.at(address), count))
+            .collect();
+        sorted(counts)
+    }
+
+    /// Write a report with the hot spots, the counts per label and per
+    /// opcode, and the estimated calls. Instructions are decoded from the
+    /// memory of `machine`.
+    pub fn report_on<W: Write>(
+        &self,
+        machine: &Machine,
+        labels: &HashMap<String, usize>,
+        out: &mut W,
+    ) -> io::Result<()> {
+        let symbols = Symbols::new(labels);
+        let percent = |count: u64| 100.0 * count as f64 / self.steps.max(1) as f64;
+        writeln!(out, "{} instructions executed", self.steps)?;
+
+        writeln!(out, "\nhot spots:")?;
+        writeln!(out, "{:>10} {:>7}  address  instruction", "count", "%")?;
+        for (address, count) in self.hot_spots().into_iter().take(HOT_SPOTS) {
+            let instruction = machine
+                .memory()
+                .get(address..)
+                .and_then(|bytes| Instruction::decode_for(bytes, machine.isa()).ok())
+                .map_or_else(|| "???".to_string(), |(i, _)| i.to_string());
+            let line = format!(
+                "{:>10} {:>6.2}%  {:04}     {:<28} {}",
+                count,
+                percent(count),
+                address,
+                instruction,
+                symbols.enclosing(address).unwrap_or_default()
+            );
+            writeln!(out, "{}", line.trim_end())?;
+        }
+
+        if !labels.is_empty() {
+            writeln!(out, "\nby label:")?;
+            writeln!(out, "{:>10} {:>7}  label", "count", "%")?;
+            for (label, count) in self.by_label(labels) {
+                writeln!(out, "{:>10} {:>6.2}%  {}", count, percent(count), label)?;
+            }
+        }
+
+        writeln!(out, "\nby opcode:")?;
+        writeln!(out, "{:>10} {:>7}  opcode", "count", "%")?;
+        let mut opcodes: Vec<(u8, u64)> = self.opcodes.iter().map(|(&o, &n)| (o, n)).collect();
+        opcodes.sort_by(|(o1, n1), (o2, n2)| n2.cmp(n1).then(o1.cmp(o2)));
+        for (opcode, count) in opcodes {
+            let mnemonic = Instruction::mnemonic_of(opcode).unwrap_or("???");
+            writeln!(
+                out,
+                "{:>10} {:>6.2}%  {} ({})",
+                count,
+                percent(count),
+                mnemonic,
+                opcode
+            )?;
+        }
+
+        writeln!(out, "\nestimated calls:")?;
+        writeln!(out, "{:>10}  subroutine", "calls")?;
+        for (label, count) in self.calls_by_label(labels) {
+            writeln!(out, "{:>10}  {}", count, label)?;
+        }
+        Ok(())
+    }
+
+    /// Account for the instruction at `address`, executed with `before` as
+    /// registers and which left IP at `next`.
+    fn record(&mut self, address: usize, instruction: Instruction, before: &[u32], next: u32) {
+        self.steps += 1;
+        *self.addresses.entry(address).or_default() += 1;
+        *self.opcodes.entry(instruction.opcode()).or_default() += 1;
+        let fallthrough = address + instruction.size();
+        if next as usize != fallthrough && self.last_stored == Some(fallthrough as u32) {
+            *self.calls.entry(next as usize).or_default() += 1;
+        }
+        if let Instruction::Store(_, b) = instruction {
+            self.last_stored = Some(before[b as usize]);
+        }
+    }
+}
+
+/// Labels sorted by address, to find the one enclosing an address.
+struct Symbols {
+    sorted: Vec<(usize, String)>,
+}
+
+impl Symbols {
+    fn new(labels: &HashMap<String, usize>) -> Self {
+        let mut by_address: BTreeMap<usize, Vec<&str>> = BTreeMap::new();
+        for (label, &address) in labels {
+            by_address.entry(address).or_default().push(label);
+        }
+        let sorted = by_address
+            .into_iter()
+            .map(|(address, mut names)| {
+                names.sort_unstable();
+                (address, names.join(", "))
+            })
+            .collect();
+        Symbols { sorted }
+    }
+
+    /// Name of the closest label at or before `address`.
+    fn enclosing(&self, address: usize) -> Option<&str> {
+        match self.sorted.partition_point(|(a, _)| *a <= address) {
+            0 => None,
+            i => Some(&self.sorted[i - 1].1),
+        }
+    }
+
+    /// Name of the label at `address`, or the address itself.
+    fn at(&self, address: usize) -> String {
+        match self.sorted.binary_search_by_key(&address, |(a, _)| *a) {
+            Ok(i) => self.sorted[i].1.clone(),
+            Err(_) => format!("{:04}", address),
+        }
+    }
+}
+
+/// Counts sorted by decreasing value, then by name.
+fn sorted(counts: BTreeMap<String, u64>) -> Vec<(String, u64)> {
+    let mut counts: Vec<(String, u64)> = counts.into_iter().collect();
+    counts.sort_by(|(l1, n1), (l2, n2)| n2.cmp(n1).then(l1.cmp(l2)));
+    counts
+}
+
+impl Machine {
+    /// Run until the program terminates or until an error happens, like
+    /// [run_on](Machine::run_on), while counting executed instructions
+    /// into `profile`.
+    pub fn run_profiled<T: Write>(
+        &mut self,
+        fd: &mut T,
+        profile: &mut Profile,
+    ) -> Result<u32, MachineError> {
+        self.run_profiled_with_io(&mut io::empty(), fd, profile)
+    }
+
+    /// Similar to [run_profiled](Machine::run_profiled), input
+    /// instructions reading from `input`.
+    pub fn run_profiled_with_io<R: Read, T: Write>(
+        &mut self,
+        input: &mut R,
+        fd: &mut T,
+        profile: &mut Profile,
+    ) -> Result<u32, MachineError> {
+        while !self.step_profiled_with_io(input, fd, profile)? {}
+        Ok(self.exit_status())
+    }
+
+    /// Similar to [step_with_io](Machine::step_with_io), counting the
+    /// executed instruction into `profile`. A failing instruction is not
+    /// counted.
+    pub fn step_profiled_with_io<R: Read, T: Write>(
+        &mut self,
+        input: &mut R,
+        fd: &mut T,
+        profile: &mut Profile,
+    ) -> Result<bool, MachineError> {
+        let address = self.regs()[IP] as usize;
+        let before = self.regs().to_vec();
+        // Decode first, as a store may overwrite its own instruction
+        let decoded = self
+            .memory()
+            .get(address..)
+            .and_then(|bytes| Instruction::decode_for(bytes, self.isa()).ok());
+        let done = self.step_with_io(input, fd)?;
+        if let Some((instruction, _)) = decoded {
+            profile.record(address, instruction, &before, self.regs()[IP]);
+        }
+        Ok(done)
+    }
+}
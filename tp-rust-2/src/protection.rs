@@ -0,0 +1,193 @@
+//! Memory protection.
+//!
+//! Regions of the address space can be given [Permissions], e.g. to make
+//! the code read-only, the data non-executable, or to put a guard zone
+//! without any permission below the stack. An access denied by a region
+//! fails with [ProtectionFault](MachineErrorKind::ProtectionFault).
+//!
+//! Addresses outside of every region are unrestricted, so a machine
+//! without regions behaves as an unprotected one. When regions overlap,
+//! the one added last applies. Instructions are checked when they are
+//! fetched, `load`, `store` and their byte variants when they access the
+//! memory or a device. The host writing memory with
+//! [set_mem](Machine::set_mem) is not restricted.
+
+use crate::{assembler::parse_number, Machine, MachineError, MachineErrorKind};
+use std::{fmt, str::FromStr};
+
+/// Kind of memory access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::Execute => write!(f, "execute"),
+        }
+    }
+}
+
+/// Accesses allowed in a region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Permissions {
+    /// No access at all, for guard zones.
+    pub const NONE: Permissions = Permissions {
+        read: false,
+        write: false,
+        execute: false,
+    };
+    /// Read and execute, for code.
+    pub const CODE: Permissions = Permissions {
+        read: true,
+        write: false,
+        execute: true,
+    };
+    /// Read and write, for data and stacks.
+    pub const DATA: Permissions = Permissions {
+        read: true,
+        write: true,
+        execute: false,
+    };
+
+    /// Whether `access` is allowed.
+    pub fn allows(self, access: Access) -> bool {
+        match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        }
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flag = |allowed: bool, c: char| if allowed { c } else { '-' };
+        write!(
+            f,
+            "{}{}{}",
+            flag(self.read, 'r'),
+            flag(self.write, 'w'),
+            flag(self.execute, 'x')
+        )
+    }
+}
+
+/// Parse permissions written as in [Display](fmt::Display), e.g. `r-x`.
+/// The dashes may be omitted, as in `rx`, and `-` alone means none.
+impl FromStr for Permissions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut permissions = Permissions::NONE;
+        for c in s.chars() {
+            let flag = match c {
+                'r' => &mut permissions.read,
+                'w' => &mut permissions.write,
+                'x' => &mut permissions.execute,
+                '-' => continue,
+                _ => return Err(format!("invalid permissions `{}`", s)),
+            };
+            if *flag {
+                return Err(format!("invalid permissions `{}`", s));
+            }
+            *flag = true;
+        }
+        Ok(permissions)
+    }
+}
+
+/// Range of addresses from `start` included to `end` excluded, with its
+/// permissions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub start: usize,
+    pub end: usize,
+    pub permissions: Permissions,
+}
+
+/// Parse a region written as `start:end:permissions`, e.g. `0:152:r-x`.
+/// Addresses are decimal or `0x`-prefixed hexadecimal.
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let address = |text: &str| {
+            parse_number(text)
+                .and_then(|a| usize::try_from(a).ok())
+                .ok_or_else(|| format!("invalid address `{}`", text))
+        };
+        match s.split(':').collect::<Vec<_>>()[..] {
+            [start, end, permissions] => Ok(Region {
+                start: address(start)?,
+                end: address(end)?,
+                permissions: permissions.parse()?,
+            }),
+            _ => Err(format!("invalid region `{}`", s)),
+        }
+    }
+}
+
+impl Region {
+    fn contains(&self, address: usize) -> bool {
+        (self.start..self.end).contains(&address)
+    }
+}
+
+impl Machine {
+    /// Restrict the accesses to the addresses from `start` included to
+    /// `end` excluded to `permissions`.
+    pub fn protect(&mut self, start: usize, end: usize, permissions: Permissions) {
+        self.regions.push(Region {
+            start,
+            end,
+            permissions,
+        });
+        // Instructions cached by the fast engine were checked against the
+        // previous regions
+        self.cache.clear();
+    }
+
+    /// Protected regions, in the order they were added.
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// Remove all the protected regions.
+    pub fn clear_regions(&mut self) {
+        self.regions.clear();
+    }
+
+    /// Check that the `len` bytes starting at `address` allow `access`.
+    pub(crate) fn check_access(
+        &self,
+        address: usize,
+        len: usize,
+        access: Access,
+    ) -> Result<(), MachineError> {
+        if self.regions.is_empty() {
+            return Ok(());
+        }
+        for address in address..address.saturating_add(len) {
+            let allowed = match self.regions.iter().rev().find(|r| r.contains(address)) {
+                Some(region) => region.permissions.allows(access),
+                None => true,
+            };
+            if !allowed {
+                return Err(MachineErrorKind::ProtectionFault { address, access }.into());
+            }
+        }
+        Ok(())
+    }
+}
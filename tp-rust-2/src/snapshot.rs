@@ -0,0 +1,195 @@
+//! Snapshots of the complete machine state, so that a program can be
+//! paused and resumed later, possibly by another process.
+//!
+//! A snapshot is laid out as follows, all integers being little-endian:
+//!
+//! ```text
+//! offset  size      content
+//!      0     8      magic "TPVMSNAP"
+//!      8     2      format version (currently 1)
+//!     10     4      number of registers N
+//!     14     8      memory size M
+//!     22     8      number of executed steps
+//!     30     1      instruction set (0 base, 1 extended)
+//!     31     1      output mode (0 Latin-1, 1 raw)
+//!     32     4      exit status
+//!     36   4*N      registers
+//!  36+4N     M      memory
+//!    end     4      FNV-1a checksum of all the preceding bytes
+//! ```
+//!
+//! The step limit, the deadline, the undo journal, the mapped devices, the
+//! protected regions and the watchpoints are not part of the state: they
+//! must be set up again after a restore.
+
+use crate::{Isa, Machine, OutputMode};
+use std::fmt;
+
+const MAGIC: &[u8; 8] = b"TPVMSNAP";
+const VERSION: u16 = 1;
+const HEADER_SIZE: usize = 36;
+const CHECKSUM_SIZE: usize = 4;
+
+/// Reasons why a snapshot cannot be restored.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The data does not start with the snapshot magic number.
+    BadMagic,
+    /// The snapshot was written by an unknown version of the format.
+    UnsupportedVersion(u16),
+    /// The data ends before the snapshot is complete.
+    Truncated { expected: usize, found: usize },
+    /// Unexpected bytes follow the end of the snapshot.
+    TrailingData(usize),
+    /// The checksum does not match the content.
+    ChecksumMismatch,
+    /// The machine described by the snapshot cannot exist.
+    InvalidGeometry { nregs: u32, memory_size: u64 },
+    /// The instruction set or the output mode is unknown.
+    InvalidSettings { isa: u8, output_mode: u8 },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::BadMagic => write!(f, "not a machine snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+            SnapshotError::Truncated { expected, found } => write!(
+                f,
+                "truncated snapshot: {} bytes expected, {} found",
+                expected, found
+            ),
+            SnapshotError::TrailingData(len) => {
+                write!(f, "{} unexpected bytes after the snapshot", len)
+            }
+            SnapshotError::ChecksumMismatch => write!(f, "corrupted snapshot: bad checksum"),
+            SnapshotError::InvalidGeometry { nregs, memory_size } => write!(
+                f,
+                "invalid snapshot: {} registers and {} bytes of memory",
+                nregs, memory_size
+            ),
+            SnapshotError::InvalidSettings { isa, output_mode } => write!(
+                f,
+                "invalid snapshot: instruction set {} and output mode {}",
+                isa, output_mode
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl Machine {
+    /// Serialize the memory, the registers, the step count, the instruction
+    /// set, the output mode and the exit status.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let regs = self.regs();
+        let memory = self.memory();
+        let mut bytes =
+            Vec::with_capacity(HEADER_SIZE + 4 * regs.len() + memory.len() + CHECKSUM_SIZE);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(regs.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(memory.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&self.steps().to_le_bytes());
+        bytes.push(match self.isa() {
+            Isa::Base => 0,
+            Isa::Extended => 1,
+        });
+        bytes.push(match self.output_mode() {
+            OutputMode::Latin1 => 0,
+            OutputMode::Raw => 1,
+        });
+        bytes.extend_from_slice(&self.exit_status().to_le_bytes());
+        for reg in regs {
+            bytes.extend_from_slice(&reg.to_le_bytes());
+        }
+        bytes.extend_from_slice(memory);
+        let checksum = fnv1a(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    /// Recreate a machine from a snapshot produced by
+    /// [snapshot](Machine::snapshot). The machine has no step limit and
+    /// no deadline.
+    pub fn restore(bytes: &[u8]) -> Result<Machine, SnapshotError> {
+        let truncated = |expected| SnapshotError::Truncated {
+            expected,
+            found: bytes.len(),
+        };
+        if !bytes.starts_with(MAGIC) {
+            return Err(if MAGIC.starts_with(bytes) {
+                truncated(HEADER_SIZE)
+            } else {
+                SnapshotError::BadMagic
+            });
+        }
+        let header = bytes.get(..HEADER_SIZE).ok_or(truncated(HEADER_SIZE))?;
+        let version = u16::from_le_bytes([header[8], header[9]]);
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let nregs = u32::from_le_bytes(header[10..14].try_into().unwrap());
+        let memory_size = u64::from_le_bytes(header[14..22].try_into().unwrap());
+        let steps = u64::from_le_bytes(header[22..30].try_into().unwrap());
+        let (isa, output_mode) = (header[30], header[31]);
+        let exit_status = u32::from_le_bytes(header[32..36].try_into().unwrap());
+
+        // Validate the geometry before allocating anything
+        let invalid = SnapshotError::InvalidGeometry { nregs, memory_size };
+        if nregs == 0 || memory_size > u32::MAX as u64 + 1 {
+            return Err(invalid);
+        }
+        let expected = (nregs as usize)
+            .checked_mul(4)
+            .and_then(|regs| usize::try_from(memory_size).ok()?.checked_add(regs))
+            .and_then(|len| len.checked_add(HEADER_SIZE + CHECKSUM_SIZE))
+            .ok_or(invalid)?;
+        if bytes.len() < expected {
+            return Err(truncated(expected));
+        }
+        if bytes.len() > expected {
+            return Err(SnapshotError::TrailingData(bytes.len() - expected));
+        }
+        let (content, checksum) = bytes.split_at(expected - CHECKSUM_SIZE);
+        if fnv1a(content) != u32::from_le_bytes(checksum.try_into().unwrap()) {
+            return Err(SnapshotError::ChecksumMismatch);
+        }
+
+        let invalid = SnapshotError::InvalidSettings { isa, output_mode };
+        let isa = match isa {
+            0 => Isa::Base,
+            1 => Isa::Extended,
+            _ => return Err(invalid),
+        };
+        let output_mode = match output_mode {
+            0 => OutputMode::Latin1,
+            1 => OutputMode::Raw,
+            _ => return Err(invalid),
+        };
+
+        let (registers, memory) = content[HEADER_SIZE..].split_at(4 * nregs as usize);
+        let mut machine = Machine::with_geometry(memory_size as usize, nregs as usize);
+        for (reg, value) in registers.chunks_exact(4).enumerate() {
+            machine
+                .set_reg(reg, u32::from_le_bytes(value.try_into().unwrap()))
+                .unwrap();
+        }
+        machine.set_mem(0, memory).unwrap();
+        machine.set_steps(steps);
+        machine.set_isa(isa);
+        machine.set_output_mode(output_mode);
+        machine.set_exit_status(exit_status);
+        Ok(machine)
+    }
+}
+
+/// 32-bit FNV-1a hash, enough to detect accidental corruption.
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
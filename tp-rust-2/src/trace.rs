@@ -0,0 +1,120 @@
+//! Execution trace, one line per executed instruction.
+//!
+//! Each line holds the address of the instruction, the instruction itself
+//! and its effects: the register written with its new value, or the
+//! memory word written by a store, e.g.
+//!
+//! ```text
+//! 0016   store [r2] <- r3            [4092] = 0x00000017
+//! 0019   loadimm r0 <- #87           r0 = 0x00000057
+//! ```
+//!
+//! Traces of two versions of a program can thus be compared with `diff`.
+
+use crate::{Instruction, Machine, MachineError, MachineErrorKind};
+use std::io::{self, Read, Write};
+
+impl Machine {
+    /// Run until the program terminates or until an error happens, like
+    /// [run_on](Machine::run_on), while writing the trace of every
+    /// executed instruction on `trace`.
+    pub fn run_traced<T: Write, U: Write>(
+        &mut self,
+        fd: &mut T,
+        trace: &mut U,
+    ) -> Result<u32, MachineError> {
+        self.run_traced_with_io(&mut io::empty(), fd, trace)
+    }
+
+    /// Similar to [run_traced](Machine::run_traced), input instructions
+    /// reading from `input`.
+    pub fn run_traced_with_io<R: Read, T: Write, U: Write>(
+        &mut self,
+        input: &mut R,
+        fd: &mut T,
+        trace: &mut U,
+    ) -> Result<u32, MachineError> {
+        while !self.step_traced_with_io(input, fd, trace)? {}
+        Ok(self.exit_status())
+    }
+
+    /// Similar to [step_on](Machine::step_on), writing the trace of the
+    /// executed instruction on `trace`. If the instruction fails, the
+    /// error is traced as well before being returned.
+    pub fn step_traced<T: Write, U: Write>(
+        &mut self,
+        fd: &mut T,
+        trace: &mut U,
+    ) -> Result<bool, MachineError> {
+        self.step_traced_with_io(&mut io::empty(), fd, trace)
+    }
+
+    /// Similar to [step_traced](Machine::step_traced), input instructions
+    /// reading from `input`.
+    pub fn step_traced_with_io<R: Read, T: Write, U: Write>(
+        &mut self,
+        input: &mut R,
+        fd: &mut T,
+        trace: &mut U,
+    ) -> Result<bool, MachineError> {
+        let address = self.regs()[0] as usize;
+        let before = self.regs().to_vec();
+        let decoded = self
+            .memory()
+            .get(address..)
+            .and_then(|bytes| Instruction::decode_for(bytes, self.isa()).ok());
+        let result = self.step_with_io(input, fd);
+
+        let mut line = match decoded {
+            Some((instruction, _)) => format!("{:04}   {:<28}", address, instruction.to_string()),
+            None => format!("{:04}   {:<28}", address, "???"),
+        };
+        match &result {
+            Ok(_) => match decoded.map(|(instruction, _)| instruction) {
+                Some(Instruction::MoveIf(a, _, c)) if before[c as usize] != 0 => {
+                    line.push_str(&format!(" r{} = {:#010x}", a, self.regs()[a as usize]));
+                }
+                Some(
+                    Instruction::Load(a, _)
+                    | Instruction::LoadImm(a, _)
+                    | Instruction::Sub(a, _, _)
+                    | Instruction::Add(a, _, _)
+                    | Instruction::Mul(a, _, _)
+                    | Instruction::Div(a, _, _)
+                    | Instruction::Mod(a, _, _)
+                    | Instruction::And(a, _, _)
+                    | Instruction::Or(a, _, _)
+                    | Instruction::Xor(a, _, _)
+                    | Instruction::Shl(a, _, _)
+                    | Instruction::Shr(a, _, _)
+                    | Instruction::Sar(a, _, _)
+                    | Instruction::Cmp(a, _, _)
+                    | Instruction::LoadByte(a, _),
+                ) => {
+                    line.push_str(&format!(" r{} = {:#010x}", a, self.regs()[a as usize]));
+                }
+                Some(Instruction::In(a, b) | Instruction::InNumber(a, b)) => {
+                    for reg in [a, b] {
+                        line.push_str(&format!(" r{} = {:#010x}", reg, self.regs()[reg as usize]));
+                    }
+                }
+                Some(Instruction::Store(a, b)) => {
+                    // The stored value may have gone to a device
+                    line.push_str(&format!(
+                        " [{}] = {:#010x}",
+                        before[a as usize], before[b as usize]
+                    ));
+                }
+                Some(Instruction::StoreByte(a, b)) => {
+                    let value = before[b as usize] & 0xff;
+                    line.push_str(&format!(" [{}] = {:#04x}", before[a as usize], value));
+                }
+                _ => (),
+            },
+            Err(e) => line.push_str(&format!(" error: {}", e.kind)),
+        }
+        writeln!(trace, "{}", line.trim_end())
+            .map_err(|e| MachineError::from(MachineErrorKind::WriteToBufferFailed(e)))?;
+        result
+    }
+}
@@ -0,0 +1,178 @@
+//! Watchpoints, stopping the machine when some memory is accessed or some
+//! register changes.
+//!
+//! A watchpoint triggers after the instruction accessing the memory or
+//! changing the register has been executed. The machine then stops with a
+//! [Watchpoint](MachineErrorKind::Watchpoint) error located at this
+//! instruction and describing the access. Unlike other errors, the
+//! instruction is complete: running the machine again resumes after it.
+//!
+//! Only `load`, `store` and their byte variants are watched. Accesses
+//! forwarded to a device are not.
+
+use crate::{Machine, MachineErrorKind};
+use std::fmt;
+
+/// What a watchpoint watches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watchpoint {
+    /// Writes to the addresses from `start` included to `end` excluded.
+    Write { start: usize, end: usize },
+    /// Reads from the addresses from `start` included to `end` excluded.
+    Read { start: usize, end: usize },
+    /// Reads from or writes to the addresses from `start` included to
+    /// `end` excluded.
+    Access { start: usize, end: usize },
+    /// Changes of the value of a register.
+    Register(usize),
+}
+
+impl Watchpoint {
+    fn covers(&self, address: usize, len: usize, write: bool) -> bool {
+        let (start, end) = match *self {
+            Watchpoint::Write { start, end } if write => (start, end),
+            Watchpoint::Read { start, end } if !write => (start, end),
+            Watchpoint::Access { start, end } => (start, end),
+            _ => return false,
+        };
+        address < end && start < address.saturating_add(len)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Watchpoint::Write { start, end } => write!(f, "write {:04}..{:04}", start, end),
+            Watchpoint::Read { start, end } => write!(f, "read {:04}..{:04}", start, end),
+            Watchpoint::Access { start, end } => write!(f, "access {:04}..{:04}", start, end),
+            Watchpoint::Register(reg) => write!(f, "register r{}", reg),
+        }
+    }
+}
+
+/// Access which triggered a watchpoint. Values are words for `load` and
+/// `store`, and bytes for their byte variants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchEvent {
+    Read { address: usize, value: u32 },
+    Write { address: usize, old: u32, new: u32 },
+    Register { reg: usize, old: u32, new: u32 },
+}
+
+impl fmt::Display for WatchEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WatchEvent::Read { address, value } => {
+                write!(f, "read [{}] = {:#010x}", address, value)
+            }
+            WatchEvent::Write { address, old, new } => {
+                write!(f, "write [{}] {:#010x} -> {:#010x}", address, old, new)
+            }
+            WatchEvent::Register { reg, old, new } => {
+                write!(f, "r{} {:#010x} -> {:#010x}", reg, old, new)
+            }
+        }
+    }
+}
+
+/// Watchpoint triggered by an instruction, with the access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub watchpoint: Watchpoint,
+    pub event: WatchEvent,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "watchpoint on {} hit: {}", self.watchpoint, self.event)
+    }
+}
+
+impl Machine {
+    /// Add a watchpoint. Returns `false` if it was already set.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        if self.watchpoints.contains(&watchpoint) {
+            return false;
+        }
+        self.watchpoints.push(watchpoint);
+        true
+    }
+
+    /// Remove a watchpoint. Returns `false` if it was not set.
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|w| *w != watchpoint);
+        self.watchpoints.len() != count
+    }
+
+    /// Current watchpoints, in the order they were added.
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Record a memory access of `len` bytes at `address`, `old` being
+    /// equal to `new` for reads.
+    pub(crate) fn watch_memory(
+        &mut self,
+        address: usize,
+        len: usize,
+        write: bool,
+        old: u32,
+        new: u32,
+    ) {
+        if self.watch_hit.is_some() {
+            return;
+        }
+        if let Some(&watchpoint) = self
+            .watchpoints
+            .iter()
+            .find(|w| w.covers(address, len, write))
+        {
+            let event = if write {
+                WatchEvent::Write { address, old, new }
+            } else {
+                WatchEvent::Read {
+                    address,
+                    value: new,
+                }
+            };
+            self.watch_hit = Some(WatchHit { watchpoint, event });
+        }
+    }
+
+    /// Values of the watched registers, to be compared after the execution
+    /// of an instruction by [check_watchpoints](Machine::check_watchpoints).
+    pub(crate) fn watched_registers(&self) -> Vec<(usize, u32)> {
+        self.watchpoints
+            .iter()
+            .filter_map(|w| match *w {
+                Watchpoint::Register(reg) => Some((reg, *self.registers.get(reg)?)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Report the memory access recorded during the instruction, or else
+    /// the first watched register whose value changed.
+    pub(crate) fn check_watchpoints(
+        &mut self,
+        before: &[(usize, u32)],
+    ) -> Result<(), MachineErrorKind> {
+        if self.watch_hit.is_none() {
+            for &(reg, old) in before {
+                let new = self.registers[reg];
+                if new != old {
+                    self.watch_hit = Some(WatchHit {
+                        watchpoint: Watchpoint::Register(reg),
+                        event: WatchEvent::Register { reg, old, new },
+                    });
+                    break;
+                }
+            }
+        }
+        match self.watch_hit.take() {
+            Some(hit) => Err(MachineErrorKind::Watchpoint(hit)),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use interpreter::analysis::{analyze, analyze_for, Edge, EdgeKind, Issue};
+use interpreter::{assembler, Isa};
+use std::collections::HashMap;
+
+fn edge(target: usize, kind: EdgeKind) -> Edge {
+    Edge { target, kind }
+}
+
+#[test]
+fn fact_graph() {
+    let cfg = analyze(include_bytes!("fact.bin"));
+    assert_eq!(Vec::<Issue>::new(), cfg.issues);
+    assert_eq!(
+        vec![0, 23, 24, 32, 48, 52, 68, 87, 91, 107, 111, 134, 146],
+        cfg.blocks.keys().copied().collect::<Vec<_>>()
+    );
+    // Call of fact
+    let entry = cfg.block(0).unwrap();
+    assert_eq!(6, entry.instructions.len());
+    assert_eq!(vec![edge(87, EdgeKind::Jump)], entry.successors);
+    // Conditional jump through a register holding a constant
+    assert_eq!(
+        vec![edge(48, EdgeKind::FallThrough), edge(52, EdgeKind::Branch)],
+        cfg.block(32).unwrap().successors
+    );
+    // Returns go to the pushed return addresses
+    assert!(cfg.block(68).unwrap().indirect);
+    assert!(cfg.block(68).unwrap().successors.is_empty());
+    assert_eq!(
+        vec![23, 134],
+        cfg.indirect_targets.iter().copied().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn unreachable_code() {
+    let cfg = analyze(include_bytes!("fibo.bin"));
+    assert_eq!(
+        vec![
+            Issue::Unreachable { start: 55, end: 59 },
+            Issue::Unreachable {
+                start: 98,
+                end: 102
+            }
+        ],
+        cfg.issues
+    );
+    // Data following the code is never executed either
+    let cfg = analyze(include_bytes!("../examples/hello_world.bin"));
+    assert_eq!(
+        vec![Issue::Unreachable {
+            start: 148,
+            end: 162
+        }],
+        cfg.issues
+    );
+}
+
+#[test]
+fn invalid_code() {
+    // 0: loadimm r1 <- #7
+    // 4: loadimm r0 <- #6, whose immediate decodes as `out r0`
+    // 8: exit
+    let cfg = analyze(&[4, 1, 7, 0, 4, 0, 6, 0, 7]);
+    assert_eq!(
+        vec![Issue::MidInstruction {
+            from: 4,
+            target: 6,
+            instruction: 4
+        }],
+        cfg.issues
+    );
+    assert_eq!(2, cfg.block(6).unwrap().instructions.len());
+
+    // 0: loadimm r0 <- #5
+    // 4: exit
+    // 5: 99
+    let cfg = analyze(&[4, 0, 5, 0, 7, 99]);
+    assert_eq!(
+        vec![
+            Issue::Unreachable { start: 4, end: 5 },
+            Issue::InvalidOpcode {
+                address: 5,
+                opcode: 99
+            }
+        ],
+        cfg.issues
+    );
+
+    // 0: loadimm r0 <- #4
+    // 4: truncated loadimm
+    let cfg = analyze(&[4, 0, 4, 0, 4, 1]);
+    assert_eq!(vec![Issue::Truncated { address: 4 }], cfg.issues);
+
+    // 0: loadimm r0 <- #100
+    let cfg = analyze(&[4, 0, 100, 0]);
+    assert_eq!(
+        vec![Issue::OutsideCode {
+            from: 0,
+            target: 100
+        }],
+        cfg.issues
+    );
+    // Without exit, execution falls off the end of the code
+    let cfg = analyze(&[6, 1]);
+    assert_eq!(vec![Issue::OutsideCode { from: 0, target: 2 }], cfg.issues);
+}
+
+#[test]
+fn extended_branches() {
+    let source = "
+        in_number r1, r2
+    loop:
+        jz r1, #end
+        out_number r1
+        jmp #loop
+    end:
+        exit
+    ";
+    let code = assembler::assemble(source).unwrap();
+    // The extended opcodes are invalid in the base instruction set
+    assert!(analyze(&code).issues.contains(&Issue::InvalidOpcode {
+        address: 3,
+        opcode: 24
+    }));
+    let cfg = analyze_for(&code, Isa::Extended);
+    assert_eq!(Vec::<Issue>::new(), cfg.issues);
+    assert_eq!(
+        vec![edge(7, EdgeKind::FallThrough), edge(12, EdgeKind::Branch)],
+        cfg.block(3).unwrap().successors
+    );
+    assert_eq!(
+        vec![edge(3, EdgeKind::Jump)],
+        cfg.block(7).unwrap().successors
+    );
+}
+
+#[test]
+fn dot_export() {
+    let (_, labels) = assembler::assemble_with_labels(include_str!("fact.dis")).unwrap();
+    let mut out = Vec::new();
+    analyze(include_bytes!("fact.bin"))
+        .write_dot(&labels, &mut out)
+        .unwrap();
+    let dot = String::from_utf8(out).unwrap();
+    assert!(dot.starts_with("digraph cfg {\n"));
+    assert!(dot.ends_with("}\n"));
+    assert!(dot.contains("    b87 [label=\"0087 <fact>:\\l  0087   loadimm r11 <- #1\\l\"];\n"));
+    assert!(dot.contains("    b0 -> b87 [style=bold];\n"));
+    assert!(dot.contains("    b91 -> b111 [style=dashed];\n"));
+    assert!(dot.contains("    b87 -> b91;\n"));
+
+    // Invalid instructions are shown in red
+    let mut out = Vec::new();
+    analyze(&[4, 0, 5, 0, 7, 99])
+        .write_dot(&HashMap::new(), &mut out)
+        .unwrap();
+    let dot = String::from_utf8(out).unwrap();
+    assert!(dot.contains("    b5 [label=\"0005: invalid opcode 99\", color=red];\n"));
+    assert!(dot.contains("    b0 -> b5 [style=bold];\n"));
+}
@@ -0,0 +1,114 @@
+use interpreter::assembler::{assemble, instruction_lines, AsmError};
+use interpreter::Machine;
+
+macro_rules! check_listing {
+    ($name:ident, $path:literal) => {
+        #[test]
+        fn $name() {
+            let code = assemble(include_str!(concat!($path, ".dis"))).unwrap();
+            assert_eq!(&include_bytes!(concat!($path, ".bin"))[..], &code[..]);
+        }
+    };
+}
+
+check_listing!(assemble_push_pop, "push_pop");
+check_listing!(assemble_function, "function");
+check_listing!(assemble_multiply, "multiply");
+check_listing!(assemble_fact, "fact");
+check_listing!(assemble_afact, "afact");
+check_listing!(assemble_rfact, "rfact");
+check_listing!(assemble_rfact_tr, "rfact_tr");
+check_listing!(assemble_fibo, "fibo");
+check_listing!(assemble_hello_world, "../examples/hello_world");
+check_listing!(assemble_count, "../examples/count");
+check_listing!(assemble_factorial, "../examples/factorial");
+check_listing!(assemble_fibonacci, "../examples/fibonacci");
+check_listing!(assemble_99bottles, "../examples/99bottles");
+check_listing!(assemble_cat, "../examples/cat");
+check_listing!(assemble_sum, "../examples/sum");
+
+#[test]
+fn assemble_without_addresses() {
+    let source = "
+        loadimm r1 <- #-2   ; comment
+        loadimm r0 <- #end
+        out r1
+    end:
+        store [r2] <- r1
+        exit
+        b'a;\\x41\\n'
+    ";
+    let code = assemble(source).unwrap();
+    assert_eq!(
+        &[4, 1, 0xfe, 0xff, 4, 0, 10, 0, 6, 1, 2, 2, 1, 7, b'a', b';', b'A', b'\n'][..],
+        &code[..]
+    );
+    let mut machine = Machine::new(&code);
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(0xfffffffe, machine.regs()[1]);
+}
+
+#[test]
+fn lines_of_instructions() {
+    let source = "start:\n  loadimm r1 <- #2 ; two\n\n  out_number r1\n  b'x'\n  exit\n";
+    assert_eq!(Ok(vec![(2, 0), (4, 4), (6, 7)]), instruction_lines(source));
+    assert!(instruction_lines("jump r1").is_err());
+}
+
+#[test]
+fn assemble_errors() {
+    assert_eq!(
+        Err(AsmError::UnknownLabel {
+            line: 1,
+            label: "nowhere".into()
+        }),
+        assemble("loadimm r0 <- #nowhere")
+    );
+    assert_eq!(
+        Err(AsmError::DuplicateLabel {
+            line: 2,
+            label: "a".into()
+        }),
+        assemble("a:\na:")
+    );
+    assert_eq!(
+        Err(AsmError::ImmediateOutOfRange {
+            line: 1,
+            value: 40000
+        }),
+        assemble("loadimm r1 <- #40000")
+    );
+    assert_eq!(
+        Err(AsmError::InvalidRegister {
+            line: 1,
+            register: "x1".into()
+        }),
+        assemble("out x1")
+    );
+    assert_eq!(
+        Err(AsmError::AddressMismatch {
+            line: 2,
+            expected: 1,
+            found: 2
+        }),
+        assemble("0000 exit\n0002 exit")
+    );
+    assert!(matches!(
+        assemble("jump r1"),
+        Err(AsmError::Syntax { line: 1, .. })
+    ));
+    for number in ["+5", "0x-5", "0x+5", "0x", "-", "--5"] {
+        assert!(
+            matches!(
+                assemble(&format!("loadimm r1 <- #{}", number)),
+                Err(AsmError::Syntax { line: 1, .. })
+            ),
+            "{}",
+            number
+        );
+    }
+    assert_eq!(
+        assemble("loadimm r1 <- #-0x5"),
+        assemble("loadimm r1 <- #-5")
+    );
+}
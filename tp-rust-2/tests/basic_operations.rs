@@ -147,7 +147,7 @@ fn test_store() {
     let mut machine = Machine::new(&[2, 0, 1]);
     machine.set_reg(1, 0x01020304).unwrap();
     expect(&mut machine, false, 3);
-    assert_eq!(&[04, 03, 02, 01], &machine.memory()[3..7]);
+    assert_eq!(&[4, 3, 2, 1], &machine.memory()[3..7]);
 }
 
 #[test]
@@ -291,8 +291,8 @@ fn no_wraparound_past_end_of_memory() {
     // 1:
     let mut memory = Machine::new(&[]).memory().to_vec();
     let memory_size = memory.len();
-    for i in memory_size - 4..memory_size {
-        memory[i] = 1;
+    for byte in &mut memory[memory_size - 4..] {
+        *byte = 1;
     }
     memory[0] = 7;
     let mut machine = Machine::new(&memory);
@@ -0,0 +1,114 @@
+use interpreter::{Machine, MachineBuilder, MachineErrorKind, MEMORY_SIZE, NREGS};
+
+#[test]
+fn default_geometry() {
+    let machine = MachineBuilder::new().program(&[1, 2, 3]).build().unwrap();
+    let reference = Machine::new(&[1, 2, 3]);
+    assert_eq!(MEMORY_SIZE, machine.memory().len());
+    assert_eq!(NREGS, machine.regs().len());
+    assert_eq!(reference.memory(), machine.memory());
+    assert_eq!(reference.regs(), machine.regs());
+}
+
+#[test]
+fn custom_geometry() {
+    // 0x100: exit
+    let mut machine = MachineBuilder::new()
+        .memory_size(64 * 1024)
+        .registers(32)
+        .register(20, 42)
+        .load_address(0x100)
+        .program(&[7])
+        .build()
+        .unwrap();
+    assert_eq!(64 * 1024, machine.memory().len());
+    assert_eq!(32, machine.regs().len());
+    assert_eq!(0x100, machine.regs()[0]);
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(0x101, machine.regs()[0]);
+    assert_eq!(42, machine.regs()[20]);
+}
+
+#[test]
+fn entry_point() {
+    // 0: out_number r1
+    // 2: exit
+    let mut machine = MachineBuilder::new()
+        .program(&[8, 1, 7])
+        .entry_point(2)
+        .register(1, 5)
+        .build()
+        .unwrap();
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    assert!(out.is_empty());
+}
+
+#[test]
+fn registers_bounds_follow_geometry() {
+    // 0: sub r20 <- r20 - r1
+    // 4: exit
+    let code = [5, 20, 20, 1, 7];
+    let mut machine = MachineBuilder::new()
+        .registers(21)
+        .register(1, 1)
+        .program(&code)
+        .build()
+        .unwrap();
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(0xffffffff, machine.regs()[20]);
+
+    let e = Machine::new(&code).run_on(&mut Vec::new()).unwrap_err();
+    assert!(matches!(e.kind, MachineErrorKind::RegisterOutOfBounds(20)));
+}
+
+#[test]
+fn memory_bounds_follow_geometry() {
+    // 0: store [r1] <- r1
+    // 3: exit
+    let code = [2, 1, 1, 7];
+    let mut machine = MachineBuilder::new()
+        .memory_size(8192)
+        .register(1, 8000)
+        .program(&code)
+        .build()
+        .unwrap();
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(&[0x40, 0x1f, 0, 0], &machine.memory()[8000..8004]);
+
+    let mut machine = MachineBuilder::new()
+        .memory_size(16)
+        .register(1, 13)
+        .program(&code)
+        .build()
+        .unwrap();
+    let e = machine.run_on(&mut Vec::new()).unwrap_err();
+    assert!(matches!(
+        e.kind,
+        MachineErrorKind::MemoryIndexOutOfBounds(13)
+    ));
+}
+
+fn build_error(builder: MachineBuilder) -> MachineErrorKind {
+    match builder.build() {
+        Ok(_) => panic!("configuration should be rejected"),
+        Err(e) => e.kind,
+    }
+}
+
+#[test]
+fn invalid_configurations() {
+    let kind = build_error(MachineBuilder::new().registers(0));
+    assert!(matches!(kind, MachineErrorKind::RegisterOutOfBounds(0)));
+
+    let kind = build_error(MachineBuilder::new().register(16, 0));
+    assert!(matches!(kind, MachineErrorKind::RegisterOutOfBounds(16)));
+
+    let kind = build_error(
+        MachineBuilder::new()
+            .memory_size(8)
+            .load_address(4)
+            .program(&[0; 5]),
+    );
+    assert!(matches!(kind, MachineErrorKind::MemoryIndexOutOfBounds(4)));
+}
@@ -0,0 +1,237 @@
+use interpreter::assembler::assemble;
+use std::path::PathBuf;
+use std::process::{self, Command, Output};
+
+/// Write `code` to a file of the temporary directory unique to the test.
+fn binary(name: &str, code: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("tp-rust-2-cli-{}-{}.bin", process::id(), name));
+    std::fs::write(&path, code).unwrap();
+    path
+}
+
+fn program(name: &str, source: &str) -> PathBuf {
+    binary(name, &assemble(source).unwrap())
+}
+
+fn tp(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_tp-rust-2"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+#[test]
+fn preset_registers_and_dumps() {
+    let fact = "tests/fact.bin";
+    let output = tp(&["run", "--reg", "r10=5", "--dump-regs", fact]);
+    assert_eq!(Some(0), output.status.code());
+    assert!(stderr(&output).contains("r11 = 0x00000078 (120)\n"));
+
+    let output = tp(&["--reg", "r10=0x3", "--dump-mem", "4088:4096", fact]);
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("4088: 86 00 00 00 17 00 00 00\n", stderr(&output));
+}
+
+#[test]
+fn output_file() {
+    let hello = program(
+        "hello",
+        "loadimm r1 <- #0x48\nout r1\nloadimm r1 <- #0x69\nout r1\nexit",
+    );
+    let path = hello.with_extension("out");
+    let output = tp(&[
+        "run",
+        "--output",
+        path.to_str().unwrap(),
+        hello.to_str().unwrap(),
+    ]);
+    assert_eq!(Some(0), output.status.code());
+    assert!(output.stdout.is_empty());
+    assert_eq!(b"Hi", &std::fs::read(&path).unwrap()[..]);
+
+    let output = tp(&[hello.to_str().unwrap()]);
+    assert_eq!(b"Hi", &output.stdout[..]);
+}
+
+#[test]
+fn trace() {
+    let exit = program("exit", "exit");
+    let output = tp(&["trace", exit.to_str().unwrap()]);
+    assert_eq!(Some(0), output.status.code());
+    assert!(stderr(&output).contains("exit"));
+}
+
+#[test]
+fn machine_error_status() {
+    let invalid = binary("invalid", &[99]);
+    let output = tp(&["run", invalid.to_str().unwrap()]);
+    assert_eq!(Some(82), output.status.code());
+    assert!(stderr(&output).starts_with("error: "));
+
+    let output = tp(&[
+        "run",
+        "--reg",
+        "r10=1000",
+        "--max-steps",
+        "10",
+        "tests/fact.bin",
+    ]);
+    assert_eq!(Some(87), output.status.code());
+
+    let division = program("division", "div r1 <- r1 / r2\nexit");
+    let output = tp(&["run", division.to_str().unwrap()]);
+    assert_eq!(Some(82), output.status.code());
+    let output = tp(&["run", "--extended", division.to_str().unwrap()]);
+    assert_eq!(Some(90), output.status.code());
+
+    let store = program("store", "loadimm r1 <- #5000\nstore [r1] <- r1\nexit");
+    let output = tp(&["run", "--fast", store.to_str().unwrap()]);
+    assert_eq!(Some(81), output.status.code());
+}
+
+#[test]
+fn program_status() {
+    let exit = program("status", "jnz r1, #4\nloadimm r1 <- #3\nexit r1");
+    let output = tp(&["run", exit.to_str().unwrap()]);
+    assert_eq!(Some(82), output.status.code());
+    let output = tp(&["run", "--extended", exit.to_str().unwrap()]);
+    assert_eq!(Some(3), output.status.code());
+    assert!(output.stderr.is_empty());
+    let output = tp(&["trace", "--extended", exit.to_str().unwrap()]);
+    assert_eq!(Some(3), output.status.code());
+
+    let run = |status: &str| {
+        tp(&[
+            "run",
+            "--extended",
+            "--reg",
+            &format!("r1={}", status),
+            exit.to_str().unwrap(),
+        ])
+    };
+    for (status, expected) in [
+        ("63", 63),
+        ("100", 100),
+        ("255", 255),
+        ("300", 44),
+        ("-1", 255),
+    ] {
+        assert_eq!(Some(expected), run(status).status.code());
+    }
+    // Statuses of the command cannot be confused with those of programs
+    for status in ["64", "65", "80", "92", "320"] {
+        let output = run(status);
+        assert_eq!(Some(65), output.status.code());
+        assert!(stderr(&output).contains(&format!("exit status {} is reserved", status)));
+    }
+    assert_eq!(Some(93), run("93").status.code());
+}
+
+#[test]
+fn resume_extended_program() {
+    let count = program("count", "loadimm r1 <- #3\nloadimm r2 <- #1\nloop:\nout_number r1\nsub r1 <- r1 - r2\njnz r1, #loop\nexit r2");
+    let state = count.with_extension("state");
+    let output = tp(&[
+        "run",
+        "--extended",
+        "--max-steps",
+        "4",
+        "--save-state",
+        state.to_str().unwrap(),
+        count.to_str().unwrap(),
+    ]);
+    assert_eq!(Some(87), output.status.code());
+    assert_eq!(b"3", &output.stdout[..]);
+    let output = tp(&["run", "--load-state", state.to_str().unwrap()]);
+    assert_eq!(Some(1), output.status.code());
+    assert_eq!(b"21", &output.stdout[..]);
+}
+
+#[test]
+fn compiled_program() {
+    let source = binary("compiled", &[]).with_extension("src");
+    std::fs::write(&source, "fn main() { print \"6 * 7 = \", 6 * 7; }").unwrap();
+    let output = tp(&["compile", source.to_str().unwrap()]);
+    assert_eq!(Some(0), output.status.code());
+    assert!(stderr(&output).contains("run it with --extended"));
+
+    let program = source.with_extension("bin");
+    let output = tp(&["run", program.to_str().unwrap()]);
+    assert_eq!(Some(82), output.status.code());
+    assert!(output.stdout.is_empty());
+    assert!(stderr(&output).contains("belongs to the extended instruction set"));
+    let output = tp(&["run", "--extended", program.to_str().unwrap()]);
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(b"6 * 7 = 42\n", &output.stdout[..]);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn failed_output() {
+    use std::fs::File;
+    use std::process::Stdio;
+
+    // Every write to this device fails
+    let full = || Stdio::from(File::options().write(true).open("/dev/full").unwrap());
+    let output = Command::new(env!("CARGO_BIN_EXE_tp-rust-2"))
+        .args(["dis", "tests/fact.bin"])
+        .stdout(full())
+        .output()
+        .unwrap();
+    assert_eq!(Some(65), output.status.code());
+    assert!(stderr(&output).starts_with("stdout: "));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_tp-rust-2"))
+        .args(["run", "--profile", "--reg", "r10=3", "tests/fact.bin"])
+        .stderr(full())
+        .output()
+        .unwrap();
+    assert_eq!(Some(65), output.status.code());
+}
+
+#[test]
+fn usage_errors() {
+    let output = tp(&[]);
+    assert_eq!(Some(64), output.status.code());
+    assert!(stderr(&output).starts_with("usage: "));
+
+    let output = tp(&["help"]);
+    assert_eq!(Some(0), output.status.code());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("--dump-regs"));
+
+    for args in [
+        &["run"][..],
+        &["run", "--frobnicate", "tests/fact.bin"],
+        &["run", "--reg", "r10", "tests/fact.bin"],
+        &["run", "--reg", "r10=0x100000000", "tests/fact.bin"],
+        &["run", "--reg", "r16=1", "tests/fact.bin"],
+        &["run", "--dump-mem", "12", "tests/fact.bin"],
+        &["run", "--max-steps", "tests/fact.bin"],
+        &["run", "--timeout", "-1", "tests/fact.bin"],
+        &["run", "--timeout", "NaN", "tests/fact.bin"],
+        &["run", "--timeout", "inf", "tests/fact.bin"],
+        &["run", "tests/fact.bin", "extra"],
+        &["debug", "--reg"],
+        &["run", "--trace", "--fast", "tests/fact.bin"],
+        &["trace", "--profile", "tests/fact.bin"],
+    ] {
+        assert_eq!(Some(64), tp(args).status.code(), "{:?}", args);
+    }
+
+    let large = binary("large", &[7; 4097]);
+    for command in ["run", "debug", "gdb"] {
+        let output = tp(&[command, large.to_str().unwrap()]);
+        assert_eq!(Some(65), output.status.code());
+        assert!(stderr(&output).contains("does not fit"));
+    }
+
+    let output = tp(&["run", "tests/does-not-exist.bin"]);
+    assert_eq!(Some(65), output.status.code());
+    assert!(stderr(&output).starts_with("tests/does-not-exist.bin: "));
+}
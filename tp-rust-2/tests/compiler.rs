@@ -0,0 +1,287 @@
+use interpreter::assembler::{assemble, assemble_with_labels};
+use interpreter::compiler::{self, compile, compile_to_listing, CompileError};
+use interpreter::{Machine, MachineErrorKind};
+
+/// Output of the compiled program.
+fn run(source: &str) -> String {
+    let code = compile(source).unwrap();
+    let mut machine = Machine::new(&code);
+    machine.set_isa(compiler::ISA);
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn recursive_factorial() {
+    let source = "
+        // Factorials from 1 to 5
+        fn fact(n) {
+            if n <= 1 {
+                return 1;
+            }
+            return n * fact(n - 1);
+        }
+
+        fn main() {
+            var i = 1;
+            while i <= 5 {
+                print i, \"! = \", fact(i);
+                i = i + 1;
+            }
+        }
+    ";
+    assert_eq!("1! = 1\n2! = 2\n3! = 6\n4! = 24\n5! = 120\n", run(source));
+}
+
+#[test]
+fn arithmetic() {
+    let source = "
+        fn main() {
+            print 1 + 2 * 3, \" \", (1 + 2) * 3, \" \", 7 / 2, \" \", -7 % 3;
+            print 10 - 4 - 3, \" \", -(2 - 5), \" \", 100000 * 3, \" \", 2147483647 + 1;
+            print 4294967295, \" \", 65535, \" \", 32768, \" \", -32769;
+        }
+    ";
+    assert_eq!(
+        "7 9 3 -1\n3 3 300000 -2147483648\n-1 65535 32768 -32769\n",
+        run(source)
+    );
+}
+
+#[test]
+fn comparisons_and_logic() {
+    let source = "
+        fn check(a, b) {
+            print a < b, a <= b, a > b, a >= b, a == b, a != b;
+        }
+
+        fn loud(x) {
+            print \"loud \", x;
+            return x;
+        }
+
+        fn main() {
+            check(1, 2);
+            check(2, 2);
+            check(-3, 2);
+            check(2147483647, -2147483647);
+            print !0, !5, 3 && 4, 0 && loud(1), 0 || 0, 2 || loud(2);
+            print loud(0) || loud(3);
+        }
+    ";
+    assert_eq!(
+        "110001\n010110\n110001\n001101\n101001\nloud 0\nloud 3\n1\n",
+        run(source)
+    );
+}
+
+#[test]
+fn blocks_and_recursion() {
+    let source = "
+        fn fib(n) {
+            if n < 2 {
+                return n;
+            }
+            return fib(n - 1) + fib(n - 2);
+        }
+
+        fn collatz(n) {
+            var steps = 0;
+            while n != 1 {
+                if n % 2 == 0 {
+                    n = n / 2;
+                } else if n % 3 == 0 {
+                    n = 3 * n + 1;
+                } else {
+                    n = 3 * n + 1;
+                }
+                steps = steps + 1;
+            }
+            return steps;
+        }
+
+        fn nothing() {
+        }
+
+        fn main() {
+            var x = 1;
+            if x {
+                var x = x + 10;
+                print x;
+            }
+            print x, \" \", fib(15), \" \", collatz(27), \" \", nothing();
+            // Intermediate values survive calls
+            print 1 + (2 + (3 + fib(5) * fib(6)));
+        }
+    ";
+    assert_eq!("11\n1 610 111 0\n46\n", run(source));
+}
+
+#[test]
+fn calling_convention() {
+    let listing = compile_to_listing(
+        "
+        fn main() {
+            print answer();
+        }
+
+        fn answer() {
+            return 42;
+        }
+    ",
+    )
+    .unwrap();
+    let (code, labels) = assemble_with_labels(&listing).unwrap();
+    assert!(labels.contains_key("main"));
+    assert!(labels.contains_key("answer"));
+    assert!(labels
+        .keys()
+        .all(|l| l == "main" || l == "answer" || l.starts_with('_')));
+    // Same start as the existing programs, after an extended instruction
+    // failing at once without the extended instruction set
+    assert_eq!(
+        assemble(
+            "
+            xor r3 <- r3 ^ r3
+            loadimm r2 <- #4096
+            loadimm r3 <- #4
+            sub r2 <- r2 - r3
+        "
+        )
+        .unwrap(),
+        code[..16]
+    );
+    assert!(listing.contains("    loadimm r0 <- #answer\n"));
+    assert!(listing.ends_with("    load r0 <- [r3]\n"));
+}
+
+#[test]
+fn unicode_text() {
+    // Characters from U+8000 on and outside of the BMP are loaded in two
+    // halves
+    let source = "fn main() { print \"é € ！ 😀\"; }";
+    assert_eq!("é € ！ 😀\n", run(source));
+    assert_eq!(
+        "\u{8000}\u{10ffff}\n",
+        run("fn main() { print \"\u{8000}\u{10ffff}\"; }")
+    );
+}
+
+#[test]
+fn exit_status() {
+    let status = |source: &str| {
+        let code = compile(source).unwrap();
+        let mut machine = Machine::new(&code);
+        machine.set_isa(compiler::ISA);
+        machine.run_on(&mut Vec::new()).unwrap()
+    };
+    assert_eq!(42, status("fn main() { return 6 * 7; }"));
+    assert_eq!(-1i32 as u32, status("fn main() { return -1; }"));
+    assert_eq!(0, status("fn main() { var x = 3; }"));
+}
+
+#[test]
+fn runtime_errors() {
+    let code = compile("fn main() { print 1 / (2 - 2); }").unwrap();
+    let mut machine = Machine::new(&code);
+    machine.set_isa(compiler::ISA);
+    let e = machine.run_on(&mut Vec::new()).unwrap_err();
+    assert!(matches!(e.kind, MachineErrorKind::DivisionByZero));
+
+    // Without the extended instruction set, before any output
+    let code = compile("fn main() { print \"1 / 0 = \", 1 / 0; }").unwrap();
+    let mut machine = Machine::new(&code);
+    let mut out = Vec::new();
+    let e = machine.run_on(&mut out).unwrap_err();
+    assert!(matches!(e.kind, MachineErrorKind::InvalidOpcode(_)));
+    assert_eq!(Some(0), e.ip);
+    assert!(out.is_empty());
+}
+
+#[test]
+fn compile_errors() {
+    let error = |source: &str| compile(source).unwrap_err();
+    assert_eq!(
+        CompileError::UnexpectedCharacter {
+            line: 2,
+            character: '@'
+        },
+        error("fn main() {\n @ }")
+    );
+    assert_eq!(
+        CompileError::UnexpectedToken {
+            line: 1,
+            found: "`}`".to_string(),
+            expected: "`;`".to_string()
+        },
+        error("fn main() { print 1 }")
+    );
+    assert_eq!(
+        "line 1: expected expression, found end of input",
+        error("fn main() { print 1 +").to_string()
+    );
+    assert_eq!(
+        CompileError::UnterminatedString { line: 1 },
+        error("fn main() { print \"abc\n\"; }")
+    );
+    assert_eq!(
+        CompileError::InvalidNumber {
+            line: 1,
+            text: "4294967296".to_string()
+        },
+        error("fn main() { print 4294967296; }")
+    );
+    assert_eq!(
+        CompileError::UnknownVariable {
+            line: 3,
+            name: "y".to_string()
+        },
+        error("fn main() {\n if 1 { var y = 1; }\n print y;\n}")
+    );
+    assert_eq!(
+        CompileError::DuplicateVariable {
+            line: 1,
+            name: "x".to_string()
+        },
+        error("fn main() { var x = 1; var x = 2; }")
+    );
+    assert_eq!(
+        CompileError::DuplicateVariable {
+            line: 1,
+            name: "a".to_string()
+        },
+        error("fn f(a, a) {} fn main() {}")
+    );
+    assert_eq!(
+        CompileError::UnknownFunction {
+            line: 1,
+            name: "g".to_string()
+        },
+        error("fn main() { g(); }")
+    );
+    assert_eq!(
+        CompileError::DuplicateFunction {
+            line: 2,
+            name: "main".to_string()
+        },
+        error("fn main() {}\nfn main() {}")
+    );
+    assert_eq!(
+        "line 1: function `f` takes 1 arguments but 2 were given",
+        error("fn f(a) {} fn main() { f(1, 2); }").to_string()
+    );
+    assert_eq!(CompileError::MissingMain, error("fn main(argc) {}"));
+    assert_eq!(
+        "line 1: expected name, found `while`",
+        error("fn main() { var while = 1; }").to_string()
+    );
+    assert_eq!(
+        CompileError::ExpressionTooComplex { line: 1 },
+        error("fn main() { print 1 + (1 + (1 + (1 + (1 + (1 + (1 + 1)))))); }")
+    );
+    assert_eq!(
+        "4",
+        run("fn main() { print 1 + (1 + (1 + (1 * 1))); }").trim()
+    );
+}
@@ -0,0 +1,89 @@
+use interpreter::{assembler, coverage::Coverage, Machine};
+
+fn fact_coverage(inputs: impl IntoIterator<Item = u32>) -> Coverage {
+    let mut coverage = Coverage::default();
+    for n in inputs {
+        let mut machine = Machine::new(include_bytes!("fact.bin"));
+        machine.set_coverage(true);
+        machine.set_reg(10, n).unwrap();
+        machine.run().unwrap();
+        coverage.merge(machine.coverage().unwrap());
+    }
+    coverage
+}
+
+#[test]
+fn disabled_by_default() {
+    let mut machine = Machine::new(include_bytes!("fact.bin"));
+    assert!(machine.coverage().is_none());
+    machine.set_coverage(true);
+    machine.step().unwrap();
+    assert_eq!(1, machine.coverage().unwrap().hits(0));
+    // Enabling again keeps the hits
+    machine.set_coverage(true);
+    assert_eq!(1, machine.coverage().unwrap().hits(0));
+    machine.set_coverage(false);
+    assert!(machine.coverage().is_none());
+}
+
+#[test]
+fn partial_coverage() {
+    let report = fact_coverage([1])
+        .annotate(include_str!("fact.dis"))
+        .unwrap();
+    assert_eq!(43, report.total());
+    assert_eq!(18, report.covered());
+    assert_eq!(25, report.uncovered().len());
+    assert_eq!(Some(&24), report.uncovered().first());
+    let text = report.to_string();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(
+        vec![
+            "       1 |   0019   loadimm r0 <- #fact",
+            "         | return_from_fact_1:",
+            "       1 |   0023   exit",
+            "         | mult:",
+            "   ##### |   0024   sub r13 <- r1 - r11",
+        ],
+        lines[5..10]
+    );
+    assert_eq!(Some(&"coverage: 18/43 instructions (41.86%)"), lines.last());
+}
+
+#[test]
+fn full_coverage_of_fact() {
+    let coverage = fact_coverage(1..13);
+    let report = coverage.annotate(include_str!("fact.dis")).unwrap();
+    assert_eq!(Vec::<usize>::new(), report.uncovered());
+    assert_eq!(100.0, report.percentage());
+    // The loop of fact runs once per input
+    assert_eq!(78, coverage.hits(91));
+}
+
+#[test]
+fn same_hits_with_fast_engine() {
+    let run = |fast: bool| {
+        let mut machine = Machine::new(include_bytes!("rfact.bin"));
+        machine.set_coverage(true);
+        machine.set_reg(10, 6).unwrap();
+        if fast {
+            machine.run_fast().unwrap();
+        } else {
+            machine.run().unwrap();
+        }
+        machine.coverage().unwrap().clone()
+    };
+    assert_eq!(run(false), run(true));
+}
+
+#[test]
+fn data_is_not_counted() {
+    let listing = "  0000   loadimm r1 <- #text\n  0004   out_number r1\n  0006   exit\ntext:\n  0007   b'hi'\n";
+    let mut machine = Machine::new(&assembler::assemble(listing).unwrap());
+    machine.set_coverage(true);
+    machine.run_on(&mut Vec::new()).unwrap();
+    let report = machine.coverage().unwrap().annotate(listing).unwrap();
+    assert_eq!(3, report.total());
+    assert_eq!(3, report.covered());
+    assert!(report.to_string().contains("\n         |   0007   b'hi'\n"));
+}
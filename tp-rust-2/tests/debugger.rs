@@ -0,0 +1,143 @@
+use interpreter::assembler::assemble_with_labels;
+use interpreter::debugger::{Debugger, Stop};
+use interpreter::{Machine, MachineError, MachineErrorKind, WatchEvent, Watchpoint};
+
+fn fact_debugger(n: u32) -> Debugger {
+    let (code, labels) = assemble_with_labels(include_str!("fact.dis")).unwrap();
+    let mut machine = Machine::new(&code);
+    machine.set_reg(10, n).unwrap();
+    Debugger::new(machine).with_labels(labels)
+}
+
+#[test]
+fn breakpoint_on_label() {
+    let mut debugger = fact_debugger(5);
+    let mult = debugger.resolve("mult").unwrap();
+    assert_eq!(24, mult);
+    assert!(debugger.add_breakpoint(mult));
+    let mut out = Vec::new();
+    let mut hits = 0;
+    loop {
+        match debugger.continue_on(&mut out) {
+            Stop::Breakpoint(address) => {
+                assert_eq!(mult, address);
+                hits += 1;
+            }
+            Stop::Exit => break,
+            stop => panic!("unexpected {:?}", stop),
+        }
+    }
+    assert_eq!(4, hits);
+    assert_eq!(120, debugger.machine().regs()[11]);
+    // Nothing runs anymore once the program has exited
+    assert!(matches!(debugger.step_on(&mut out), Stop::Exit));
+}
+
+#[test]
+fn error_keeps_faulting_address() {
+    // 0: exit
+    // 1: sub r100 <- r0 - r0
+    let mut machine = Machine::new(&[7, 5, 100, 0, 0]);
+    machine.set_reg(0, 1).unwrap();
+    let mut debugger = Debugger::new(machine);
+    match debugger.step_on(&mut Vec::new()) {
+        Stop::Error(
+            1,
+            MachineError {
+                kind: MachineErrorKind::RegisterOutOfBounds(100),
+                ..
+            },
+        ) => (),
+        stop => panic!("unexpected {:?}", stop),
+    }
+    assert_eq!(1, debugger.machine().regs()[0]);
+}
+
+#[test]
+fn scripted_session() {
+    let mut debugger = fact_debugger(3);
+    let script = "b fact\nc\nr\nset r10 4\nl\nd fact\nc\nx 4092 4\nq\n";
+    let mut out = Vec::new();
+    debugger.repl(script.as_bytes(), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("breakpoint at 0087 <fact>"));
+    assert!(out.contains("breakpoint reached at 0087 <fact>: loadimm r11 <- #1"));
+    assert!(out.contains("r10 = 0x00000003 (3)"));
+    assert!(out.contains("fact:\n=> 0087   loadimm r11 <- #1"));
+    assert!(out.contains("breakpoint at 0087 <fact> removed"));
+    assert!(out.contains("program exited with status 0\n"));
+    // Return address pushed by the initial call
+    assert!(out.contains("4092: 17 00 00 00"));
+    assert_eq!(24, debugger.machine().regs()[11]);
+}
+
+#[test]
+fn empty_line_repeats_command() {
+    let mut debugger = fact_debugger(1);
+    debugger
+        .repl("s\n\n\n".as_bytes(), &mut Vec::new())
+        .unwrap();
+    assert_eq!(12, debugger.machine().regs()[0]);
+}
+
+#[test]
+fn reverse_execution() {
+    let mut debugger = fact_debugger(3);
+    let mut out = Vec::new();
+    assert!(matches!(debugger.continue_on(&mut out), Stop::Exit));
+    assert!(debugger.step_back());
+    assert!(matches!(debugger.step_on(&mut out), Stop::Exit));
+
+    let script = "rw fact\nrs 2\nrw 3\nq\n";
+    let mut out = Vec::new();
+    debugger.repl(script.as_bytes(), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("stopped at 0087 <fact>: loadimm r11 <- #1"));
+    assert!(out.contains("stopped at 0016: store [r2] <- r3"));
+    assert!(out.contains("no more history, stopped at 0000: "));
+    assert_eq!(0, debugger.machine().steps());
+}
+
+#[test]
+fn watchpoints() {
+    let mut debugger = fact_debugger(3);
+    let mut out = Vec::new();
+    assert!(debugger.machine_mut().add_watchpoint(Watchpoint::Write {
+        start: 4092,
+        end: 4096
+    }));
+    match debugger.continue_on(&mut out) {
+        Stop::Watchpoint(16, hit) => assert_eq!(
+            WatchEvent::Write {
+                address: 4092,
+                old: 0,
+                new: 23
+            },
+            hit.event
+        ),
+        stop => panic!("unexpected {:?}", stop),
+    }
+    // Stopped after the store
+    assert_eq!(19, debugger.machine().regs()[0]);
+    debugger.machine_mut().remove_watchpoint(Watchpoint::Write {
+        start: 4092,
+        end: 4096,
+    });
+
+    let script = "watch r11\nrwatch 4088 4\ni\nc\nunwatch r11\nc\nunwatch 4088\nunwatch r1\nc\nq\n";
+    let mut out = Vec::new();
+    debugger.repl(script.as_bytes(), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("watchpoint on register r11\nwatchpoint on read 4088..4092\n"));
+    assert!(out.contains(
+        "watchpoint on register r11 hit by 0087 <fact>: loadimm r11 <- #1\n  \
+         r11 0x00000000 -> 0x00000001\nstopped at 0091 <fact_loop>: loadimm r8 <- #1\n"
+    ));
+    assert!(out.contains("watchpoint on register r11 removed"));
+    assert!(out.contains("watchpoint on read 4088..4092 hit by 0084: load r0 <- [r3]\n"));
+    assert!(out.contains("  read [4088] = 0x00000086\n"));
+    assert!(out.contains("watchpoint on read 4088..4092 removed"));
+    assert!(out.contains("no watchpoint on `r1`"));
+    assert!(out.contains("program exited"));
+    assert_eq!(6, debugger.machine().regs()[11]);
+}
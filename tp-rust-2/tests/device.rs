@@ -0,0 +1,191 @@
+use interpreter::assembler::assemble;
+use interpreter::device::{
+    Console, CycleCounter, Device, Random, CONSOLE_ADDRESS, CYCLE_COUNTER_ADDRESS, RANDOM_ADDRESS,
+};
+use interpreter::{Machine, MachineErrorKind};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// Output which stays readable after being moved into a device.
+#[derive(Clone, Default)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+const ECHO: &str = "
+  loadimm r1 <- #-256         ; console
+  loadimm r6 <- #-1
+loop:
+  load r4 <- [r1]
+  sub r5 <- r4 - r6           ; 0 at end of input
+  loadimm r7 <- #echo
+  move r0 <- r7 if r5 != 0
+  exit
+echo:
+  store [r1] <- r4
+  loadimm r0 <- #loop
+";
+
+#[test]
+fn console_echo() {
+    let output = SharedOutput::default();
+    let mut machine = Machine::new(&assemble(ECHO).unwrap());
+    machine
+        .map_device(
+            CONSOLE_ADDRESS,
+            Box::new(Console::new(&b"hello\n"[..], output.clone())),
+        )
+        .unwrap();
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    assert!(out.is_empty());
+    assert_eq!(b"hello\n", &output.0.borrow()[..]);
+}
+
+#[test]
+fn cycle_counter() {
+    let code = assemble(
+        "
+  loadimm r1 <- #-240         ; cycle counter
+  loadimm r2 <- #-236         ; high half
+  load r3 <- [r1]
+  load r4 <- [r2]
+  store [r1] <- r1
+  loadimm r5 <- #0
+  load r5 <- [r1]
+  exit
+",
+    )
+    .unwrap();
+    let mut machine = Machine::new(&code);
+    machine
+        .map_device(CYCLE_COUNTER_ADDRESS, Box::new(CycleCounter::new()))
+        .unwrap();
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(2, machine.regs()[3]);
+    assert_eq!(0, machine.regs()[4]);
+    assert_eq!(2, machine.regs()[5]);
+}
+
+#[test]
+fn cycle_counter_after_step_back() {
+    let code = assemble(
+        "
+  loadimm r1 <- #-240         ; cycle counter
+  store [r1] <- r1
+  load r3 <- [r1]
+  exit
+",
+    )
+    .unwrap();
+    let mut machine = Machine::new(&code);
+    machine
+        .map_device(CYCLE_COUNTER_ADDRESS, Box::new(CycleCounter::new()))
+        .unwrap();
+    machine.set_history_limit(usize::MAX);
+    machine.step_on(&mut Vec::new()).unwrap();
+    machine.step_on(&mut Vec::new()).unwrap();
+    // Back before the restart, which stepping back does not undo
+    assert!(machine.step_back());
+    assert!(machine.step_back());
+    machine.set_reg(0, 7).unwrap();
+    machine.set_reg(1, -240i32 as u32).unwrap();
+    machine.step_on(&mut Vec::new()).unwrap();
+    assert_eq!(0, machine.regs()[3]);
+}
+
+#[test]
+fn random_is_reproducible() {
+    let mut a = Random::new(42);
+    let mut b = Random::new(42);
+    let first: Vec<u32> = (0..10).map(|_| a.load(0, 0).unwrap()).collect();
+    let second: Vec<u32> = (0..10).map(|_| b.load(0, 0).unwrap()).collect();
+    assert_eq!(first, second);
+    assert!(first.windows(2).all(|w| w[0] != w[1]));
+
+    // Reseeding restarts the sequence, even with a zero seed
+    b.store(0, 42, 0).unwrap();
+    assert_eq!(first[0], b.load(0, 0).unwrap());
+    let mut zero = Random::new(0);
+    assert_ne!(0, zero.load(0, 0).unwrap());
+}
+
+#[test]
+fn random_from_program() {
+    // 0: loadimm r1 <- #-224
+    // 4: load r2 <- [r1]
+    // 7: exit
+    let mut machine = Machine::new(&[4, 1, 0x20, 0xff, 3, 2, 1, 7]);
+    machine
+        .map_device(RANDOM_ADDRESS, Box::new(Random::new(7)))
+        .unwrap();
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(Random::new(7).load(0, 0).unwrap(), machine.regs()[2]);
+}
+
+#[test]
+fn device_shadows_memory() {
+    // 0: store [r1] <- r2
+    // 3: load r3 <- [r1]
+    // 6: exit
+    let mut machine = Machine::new(&[2, 1, 2, 3, 3, 1, 7]);
+    machine.set_reg(1, 100).unwrap();
+    machine.set_reg(2, 5).unwrap();
+    machine
+        .map_device(100, Box::new(CycleCounter::new()))
+        .unwrap();
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(&[0; 8], &machine.memory()[100..108]);
+    assert_eq!(1, machine.regs()[3]);
+}
+
+#[test]
+fn invalid_device_offset() {
+    // 0: load r3 <- [r1]
+    let mut machine = Machine::new(&[3, 3, 1]);
+    machine.set_reg(1, 102).unwrap();
+    machine
+        .map_device(100, Box::new(CycleCounter::new()))
+        .unwrap();
+    let e = machine.run_on(&mut Vec::new()).unwrap_err();
+    assert!(matches!(
+        e.kind,
+        MachineErrorKind::MemoryIndexOutOfBounds(102)
+    ));
+    assert_eq!(Some(0), e.ip);
+}
+
+#[test]
+fn mapping_errors() {
+    let mut machine = Machine::new(&[]);
+    machine
+        .map_device(CYCLE_COUNTER_ADDRESS, Box::new(CycleCounter::new()))
+        .unwrap();
+    let e = machine
+        .map_device(CYCLE_COUNTER_ADDRESS - 2, Box::new(Random::new(1)))
+        .unwrap_err();
+    assert!(matches!(
+        e.kind,
+        MachineErrorKind::AddressAlreadyMapped(CYCLE_COUNTER_ADDRESS)
+    ));
+    let e = machine
+        .map_device(0xffff_fffe, Box::new(Random::new(1)))
+        .unwrap_err();
+    assert!(matches!(
+        e.kind,
+        MachineErrorKind::MemoryIndexOutOfBounds(0xffff_fffe)
+    ));
+    // Adjacent ranges are allowed
+    machine
+        .map_device(CYCLE_COUNTER_ADDRESS + 8, Box::new(Random::new(1)))
+        .unwrap();
+}
@@ -0,0 +1,112 @@
+use interpreter::assembler::assemble;
+use interpreter::disassembler::{decode_at, decode_at_for, disassemble, disassemble_for, Line};
+use interpreter::{Instruction, Isa};
+
+fn listing(code: &[u8]) -> String {
+    disassemble(code)
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[test]
+fn disassemble_like_listing() {
+    // push_pop.dis contains no label, so it must be reproduced exactly
+    assert_eq!(
+        include_str!("push_pop.dis"),
+        listing(include_bytes!("push_pop.bin"))
+    );
+}
+
+#[test]
+fn roundtrip_through_assembler() {
+    for code in [
+        &include_bytes!("fact.bin")[..],
+        include_bytes!("afact.bin"),
+        include_bytes!("rfact_tr.bin"),
+        include_bytes!("fibo.bin"),
+        include_bytes!("../examples/99bottles.bin"),
+        include_bytes!("../examples/factorial.bin"),
+    ] {
+        assert_eq!(code, &assemble(&listing(code)).unwrap()[..]);
+    }
+}
+
+#[test]
+fn decode_every_opcode() {
+    let code = [
+        1, 1, 2, 3, 2, 4, 5, 3, 6, 7, 4, 8, 0xfe, 0xff, 5, 9, 10, 11, 6, 12, 7, 8, 13,
+    ];
+    let texts: Vec<String> = disassemble(&code)
+        .into_iter()
+        .map(|line| match line {
+            Line::Instruction { instruction, .. } => instruction.to_string(),
+            Line::Data { .. } => panic!("unexpected data"),
+        })
+        .collect();
+    assert_eq!(
+        vec![
+            "move r1 <- r2 if r3 != 0",
+            "store [r4] <- r5",
+            "load r6 <- [r7]",
+            "loadimm r8 <- #-2",
+            "sub r9 <- r10 - r11",
+            "out r12",
+            "exit",
+            "out_number r13",
+        ],
+        texts
+    );
+}
+
+#[test]
+fn invalid_bytes_are_data() {
+    // Unknown opcode, register out of bounds and truncated instruction
+    let code = [0, 6, 16, 7, b'\'', b'\r', 4, 1];
+    let lines = disassemble(&code);
+    assert_eq!(
+        vec![
+            Line::Data {
+                address: 0,
+                bytes: &code[0..3]
+            },
+            Line::Instruction {
+                address: 3,
+                instruction: Instruction::Exit
+            },
+            Line::Data {
+                address: 4,
+                bytes: &code[4..]
+            },
+        ],
+        lines
+    );
+    assert_eq!("  0000   b'\\x00\\x06\\x10'", lines[0].to_string());
+    assert_eq!("  0004   b\"'\\r\\x04\\x01\"", lines[2].to_string());
+    assert_eq!(None, decode_at(&code, 6));
+    assert_eq!(Some((Instruction::Exit, 1)), decode_at(&code, 3));
+}
+
+#[test]
+fn more_registers() {
+    // sub r20 <- r1 - r2, then exit
+    let code = [5, 20, 1, 2, 7];
+    assert_eq!(None, decode_at(&code, 0));
+    assert_eq!(
+        Some((Instruction::Sub(20, 1, 2), 4)),
+        decode_at_for(&code, 0, Isa::Base, 32)
+    );
+    assert_eq!(
+        vec![
+            Line::Instruction {
+                address: 0,
+                instruction: Instruction::Sub(20, 1, 2)
+            },
+            Line::Instruction {
+                address: 4,
+                instruction: Instruction::Exit
+            },
+        ],
+        disassemble_for(&code, Isa::Base, 32)
+    );
+}
@@ -0,0 +1,89 @@
+use interpreter::{Machine, MachineError, MachineErrorKind};
+use std::error::Error;
+use std::io::{self, Write};
+
+fn run(code: &[u8]) -> MachineError {
+    Machine::new(code).run_on(&mut Vec::new()).unwrap_err()
+}
+
+#[test]
+fn error_location() {
+    // 0: exit
+    // 1: load r1 <- [r2] with r2 == 5000
+    let mut machine = Machine::new(&[7, 3, 1, 2]);
+    machine.set_reg(0, 1).unwrap();
+    machine.set_reg(2, 5000).unwrap();
+    let e = machine.run_on(&mut Vec::new()).unwrap_err();
+    assert!(matches!(
+        e.kind,
+        MachineErrorKind::MemoryIndexOutOfBounds(5000)
+    ));
+    assert_eq!(Some(1), e.ip);
+    assert_eq!(Some(3), e.opcode);
+    assert_eq!(
+        "address 5000 is outside of memory at address 1 (opcode 3)",
+        e.to_string()
+    );
+}
+
+#[test]
+fn error_kinds() {
+    let e = run(&[0x42]);
+    assert!(matches!(e.kind, MachineErrorKind::InvalidOpcode(0x42)));
+    assert_eq!((Some(0), Some(0x42)), (e.ip, e.opcode));
+
+    let e = run(&[1, 1, 2, 17]);
+    assert!(matches!(e.kind, MachineErrorKind::RegisterOutOfBounds(17)));
+
+    // Instruction truncated by the end of memory
+    let memory_size = Machine::new(&[]).memory().len();
+    let mut machine = Machine::new(&[]);
+    machine.set_mem(memory_size - 2, &[5, 1]).unwrap();
+    machine.set_reg(0, (memory_size - 2) as u32).unwrap();
+    let e = machine.step_on(&mut Vec::new()).unwrap_err();
+    assert!(matches!(e.kind, MachineErrorKind::MemoryIndexOutOfBounds(a) if a == memory_size));
+    assert_eq!(Some(memory_size - 2), e.ip);
+
+    // IP outside of memory
+    let mut machine = Machine::new(&[]);
+    machine.set_reg(0, 0xFFFF_FFFF).unwrap();
+    let e = machine.step_on(&mut Vec::new()).unwrap_err();
+    assert!(matches!(
+        e.kind,
+        MachineErrorKind::MemoryIndexOutOfBounds(0xFFFF_FFFF)
+    ));
+    assert_eq!((Some(0xFFFF_FFFF), None), (e.ip, e.opcode));
+
+    // Errors raised outside of execution have no location
+    let e = Machine::new(&[]).set_reg(16, 0).unwrap_err();
+    assert!(matches!(e.kind, MachineErrorKind::RegisterOutOfBounds(16)));
+    assert_eq!(None, e.ip);
+    assert_eq!("register r16 does not exist", e.to_string());
+}
+
+struct FailingWriter;
+
+impl Write for FailingWriter {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn write_error_has_source() {
+    // 0: out_number r0
+    let e = Machine::new(&[8, 0])
+        .step_on(&mut FailingWriter)
+        .unwrap_err();
+    assert!(matches!(e.kind, MachineErrorKind::WriteToBufferFailed(_)));
+    let source = e.source().unwrap();
+    assert_eq!("closed", source.to_string());
+    assert_eq!(
+        "cannot write output: closed at address 0 (opcode 8)",
+        e.to_string()
+    );
+}
@@ -0,0 +1,215 @@
+use interpreter::assembler::assemble;
+use interpreter::disassembler::disassemble_for;
+use interpreter::{Instruction, Isa, Machine, MachineBuilder, MachineErrorKind, NREGS};
+
+fn extended_machine(code: &[u8]) -> Machine {
+    MachineBuilder::new()
+        .isa(Isa::Extended)
+        .program(code)
+        .build()
+        .unwrap()
+}
+
+/// Run `rA <- r1 op r2` for the given operands, returning rA.
+fn binary(instruction: Instruction, b: u32, c: u32) -> Result<u32, MachineErrorKind> {
+    let mut code = instruction.encode();
+    code.push(7);
+    let mut machine = extended_machine(&code);
+    machine.set_reg(1, b).unwrap();
+    machine.set_reg(2, c).unwrap();
+    match machine.run_on(&mut Vec::new()) {
+        Ok(_) => Ok(machine.regs()[3]),
+        Err(e) => Err(e.kind),
+    }
+}
+
+#[test]
+fn base_isa_rejects_extended_opcodes() {
+    let code = [11, 3, 1, 2, 7];
+    let e = Machine::new(&code).run_on(&mut Vec::new()).unwrap_err();
+    assert!(matches!(e.kind, MachineErrorKind::InvalidOpcode(11)));
+    extended_machine(&code).run_on(&mut Vec::new()).unwrap();
+}
+
+#[test]
+fn arithmetic_and_logic() {
+    let cases = [
+        (Instruction::Add(3, 1, 2), 0xffffffff, 2, 1),
+        (Instruction::Mul(3, 1, 2), 7, -6i32 as u32, -42i32 as u32),
+        (Instruction::Div(3, 1, 2), -7i32 as u32, 2, -3i32 as u32),
+        (Instruction::Mod(3, 1, 2), -7i32 as u32, 2, -1i32 as u32),
+        (
+            Instruction::Div(3, 1, 2),
+            0x80000000,
+            -1i32 as u32,
+            0x80000000,
+        ),
+        (Instruction::And(3, 1, 2), 0b1100, 0b1010, 0b1000),
+        (Instruction::Or(3, 1, 2), 0b1100, 0b1010, 0b1110),
+        (Instruction::Xor(3, 1, 2), 0b1100, 0b1010, 0b0110),
+        (Instruction::Shl(3, 1, 2), 1, 31, 0x80000000),
+        (Instruction::Shl(3, 1, 2), 1, 32, 0),
+        (Instruction::Shr(3, 1, 2), 0x80000000, 31, 1),
+        (Instruction::Sar(3, 1, 2), 0x80000000, 31, 0xffffffff),
+        (Instruction::Sar(3, 1, 2), 0x80000000, 100, 0xffffffff),
+        (Instruction::Cmp(3, 1, 2), -1i32 as u32, 1, -1i32 as u32),
+        (Instruction::Cmp(3, 1, 2), 5, 5, 0),
+        (Instruction::Cmp(3, 1, 2), 5, -5i32 as u32, 1),
+    ];
+    for (instruction, b, c, expected) in cases {
+        let result = binary(instruction, b, c).unwrap();
+        assert_eq!(
+            expected, result,
+            "{} with {:#x} and {:#x}",
+            instruction, b, c
+        );
+    }
+}
+
+#[test]
+fn division_by_zero() {
+    for instruction in [Instruction::Div(3, 1, 2), Instruction::Mod(3, 1, 2)] {
+        assert!(matches!(
+            binary(instruction, 1, 0),
+            Err(MachineErrorKind::DivisionByZero)
+        ));
+    }
+}
+
+#[test]
+fn relative_branches() {
+    // Sum of 1..=10 followed by the factorial of 5
+    let code = assemble(
+        "
+  loadimm r1 <- #10
+  loadimm r2 <- #0
+  loadimm r3 <- #1
+sum:
+  add r2 <- r2 + r1
+  sub r1 <- r1 - r3
+  jnz r1, #sum
+  loadimm r4 <- #5
+  loadimm r5 <- #1
+fact:
+  jz r4, #done
+  mul r5 <- r5 * r4
+  sub r4 <- r4 - r3
+  jmp #fact
+done:
+  exit
+",
+    )
+    .unwrap();
+    let mut machine = extended_machine(&code);
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(55, machine.regs()[2]);
+    assert_eq!(120, machine.regs()[5]);
+}
+
+#[test]
+fn byte_accesses() {
+    // 0: loadb r3 <- [r1]
+    // 3: storeb [r2] <- r3
+    // 6: exit
+    let mut machine = extended_machine(&[25, 3, 1, 26, 2, 3, 7]);
+    machine.set_mem(100, &[0xab, 0xcd]).unwrap();
+    machine.set_mem(200, &[1, 2, 3, 4]).unwrap();
+    machine.set_reg(1, 100).unwrap();
+    machine.set_reg(2, 201).unwrap();
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(0xab, machine.regs()[3]);
+    assert_eq!(&[1, 0xab, 3, 4], &machine.memory()[200..204]);
+
+    // The last byte of memory is reachable
+    let mut machine = extended_machine(&[25, 3, 1, 7]);
+    machine.set_reg(1, 4095).unwrap();
+    machine.run_on(&mut Vec::new()).unwrap();
+    machine.set_reg(0, 0).unwrap();
+    machine.set_reg(1, 4096).unwrap();
+    let e = machine.run_on(&mut Vec::new()).unwrap_err();
+    assert!(matches!(
+        e.kind,
+        MachineErrorKind::MemoryIndexOutOfBounds(4096)
+    ));
+}
+
+#[test]
+fn base_programs_run_identically() {
+    let code = include_bytes!("../examples/99bottles.bin");
+    let mut base = Vec::new();
+    let mut extended = Vec::new();
+    Machine::new(code).run_on(&mut base).unwrap();
+    let mut machine = extended_machine(code);
+    machine.run_on(&mut extended).unwrap();
+    assert_eq!(base, extended);
+}
+
+#[test]
+fn exit_status() {
+    let code = assemble("loadimm r1 <- #-2\nexit r1").unwrap();
+    let mut machine = extended_machine(&code);
+    assert_eq!(0xfffffffe, machine.run_on(&mut Vec::new()).unwrap());
+    assert_eq!(0xfffffffe, machine.exit_status());
+    assert_eq!(
+        0xfffffffe,
+        extended_machine(&code)
+            .run_fast_on(&mut Vec::new())
+            .unwrap()
+    );
+
+    // A plain exit gives 0, even after an exit with a status
+    machine.set_reg(0, 0).unwrap();
+    machine.set_mem(4, &[7]).unwrap();
+    assert_eq!(0, machine.run_on(&mut Vec::new()).unwrap());
+    assert_eq!(0, machine.exit_status());
+
+    let e = extended_machine(&[31, 16])
+        .run_on(&mut Vec::new())
+        .unwrap_err();
+    assert!(matches!(e.kind, MachineErrorKind::RegisterOutOfBounds(16)));
+    let e = Machine::new(&code).run_on(&mut Vec::new()).unwrap_err();
+    assert!(matches!(e.kind, MachineErrorKind::InvalidOpcode(31)));
+}
+
+#[test]
+fn listings() {
+    let source = "  0000   add r1 <- r2 + r3
+  0004   mul r1 <- r2 * r3
+  0008   div r1 <- r2 / r3
+  0012   mod r1 <- r2 % r3
+  0016   and r1 <- r2 & r3
+  0020   or r1 <- r2 | r3
+  0024   xor r1 <- r2 ^ r3
+  0028   shl r1 <- r2 << r3
+  0032   shr r1 <- r2 >> r3
+  0036   sar r1 <- r2 >> r3
+  0040   cmp r1 <- r2 <=> r3
+  0044   jmp #-47
+  0047   jnz r4, #4
+  0051   jz r4, #-55
+  0055   loadb r1 <- [r2]
+  0058   storeb [r1] <- r2
+  0061   out_char r1
+  0063   out_unsigned r1
+  0065   out_hex r1
+  0067   out_binary r1
+  0069   exit r1
+  0071   exit
+";
+    let code = assemble(source).unwrap();
+    let listing: String = disassemble_for(&code, Isa::Extended, NREGS)
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect();
+    assert_eq!(source, listing);
+    for line in source.lines() {
+        let instruction = &line[9..];
+        let encoded = assemble(instruction).unwrap();
+        let (decoded, size) = Instruction::decode_for(&encoded, Isa::Extended).unwrap();
+        assert_eq!(encoded.len(), size);
+        assert_eq!(instruction, decoded.to_string());
+        if decoded.opcode() > 10 {
+            assert!(Instruction::decode(&encoded).is_err());
+        }
+    }
+}
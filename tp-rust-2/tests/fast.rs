@@ -0,0 +1,206 @@
+use interpreter::device::{CycleCounter, CYCLE_COUNTER_ADDRESS};
+use interpreter::{Isa, Machine, MachineBuilder, MachineError};
+use std::time::Instant;
+
+/// Output, final state and result of a run.
+type Outcome = (Vec<u8>, Vec<u32>, Vec<u8>, u64, Option<String>);
+
+fn outcome(machine: &Machine, out: Vec<u8>, result: Result<u32, MachineError>) -> Outcome {
+    (
+        out,
+        machine.regs().to_vec(),
+        machine.memory().to_vec(),
+        machine.steps(),
+        result.err().map(|e| e.to_string()),
+    )
+}
+
+/// Run `machine` with both engines and check that they agree.
+fn compare(machine: impl Fn() -> Machine, input: &[u8]) -> Outcome {
+    let mut regular = machine();
+    let mut out = Vec::new();
+    let result = regular.run_with_io(&mut &input[..], &mut out);
+    let expected = outcome(&regular, out, result);
+
+    let mut fast = machine();
+    let mut out = Vec::new();
+    let result = fast.run_fast_with_io(&mut &input[..], &mut out);
+    assert_eq!(expected, outcome(&fast, out, result));
+    expected
+}
+
+#[test]
+fn same_results_on_programs() {
+    let programs: [&[u8]; 8] = [
+        include_bytes!("../examples/99bottles.bin"),
+        include_bytes!("../examples/hello_world.bin"),
+        include_bytes!("../examples/factorial.bin"),
+        include_bytes!("../examples/fibonacci.bin"),
+        include_bytes!("../examples/sum.bin"),
+        include_bytes!("rfact.bin"),
+        include_bytes!("afact.bin"),
+        include_bytes!("push_pop.bin"),
+    ];
+    for program in programs {
+        let (_, _, _, _, error) = compare(
+            || {
+                let mut machine = Machine::new(program);
+                machine.set_reg(10, 6).unwrap();
+                machine
+            },
+            b"1 2 3 40\n",
+        );
+        assert_eq!(None, error);
+    }
+}
+
+#[test]
+fn same_errors() {
+    let programs: [&[u8]; 6] = [
+        // Invalid opcode
+        &[0xff],
+        // Truncated instruction
+        &[4, 1],
+        // move r1 <- r2 if r100 != 0
+        &[1, 1, 2, 100],
+        // move r100 <- r2 if r3 != 0, not taken, then exit
+        &[1, 100, 2, 3, 7],
+        // load r1 <- [r2] with r2 = -1
+        &[4, 2, 0xff, 0xff, 3, 1, 2],
+        // store [r2] <- r1 with r2 = 4094
+        &[4, 2, 0xfe, 0x0f, 2, 2, 1],
+    ];
+    for program in programs {
+        compare(|| Machine::new(program), b"");
+    }
+}
+
+#[test]
+fn same_budget() {
+    // 0: loadimm r0 <- #0
+    let (_, _, _, steps, error) = compare(
+        || {
+            let mut machine = Machine::new(&[4, 0, 0, 0]);
+            machine.set_step_limit(Some(1000));
+            machine
+        },
+        b"",
+    );
+    assert_eq!(1000, steps);
+    assert_eq!(
+        Some("step limit exceeded after 1000 steps at address 0".to_string()),
+        error
+    );
+}
+
+#[test]
+fn stores_into_code_invalidate_cache() {
+    // 0: loadimm r3 <- #7      ; `exit` once stored
+    // 4: out_number r1
+    // 6: loadimm r4 <- #4
+    // 10: store [r4] <- r3     ; overwrite 4: with `exit`
+    // 13: loadimm r0 <- #4
+    let code = [4, 3, 7, 0, 8, 1, 4, 4, 4, 0, 2, 4, 3, 4, 0, 4, 0];
+    let (out, _, _, _, error) = compare(
+        || {
+            let mut machine = Machine::new(&code);
+            machine.set_reg(1, 1).unwrap();
+            machine.set_step_limit(Some(100));
+            machine
+        },
+        b"",
+    );
+    // The second execution of 4 sees the stored code
+    assert_eq!(b"1", &out[..]);
+    assert_eq!(None, error);
+
+    // Memory modified between two runs is seen as well
+    let mut machine = Machine::new(&[8, 1, 7]);
+    machine.set_reg(1, 1).unwrap();
+    let mut out = Vec::new();
+    machine.run_fast_on(&mut out).unwrap();
+    machine.set_mem(0, &[8, 2]).unwrap();
+    machine.set_reg(0, 0).unwrap();
+    machine.set_reg(2, 2).unwrap();
+    machine.run_fast_on(&mut out).unwrap();
+    assert_eq!(b"12", &out[..]);
+}
+
+#[test]
+fn journal_and_devices() {
+    let mut machine = Machine::new(include_bytes!("rfact.bin"));
+    machine.set_reg(10, 5).unwrap();
+    machine.set_history_limit(100_000);
+    machine.run_fast_on(&mut Vec::new()).unwrap();
+    assert_eq!(120, machine.regs()[11]);
+    while machine.step_back() {}
+    let mut reference = Machine::new(include_bytes!("rfact.bin"));
+    reference.set_reg(10, 5).unwrap();
+    assert_eq!(reference.regs(), machine.regs());
+    assert_eq!(reference.memory(), machine.memory());
+
+    // 0: loadimm r1 <- #-240
+    // 4: load r2 <- [r1]
+    // 7: exit
+    let (_, regs, ..) = compare(
+        || {
+            let mut machine = Machine::new(&[4, 1, 0x10, 0xff, 3, 2, 1, 7]);
+            machine
+                .map_device(CYCLE_COUNTER_ADDRESS, Box::new(CycleCounter::new()))
+                .unwrap();
+            machine
+        },
+        b"",
+    );
+    assert_eq!(1, regs[2]);
+}
+
+#[test]
+fn extended_isa() {
+    // 0: add r3 <- r1 + r2
+    // 4: exit
+    let (_, regs, ..) = compare(
+        || {
+            MachineBuilder::new()
+                .isa(Isa::Extended)
+                .register(1, 40)
+                .register(2, 2)
+                .program(&[11, 3, 1, 2, 7])
+                .build()
+                .unwrap()
+        },
+        b"",
+    );
+    assert_eq!(42, regs[3]);
+}
+
+/// Timing comparison of both engines, to be run in release mode with
+/// `cargo test --release --test fast -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn faster_on_99bottles() {
+    const RUNS: u32 = 500;
+    let code = include_bytes!("../examples/99bottles.bin");
+    let time = |fast: bool| {
+        let start = Instant::now();
+        for _ in 0..RUNS {
+            let mut machine = Machine::new(code);
+            let mut out = Vec::new();
+            if fast {
+                machine.run_fast_on(&mut out).unwrap();
+            } else {
+                machine.run_on(&mut out).unwrap();
+            }
+        }
+        start.elapsed() / RUNS
+    };
+    let regular = time(false);
+    let fast = time(true);
+    println!(
+        "99bottles: regular {:?}, fast {:?} per run ({:.2}x)",
+        regular,
+        fast,
+        regular.as_secs_f64() / fast.as_secs_f64()
+    );
+    assert!(fast < regular);
+}
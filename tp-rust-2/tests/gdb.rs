@@ -0,0 +1,273 @@
+use interpreter::assembler::assemble_with_labels;
+use interpreter::gdb::GdbServer;
+use interpreter::{Isa, Machine};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+fn packet(data: &str) -> String {
+    let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+    format!("${}#{:02x}", data, checksum)
+}
+
+/// Packets of the script, each one acknowledged as a real client would.
+fn script(packets: &[&str]) -> Vec<u8> {
+    packets
+        .iter()
+        .map(|data| packet(data) + "+")
+        .collect::<String>()
+        .into_bytes()
+}
+
+/// Replies found in the output of the server, checking their checksums.
+fn replies(output: &[u8]) -> Vec<String> {
+    let output = String::from_utf8(output.to_vec()).unwrap();
+    output
+        .split('$')
+        .skip(1)
+        .map(|reply| {
+            let (data, checksum) = reply.split_once('#').unwrap();
+            assert_eq!(packet(data), format!("${}#{}", data, &checksum[..2]));
+            data.to_string()
+        })
+        .collect()
+}
+
+fn fact_server(n: u32) -> (GdbServer, usize) {
+    let (code, labels) = assemble_with_labels(include_str!("fact.dis")).unwrap();
+    let mut machine = Machine::new(&code);
+    machine.set_reg(10, n).unwrap();
+    (GdbServer::new(machine), labels["fact"])
+}
+
+#[test]
+fn scripted_session() {
+    let (mut server, fact) = fact_server(3);
+    let input = script(&[
+        "qSupported:multiprocess+;swbreak+;hwbreak+",
+        "?",
+        "p0",
+        "pa",
+        &format!("Z0,{:x},4", fact),
+        "c",
+        "p0",
+        "s",
+        "pb",
+        &format!("z0,{:x},4", fact),
+        "Pa=05000000",
+        "m0,4",
+        "M800,2:abcd",
+        "m800,3",
+        "vMustReplyEmpty",
+        "c",
+        "?",
+        "k",
+    ]);
+    let mut output = Vec::new();
+    server
+        .serve(&input[..], &mut output, &mut Vec::new())
+        .unwrap();
+    assert!(output.starts_with(b"+$"));
+    assert_eq!(
+        vec![
+            "PacketSize=4000;QStartNoAckMode+;swbreak+;hwbreak+;qXfer:features:read+",
+            "S05",
+            "00000000",
+            "03000000",
+            "OK",
+            "T05swbreak:;",
+            "57000000",
+            "S05",
+            "01000000",
+            "OK",
+            "OK",
+            "04020010",
+            "OK",
+            "abcd00",
+            "",
+            "W00",
+            "W00",
+        ],
+        replies(&output)
+    );
+    // fact(5) computed after changing r10 at the breakpoint
+    assert_eq!(120, server.machine().regs()[11]);
+}
+
+#[test]
+fn registers() {
+    let (mut server, _) = fact_server(4);
+    let mut registers = String::new();
+    for value in 0..16u32 {
+        registers += &format!("{:08x}", (value * 0x11).swap_bytes());
+    }
+    let set = format!("G{}", registers);
+    let input = script(&[&set, "g", "G00", "p10", "P10=00"]);
+    let mut output = Vec::new();
+    server
+        .serve(&input[..], &mut output, &mut Vec::new())
+        .unwrap();
+    assert_eq!(
+        vec!["OK", &registers, "E01", "E01", "E01"],
+        replies(&output)
+    );
+    assert_eq!(0xff, server.machine().regs()[15]);
+}
+
+#[test]
+fn target_description() {
+    let (mut server, _) = fact_server(1);
+    let input = script(&[
+        "qXfer:features:read:target.xml:0,3fff",
+        "qXfer:features:read:target.xml:0,20",
+        "qXfer:features:read:target.xml:20,3fff",
+        "qXfer:features:read:target.xml:ffff,10",
+        "qXfer:features:read:other.xml:0,10",
+    ]);
+    let mut output = Vec::new();
+    server
+        .serve(&input[..], &mut output, &mut Vec::new())
+        .unwrap();
+    let replies = replies(&output);
+    let xml = replies[0].strip_prefix('l').unwrap();
+    assert!(xml.starts_with(r#"<?xml version="1.0"?>"#));
+    assert!(xml.contains(r#"<reg name="r0" bitsize="32" type="code_ptr"/>"#));
+    assert!(xml.contains(r#"<reg name="r15" bitsize="32" type="uint32"/>"#));
+    assert!(!xml.contains(r#"name="r16""#));
+    assert!(xml.ends_with("</feature></target>"));
+    // The description can be read in parts
+    assert_eq!(format!("m{}", &xml[..0x20]), replies[1]);
+    assert_eq!(format!("l{}", &xml[0x20..]), replies[2]);
+    assert_eq!(vec!["l", "E01"], replies[3..]);
+}
+
+#[test]
+fn checksums_and_no_ack_mode() {
+    let (mut server, _) = fact_server(1);
+    let mut input = b"$?#00".to_vec();
+    input.extend(script(&["?", "QStartNoAckMode"]));
+    input.extend(packet("p0").bytes());
+    // Invalid checksums are not checked anymore
+    input.extend(b"$pa#00");
+    let mut output = Vec::new();
+    server
+        .serve(&input[..], &mut output, &mut Vec::new())
+        .unwrap();
+    assert_eq!(
+        "-+$S05#b8+$OK#9a$00000000#80$01000000#81",
+        String::from_utf8(output).unwrap()
+    );
+}
+
+#[test]
+fn retransmission() {
+    let (mut server, _) = fact_server(1);
+    let mut input = packet("p0").into_bytes();
+    input.extend(b"-");
+    input.extend(packet("D").bytes());
+    let mut output = Vec::new();
+    server
+        .serve(&input[..], &mut output, &mut Vec::new())
+        .unwrap();
+    assert_eq!(
+        "+$00000000#80$00000000#80+$OK#9a",
+        String::from_utf8(output).unwrap()
+    );
+}
+
+#[test]
+fn faults_and_watchpoints() {
+    // 0: loadimm r1 <- #100
+    // 4: store [r1] <- r1
+    // 7: opcode 99
+    let mut server = GdbServer::new(Machine::new(&[4, 1, 100, 0, 2, 1, 1, 99]));
+    let input = script(&["Z2,66,1", "c", "p0", "z2,66,1", "c", "p0", "c"]);
+    let mut output = Vec::new();
+    server
+        .serve(&input[..], &mut output, &mut Vec::new())
+        .unwrap();
+    assert_eq!(
+        vec![
+            "OK",
+            "T05watch:64;",
+            "07000000",
+            "OK",
+            "S04",
+            "07000000",
+            "S04"
+        ],
+        replies(&output)
+    );
+}
+
+#[test]
+fn program_output() {
+    let (code, _) = assemble_with_labels(
+        "
+        loadimm r1 <- #0x41
+        out r1
+        exit
+    ",
+    )
+    .unwrap();
+    let mut server = GdbServer::new(Machine::new(&code));
+    let mut fd = Vec::new();
+    server
+        .serve(&script(&["c"])[..], &mut Vec::new(), &mut fd)
+        .unwrap();
+    assert_eq!(b"A", &fd[..]);
+}
+
+#[test]
+fn exit_status() {
+    let (code, _) = assemble_with_labels(
+        "
+        loadimm r1 <- #0x12a
+        exit r1
+    ",
+    )
+    .unwrap();
+    let mut machine = Machine::new(&code);
+    machine.set_isa(Isa::Extended);
+    let mut server = GdbServer::new(machine);
+    let mut output = Vec::new();
+    server
+        .serve(&script(&["c", "?"])[..], &mut output, &mut Vec::new())
+        .unwrap();
+    assert_eq!(vec!["W2a", "W2a"], replies(&output));
+}
+
+#[test]
+fn over_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let (mut server, _) = fact_server(4);
+        server
+            .serve(stream.try_clone().unwrap(), &mut stream, &mut Vec::new())
+            .unwrap();
+        server.machine().regs()[11]
+    });
+
+    let mut client = TcpStream::connect(address).unwrap();
+    let mut reader = BufReader::new(client.try_clone().unwrap());
+    let mut exchange = |data: &str| {
+        client.write_all(packet(data).as_bytes()).unwrap();
+        let mut ack = [0];
+        reader.read_exact(&mut ack).unwrap();
+        assert_eq!(b'+', ack[0]);
+        let mut reply = Vec::new();
+        reader.read_until(b'#', &mut reply).unwrap();
+        let mut checksum = [0; 2];
+        reader.read_exact(&mut checksum).unwrap();
+        client.write_all(b"+").unwrap();
+        String::from_utf8(reply[1..reply.len() - 1].to_vec()).unwrap()
+    };
+    assert_eq!("S05", exchange("?"));
+    assert_eq!("S05", exchange("s"));
+    assert_eq!("00100000", exchange("p2"));
+    assert_eq!("W00", exchange("c"));
+    client.write_all(packet("k").as_bytes()).unwrap();
+    assert_eq!(24, server.join().unwrap());
+}
@@ -0,0 +1,110 @@
+use interpreter::assembler::assemble;
+use interpreter::{Isa, Machine, MachineBuilder, MachineErrorKind};
+
+/// Registers, memory and step count of a machine.
+fn state(machine: &Machine) -> (Vec<u32>, Vec<u8>, u64) {
+    (
+        machine.regs().to_vec(),
+        machine.memory().to_vec(),
+        machine.steps(),
+    )
+}
+
+#[test]
+fn disabled_by_default() {
+    let mut machine = Machine::new(include_bytes!("fact.bin"));
+    machine.step_on(&mut Vec::new()).unwrap();
+    assert_eq!(0, machine.history_len());
+    assert!(!machine.step_back());
+}
+
+#[test]
+fn step_back_restores_every_state() {
+    let mut machine = Machine::new(include_bytes!("rfact.bin"));
+    machine.set_reg(10, 5).unwrap();
+    machine.set_history_limit(usize::MAX);
+    let mut states = vec![state(&machine)];
+    while !machine.step_on(&mut Vec::new()).unwrap() {
+        states.push(state(&machine));
+    }
+    assert_eq!(120, machine.regs()[11]);
+    while let Some(expected) = states.pop() {
+        assert!(machine.step_back());
+        assert_eq!(expected, state(&machine));
+    }
+    assert!(!machine.step_back());
+}
+
+#[test]
+fn history_is_bounded() {
+    let mut machine = Machine::new(include_bytes!("../examples/99bottles.bin"));
+    machine.set_history_limit(10);
+    let mut states = Vec::new();
+    for _ in 0..100 {
+        states.push(state(&machine));
+        machine.step_on(&mut Vec::new()).unwrap();
+    }
+    assert_eq!(10, machine.history_len());
+    for _ in 0..10 {
+        assert!(machine.step_back());
+    }
+    assert!(!machine.step_back());
+    assert_eq!(states[90], state(&machine));
+
+    // Lowering the limit drops the oldest steps
+    machine.step_on(&mut Vec::new()).unwrap();
+    machine.step_on(&mut Vec::new()).unwrap();
+    machine.set_history_limit(1);
+    assert_eq!(1, machine.history_len());
+}
+
+#[test]
+fn step_back_restores_exit_status() {
+    let code = assemble("loadimm r1 <- #5\nexit r1").unwrap();
+    let mut machine = MachineBuilder::new()
+        .isa(Isa::Extended)
+        .program(&code)
+        .build()
+        .unwrap();
+    machine.set_history_limit(10);
+    assert_eq!(5, machine.run_on(&mut Vec::new()).unwrap());
+    assert!(machine.step_back());
+    assert_eq!(0, machine.exit_status());
+    machine.set_reg(1, 7).unwrap();
+    assert_eq!(7, machine.run_on(&mut Vec::new()).unwrap());
+}
+
+#[test]
+fn run_back_to_address() {
+    let mut machine = Machine::new(include_bytes!("fact.bin"));
+    machine.set_reg(10, 4).unwrap();
+    machine.set_history_limit(1000);
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(24, machine.regs()[11]);
+
+    // Last entry into mult (at 24), computing 12 * 2
+    assert!(machine.run_back_to(24));
+    assert_eq!(24, machine.regs()[0]);
+    assert_eq!(12, machine.regs()[11]);
+    assert_eq!(2, machine.regs()[12]);
+
+    // Never executed address: the whole journal is undone
+    assert!(!machine.run_back_to(3));
+    assert_eq!(0, machine.steps());
+    assert_eq!(0, machine.regs()[0]);
+}
+
+#[test]
+fn failed_step_is_not_journaled() {
+    // 0: loadimm r1 <- #1
+    // 4: sub r100 <- r1 - r1
+    let mut machine = Machine::new(&[4, 1, 1, 0, 5, 100, 1, 1]);
+    machine.set_history_limit(10);
+    machine.step_on(&mut Vec::new()).unwrap();
+    let e = machine.step_on(&mut Vec::new()).unwrap_err();
+    assert!(matches!(e.kind, MachineErrorKind::RegisterOutOfBounds(100)));
+    assert_eq!(1, machine.history_len());
+    assert!(machine.step_back());
+    assert_eq!(0, machine.regs()[0]);
+    assert_eq!(0, machine.regs()[1]);
+}
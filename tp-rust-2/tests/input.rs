@@ -0,0 +1,75 @@
+use interpreter::{Machine, MachineError, MachineErrorKind};
+
+fn run_with_input(code: &[u8], input: &str) -> (Machine, Result<u32, MachineError>, String) {
+    let mut machine = Machine::new(code);
+    let mut out = Vec::new();
+    let result = machine.run_with_io(&mut input.as_bytes(), &mut out);
+    (machine, result, String::from_utf8(out).unwrap())
+}
+
+#[test]
+fn test_in() {
+    // 0: in r1, r2
+    // 3: in r3, r4
+    // 6: exit
+    let (m, _, _) = run_with_input(&[9, 1, 2, 9, 3, 4, 7], "A");
+    assert_eq!(&[b'A' as u32, 0, 0, 1], &m.regs()[1..5]);
+}
+
+#[test]
+fn test_in_number() {
+    // 0: in_number r1, r2
+    // 3: in_number r3, r4
+    // 6: in_number r5, r6
+    // 9: exit
+    let code = [10, 1, 2, 10, 3, 4, 10, 5, 6, 7];
+    let (m, _, _) = run_with_input(&code, "  42\n-17 ");
+    assert_eq!(&[42, 0, -17i32 as u32, 0, 0, 1], &m.regs()[1..7]);
+
+    let (m, _, _) = run_with_input(&code, "4294967295 -2147483648");
+    assert_eq!(&[u32::MAX, 0, 0x8000_0000, 0, 0, 1], &m.regs()[1..7]);
+}
+
+#[test]
+fn test_in_number_invalid() {
+    for input in ["abc", "-", "4294967296", "-2147483649"] {
+        let (_, result, _) = run_with_input(&[10, 1, 2, 7], input);
+        assert!(matches!(
+            result.unwrap_err().kind,
+            MachineErrorKind::InvalidNumberInput
+        ));
+    }
+}
+
+#[test]
+fn test_in_out_of_bounds() {
+    let (_, result, _) = run_with_input(&[9, 100, 1, 7], "A");
+    assert!(matches!(
+        result.unwrap_err().kind,
+        MachineErrorKind::RegisterOutOfBounds(100)
+    ));
+    let (_, result, _) = run_with_input(&[10, 1, 100, 7], "1");
+    assert!(matches!(
+        result.unwrap_err().kind,
+        MachineErrorKind::RegisterOutOfBounds(100)
+    ));
+}
+
+#[test]
+fn step_on_sees_end_of_input() {
+    let mut machine = Machine::new(&[9, 1, 2]);
+    machine.set_reg(1, 5).unwrap();
+    machine.step_on(&mut Vec::new()).unwrap();
+    assert_eq!(&[0, 1], &machine.regs()[1..3]);
+}
+
+#[test]
+fn filter_programs() {
+    let (_, result, out) = run_with_input(include_bytes!("../examples/cat.bin"), "Hello\nworld\n");
+    result.unwrap();
+    assert_eq!("Hello\nworld\n", out);
+
+    let (_, result, out) = run_with_input(include_bytes!("../examples/sum.bin"), "1 2\n-10\n  40");
+    result.unwrap();
+    assert_eq!("33\n", out);
+}
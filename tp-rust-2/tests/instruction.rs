@@ -0,0 +1,66 @@
+use interpreter::{Instruction, MachineErrorKind};
+
+#[test]
+fn decode_encode_roundtrip() {
+    for instruction in [
+        Instruction::MoveIf(1, 2, 3),
+        Instruction::Store(4, 5),
+        Instruction::Load(6, 7),
+        Instruction::LoadImm(8, -2),
+        Instruction::LoadImm(9, 0x7011),
+        Instruction::Sub(10, 11, 12),
+        Instruction::Out(13),
+        Instruction::Exit,
+        Instruction::OutNumber(14),
+        Instruction::In(15, 1),
+        Instruction::InNumber(2, 3),
+    ] {
+        let mut bytes = instruction.encode();
+        assert_eq!(instruction.size(), bytes.len());
+        // Trailing bytes belong to the next instruction
+        bytes.extend([7, 7]);
+        let (decoded, size) = Instruction::decode(&bytes).unwrap();
+        assert_eq!(instruction, decoded);
+        assert_eq!(instruction.size(), size);
+    }
+}
+
+#[test]
+fn decode_loadimm_is_little_endian() {
+    let (instruction, _) = Instruction::decode(&[4, 1, 0x11, 0xd0]).unwrap();
+    assert_eq!(Instruction::LoadImm(1, 0xd011u16 as i16), instruction);
+}
+
+fn decode_error(bytes: &[u8]) -> MachineErrorKind {
+    Instruction::decode(bytes).unwrap_err().kind
+}
+
+#[test]
+fn decode_errors() {
+    assert!(matches!(
+        decode_error(&[0, 1, 2, 3]),
+        MachineErrorKind::InvalidOpcode(0)
+    ));
+    assert!(matches!(
+        decode_error(&[0xff]),
+        MachineErrorKind::InvalidOpcode(0xff)
+    ));
+    assert!(matches!(
+        decode_error(&[]),
+        MachineErrorKind::MemoryIndexOutOfBounds(0)
+    ));
+    assert!(matches!(
+        decode_error(&[5, 1]),
+        MachineErrorKind::MemoryIndexOutOfBounds(2)
+    ));
+}
+
+#[test]
+fn display_like_listings() {
+    assert_eq!(
+        "move r0 <- r9 if r8 != 0",
+        Instruction::MoveIf(0, 9, 8).to_string()
+    );
+    assert_eq!("loadimm r3 <- #-4", Instruction::LoadImm(3, -4).to_string());
+    assert_eq!("store [r2] <- r10", Instruction::Store(2, 10).to_string());
+}
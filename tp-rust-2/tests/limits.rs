@@ -0,0 +1,58 @@
+use interpreter::{Machine, MachineErrorKind};
+use std::time::{Duration, Instant};
+
+// 0: loadimm r0 <- #0
+const INFINITE_LOOP: [u8; 4] = [4, 0, 0, 0];
+
+#[test]
+fn step_limit_exceeded() {
+    let mut machine = Machine::new(&INFINITE_LOOP);
+    let e = machine
+        .run_with_limit_on(&mut Vec::new(), 1000)
+        .unwrap_err();
+    assert!(matches!(e.kind, MachineErrorKind::StepLimitExceeded(1000)));
+    assert_eq!(Some(0), e.ip);
+    assert_eq!(1000, machine.steps());
+
+    // The limit applies to each run separately
+    let e = machine.run_with_limit_on(&mut Vec::new(), 10).unwrap_err();
+    assert!(matches!(e.kind, MachineErrorKind::StepLimitExceeded(1010)));
+}
+
+#[test]
+fn step_limit_not_reached() {
+    let mut machine = Machine::new(include_bytes!("fact.bin"));
+    machine.set_reg(10, 5).unwrap();
+    machine.run_with_limit_on(&mut Vec::new(), 10_000).unwrap();
+    assert_eq!(120, machine.regs()[11]);
+
+    // A program needing exactly the budget succeeds
+    let steps = machine.steps();
+    let mut machine = Machine::new(include_bytes!("fact.bin"));
+    machine.set_reg(10, 5).unwrap();
+    machine.run_with_limit_on(&mut Vec::new(), steps).unwrap();
+}
+
+#[test]
+fn step_limit_applies_to_step() {
+    let mut machine = Machine::new(&INFINITE_LOOP);
+    machine.set_step_limit(Some(2));
+    machine.step_on(&mut Vec::new()).unwrap();
+    machine.step_on(&mut Vec::new()).unwrap();
+    let e = machine.step_on(&mut Vec::new()).unwrap_err();
+    assert!(matches!(e.kind, MachineErrorKind::StepLimitExceeded(2)));
+    machine.set_step_limit(None);
+    machine.step_on(&mut Vec::new()).unwrap();
+}
+
+#[test]
+fn deadline_exceeded() {
+    let mut machine = Machine::new(&INFINITE_LOOP);
+    let start = Instant::now();
+    let e = machine
+        .run_with_deadline_on(&mut Vec::new(), start + Duration::from_millis(50))
+        .unwrap_err();
+    assert!(matches!(e.kind, MachineErrorKind::DeadlineExceeded(_)));
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert!(machine.steps() > 0);
+}
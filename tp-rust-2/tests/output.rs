@@ -0,0 +1,101 @@
+use interpreter::assembler::assemble;
+use interpreter::compiler::compile;
+use interpreter::{Isa, Machine, MachineBuilder, MachineErrorKind, OutputMode};
+
+/// Output of `out` or `out_char` applied to each value.
+fn output(mnemonic: &str, values: &[u32], mode: OutputMode) -> Result<Vec<u8>, MachineErrorKind> {
+    let code = assemble(&format!("{} r1\nexit", mnemonic)).unwrap();
+    let mut out = Vec::new();
+    for &value in values {
+        let mut machine = MachineBuilder::new()
+            .isa(Isa::Extended)
+            .output_mode(mode)
+            .program(&code)
+            .register(1, value)
+            .build()
+            .unwrap();
+        machine.run_on(&mut out).map_err(|e| e.kind)?;
+    }
+    Ok(out)
+}
+
+#[test]
+fn latin1_output() {
+    assert_eq!(OutputMode::Latin1, Machine::new(&[]).output_mode());
+    assert_eq!(
+        "Aé\u{ff}".as_bytes(),
+        output("out", &[0x41, 0xe9, 0x1ff], OutputMode::Latin1).unwrap()
+    );
+}
+
+#[test]
+fn raw_output() {
+    assert_eq!(
+        vec![0x41, 0xe9, 0xff, 0x00],
+        output("out", &[0x41, 0xe9, 0x1ff, 0x100], OutputMode::Raw).unwrap()
+    );
+    // UTF-8 written byte by byte
+    let mut machine = Machine::new(&assemble("out r1\nout r2\nexit").unwrap());
+    machine.set_output_mode(OutputMode::Raw);
+    machine.set_reg(1, 0xc3).unwrap();
+    machine.set_reg(2, 0xa9).unwrap();
+    let mut out = Vec::new();
+    machine.run_fast_on(&mut out).unwrap();
+    assert_eq!("é", String::from_utf8(out).unwrap());
+}
+
+#[test]
+fn unicode_output() {
+    for mode in [OutputMode::Latin1, OutputMode::Raw] {
+        assert_eq!(
+            "Aé€😀".as_bytes(),
+            output("out_char", &[0x41, 0xe9, 0x20ac, 0x1f600], mode).unwrap()
+        );
+    }
+    for invalid in [0xd800, 0xdfff, 0x110000, u32::MAX] {
+        assert!(matches!(
+            output("out_char", &[invalid], OutputMode::Latin1),
+            Err(MachineErrorKind::NumberConversionToCharNotValid(value)) if value == invalid
+        ));
+    }
+    // Extended instruction
+    let e = Machine::new(&[27, 1, 7])
+        .run_on(&mut Vec::new())
+        .unwrap_err();
+    assert!(matches!(e.kind, MachineErrorKind::InvalidOpcode(27)));
+}
+
+#[test]
+fn compiled_strings() {
+    let code = compile("fn main() { print \"déjà vu: 1 €\"; }").unwrap();
+    for mode in [OutputMode::Latin1, OutputMode::Raw] {
+        let mut machine = Machine::new(&code);
+        machine.set_isa(Isa::Extended);
+        machine.set_output_mode(mode);
+        let mut out = Vec::new();
+        machine.run_on(&mut out).unwrap();
+        assert_eq!("déjà vu: 1 €\n", String::from_utf8(out).unwrap());
+    }
+}
+
+#[test]
+fn number_formats() {
+    let values = [0, 1, 255, 0x80000000, u32::MAX];
+    let text = |mnemonic: &str| {
+        let out = output(mnemonic, &values, OutputMode::Latin1).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    assert_eq!("01255-2147483648-1", text("out_number"));
+    assert_eq!("0125521474836484294967295", text("out_unsigned"));
+    assert_eq!("01ff80000000ffffffff", text("out_hex"));
+    assert_eq!(
+        format!("0111111111{}{}", format_args!("1{:031}", 0), "1".repeat(32)),
+        text("out_binary")
+    );
+    for opcode in 28..=30 {
+        let e = Machine::new(&[opcode, 1, 7])
+            .run_on(&mut Vec::new())
+            .unwrap_err();
+        assert!(matches!(e.kind, MachineErrorKind::InvalidOpcode(o) if o == opcode));
+    }
+}
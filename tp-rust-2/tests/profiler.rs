@@ -0,0 +1,94 @@
+use interpreter::{assembler, profiler::Profile, Machine};
+use std::collections::HashMap;
+
+fn fact_labels() -> HashMap<String, usize> {
+    let (_, labels) = assembler::assemble_with_labels(include_str!("fact.dis")).unwrap();
+    labels
+}
+
+fn profile_fact(n: u32) -> (Machine, Profile) {
+    let mut machine = Machine::new(include_bytes!("fact.bin"));
+    machine.set_reg(10, n).unwrap();
+    let mut profile = Profile::new();
+    machine.run_profiled(&mut Vec::new(), &mut profile).unwrap();
+    (machine, profile)
+}
+
+#[test]
+fn counts_per_address_and_opcode() {
+    let (machine, profile) = profile_fact(5);
+    assert_eq!(machine.steps(), profile.steps());
+    assert_eq!(1, profile.count(0));
+    assert_eq!(1, profile.count(23));
+    assert_eq!(0, profile.count(1));
+    // fact_loop runs once per value of r10
+    assert_eq!(5, profile.count(91));
+    assert_eq!(1, profile.opcode_count(7));
+    let total: u64 = (0..=255).map(|opcode| profile.opcode_count(opcode)).sum();
+    assert_eq!(profile.steps(), total);
+    let hot_spots = profile.hot_spots();
+    assert_eq!(
+        profile.steps(),
+        hot_spots.iter().map(|(_, n)| n).sum::<u64>()
+    );
+    assert!(hot_spots.windows(2).all(|w| w[0].1 >= w[1].1));
+}
+
+#[test]
+fn estimated_calls() {
+    let (_, profile) = profile_fact(5);
+    assert_eq!(1, profile.calls(87));
+    assert_eq!(4, profile.calls(24));
+    // Loops and conditionals are not calls
+    assert_eq!(0, profile.calls(32));
+    assert_eq!(0, profile.calls(68));
+    assert_eq!(
+        vec![("mult".to_string(), 4), ("fact".to_string(), 1)],
+        profile.calls_by_label(&fact_labels())
+    );
+}
+
+#[test]
+fn counts_per_label() {
+    let (_, profile) = profile_fact(5);
+    let by_label = profile.by_label(&fact_labels());
+    assert_eq!(
+        profile.steps(),
+        by_label.iter().map(|(_, n)| n).sum::<u64>()
+    );
+    let count = |label: &str| by_label.iter().find(|(l, _)| l == label).unwrap().1;
+    // Six instructions run before the call of fact
+    assert_eq!(6, count("(no label)"));
+    assert_eq!(1, count("return_from_fact_1"));
+    assert_eq!(8, count("mult"));
+    assert_eq!(
+        by_label[0].1,
+        by_label.iter().map(|(_, n)| *n).max().unwrap()
+    );
+}
+
+#[test]
+fn failing_instruction_not_counted() {
+    // 0: loadimm r1 <- #-1
+    // 4: load r2 <- [r1]
+    let mut machine = Machine::new(&[4, 1, 255, 255, 3, 2, 1]);
+    let mut profile = Profile::new();
+    assert!(machine.run_profiled(&mut Vec::new(), &mut profile).is_err());
+    assert_eq!(1, profile.steps());
+    assert_eq!(0, profile.count(4));
+}
+
+#[test]
+fn report() {
+    let (machine, profile) = profile_fact(5);
+    let mut out = Vec::new();
+    profile
+        .report_on(&machine, &fact_labels(), &mut out)
+        .unwrap();
+    let report = String::from_utf8(out).unwrap();
+    assert!(report.starts_with(&format!("{} instructions executed\n", profile.steps())));
+    assert!(report.contains("\nhot spots:\n"));
+    assert!(report.contains("\nby label:\n"));
+    assert!(report.contains(" loadimm (4)\n"));
+    assert!(report.contains("\n         4  mult\n         1  fact\n"));
+}
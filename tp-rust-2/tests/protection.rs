@@ -0,0 +1,186 @@
+use interpreter::assembler::assemble;
+use interpreter::device::{CycleCounter, CYCLE_COUNTER_ADDRESS};
+use interpreter::{
+    Access, Isa, Machine, MachineBuilder, MachineError, MachineErrorKind, Permissions, Region,
+};
+
+fn fault(e: &MachineError) -> Option<(usize, Access)> {
+    match e.kind {
+        MachineErrorKind::ProtectionFault { address, access } => Some((address, access)),
+        _ => None,
+    }
+}
+
+#[test]
+fn store_into_code() {
+    let code = assemble(
+        "
+        loadimm r1 <- #4
+        loadimm r2 <- #-1
+        store [r1] <- r2
+        exit
+    ",
+    )
+    .unwrap();
+    let mut machine = Machine::new(&code);
+    machine.protect(0, code.len(), Permissions::CODE);
+    let e = machine.run().unwrap_err();
+    assert_eq!(Some((4, Access::Write)), fault(&e));
+    assert_eq!((Some(8), Some(2)), (e.ip, e.opcode));
+    assert_eq!(
+        "write access to address 4 is not permitted at address 8 (opcode 2)",
+        e.to_string()
+    );
+    assert_eq!(&code[..], &machine.memory()[..code.len()]);
+
+    // Without protection, the store overwrites the code
+    let mut machine = Machine::new(&code);
+    machine.run().unwrap();
+    assert_eq!(&[0xff; 4], &machine.memory()[4..8]);
+}
+
+#[test]
+fn execute_data() {
+    // 0: loadimm r0 <- #100
+    let mut machine = Machine::new(&[4, 0, 100, 0]);
+    machine.set_mem(100, &[7]).unwrap();
+    machine.protect(0, 4, Permissions::CODE);
+    machine.protect(4, 4096, Permissions::DATA);
+    let e = machine.run().unwrap_err();
+    assert_eq!(Some((100, Access::Execute)), fault(&e));
+    assert_eq!((Some(100), Some(7)), (e.ip, e.opcode));
+
+    // An instruction must be executable as a whole
+    let mut machine = Machine::new(&[4, 1, 100, 0]);
+    machine.protect(2, 4, Permissions::DATA);
+    let e = machine.step().unwrap_err();
+    assert_eq!(Some((2, Access::Execute)), fault(&e));
+}
+
+#[test]
+fn stack_guard() {
+    let code = assemble(
+        "
+        loadimm r2 <- #4096
+        loadimm r3 <- #4
+    push:
+        sub r2 <- r2 - r3
+        store [r2] <- r1
+        loadimm r0 <- #push
+    ",
+    )
+    .unwrap();
+    let mut machine = MachineBuilder::new()
+        .program(&code)
+        .protect(0, code.len(), Permissions::CODE)
+        .protect(code.len(), 4096, Permissions::DATA)
+        .protect(3840, 3968, Permissions::NONE)
+        .build()
+        .unwrap();
+    let e = machine.run_with_limit(10_000).unwrap_err();
+    // The word at 3964 overlaps the guard zone from its first byte
+    assert_eq!(Some((3964, Access::Write)), fault(&e));
+    assert_eq!(3964, machine.regs()[2]);
+    assert_eq!(3, machine.regions().len());
+}
+
+#[test]
+fn reads() {
+    // 0: load r1 <- [r2]
+    // 3: loadb r1 <- [r3]
+    // 6: exit
+    let program = [3, 1, 2, 25, 1, 3, 7];
+    let mut machine = MachineBuilder::new()
+        .program(&program)
+        .isa(Isa::Extended)
+        .register(2, 1000)
+        .register(3, 2002)
+        .build()
+        .unwrap();
+    // The word straddles the start of the protected region
+    machine.protect(1002, 1010, Permissions::NONE);
+    let e = machine.step().unwrap_err();
+    assert_eq!(Some((1002, Access::Read)), fault(&e));
+
+    // The region added last wins
+    machine.protect(1000, 1004, Permissions::DATA);
+    machine.protect(2000, 3000, Permissions::NONE);
+    machine.protect(2002, 2003, Permissions::DATA);
+    machine.run().unwrap();
+
+    machine.clear_regions();
+    assert!(machine.regions().is_empty());
+}
+
+#[test]
+fn devices_are_protected() {
+    // 0: load r1 <- [r2]
+    let mut machine = Machine::new(&[3, 1, 2, 7]);
+    machine
+        .map_device(CYCLE_COUNTER_ADDRESS, Box::new(CycleCounter::new()))
+        .unwrap();
+    machine.set_reg(2, CYCLE_COUNTER_ADDRESS as u32).unwrap();
+    machine.protect(
+        CYCLE_COUNTER_ADDRESS,
+        CYCLE_COUNTER_ADDRESS + 8,
+        Permissions::NONE,
+    );
+    let e = machine.run().unwrap_err();
+    assert_eq!(Some((CYCLE_COUNTER_ADDRESS, Access::Read)), fault(&e));
+}
+
+#[test]
+fn fast_engine() {
+    let code = assemble(
+        "
+        loadimm r1 <- #4
+        loadimm r2 <- #-1
+        store [r1] <- r2
+        exit
+    ",
+    )
+    .unwrap();
+    let mut machine = Machine::new(&code);
+    // Fill the cache of pre-decoded instructions before protecting
+    machine.run_fast().unwrap();
+    let mut machine = Machine::new(&code);
+    machine.protect(0, 8, Permissions::CODE);
+    let e = machine.run_fast().unwrap_err();
+    assert_eq!(Some((4, Access::Write)), fault(&e));
+    assert_eq!(Some(8), e.ip);
+
+    // Executing code made non-executable after being cached
+    let mut machine = Machine::new(&[4, 1, 0, 0, 4, 0, 0, 0]);
+    machine.set_step_limit(Some(4));
+    assert!(machine.run_fast().is_err());
+    machine.set_step_limit(None);
+    machine.protect(4, 8, Permissions::DATA);
+    let e = machine.run_fast().unwrap_err();
+    assert_eq!(Some((4, Access::Execute)), fault(&e));
+}
+
+#[test]
+fn parse() {
+    assert_eq!(Ok(Permissions::CODE), "r-x".parse());
+    assert_eq!(Ok(Permissions::DATA), "rw".parse());
+    assert_eq!(Ok(Permissions::NONE), "-".parse());
+    assert!("rr".parse::<Permissions>().is_err());
+    assert!("rwz".parse::<Permissions>().is_err());
+    assert_eq!("r-x", Permissions::CODE.to_string());
+    assert_eq!(
+        Ok(Region {
+            start: 0x100,
+            end: 512,
+            permissions: Permissions::DATA
+        }),
+        "0x100:512:rw-".parse()
+    );
+    assert!("0:12".parse::<Region>().is_err());
+    assert!("0:x:rw".parse::<Region>().is_err());
+    for address in ["0x+5", "0x-5", "+5", "-5", "0x"] {
+        assert_eq!(
+            Err(format!("invalid address `{}`", address)),
+            format!("{}:512:rw", address).parse::<Region>()
+        );
+    }
+}
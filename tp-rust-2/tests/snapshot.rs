@@ -0,0 +1,173 @@
+use interpreter::assembler::assemble;
+use interpreter::{Isa, Machine, MachineBuilder, MachineErrorKind, OutputMode, SnapshotError};
+
+const BOTTLES: &[u8] = include_bytes!("../examples/99bottles.bin");
+
+#[test]
+fn round_trip() {
+    let mut machine = Machine::new(BOTTLES);
+    machine.set_reg(5, 0xdeadbeef).unwrap();
+    let _ = machine.run_with_limit_on(&mut Vec::new(), 500);
+    let restored = Machine::restore(&machine.snapshot()).unwrap();
+    assert_eq!(machine.memory(), restored.memory());
+    assert_eq!(machine.regs(), restored.regs());
+    assert_eq!(500, restored.steps());
+}
+
+#[test]
+fn resume_gives_same_output() {
+    let mut reference = Vec::new();
+    Machine::new(BOTTLES).run_on(&mut reference).unwrap();
+
+    let mut out = Vec::new();
+    let mut machine = Machine::new(BOTTLES);
+    let e = machine.run_with_limit_on(&mut out, 10_000).unwrap_err();
+    assert!(matches!(
+        e.kind,
+        MachineErrorKind::StepLimitExceeded(10_000)
+    ));
+    let snapshot = machine.snapshot();
+    drop(machine);
+
+    let mut machine = Machine::restore(&snapshot).unwrap();
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(reference, out);
+}
+
+#[test]
+fn geometry_is_kept() {
+    let machine = MachineBuilder::new()
+        .memory_size(100)
+        .registers(40)
+        .register(39, 7)
+        .program(&[7])
+        .build()
+        .unwrap();
+    let restored = Machine::restore(&machine.snapshot()).unwrap();
+    assert_eq!(100, restored.memory().len());
+    assert_eq!(40, restored.regs().len());
+    assert_eq!(7, restored.regs()[39]);
+}
+
+#[test]
+fn truncated() {
+    let snapshot = Machine::new(&[7]).snapshot();
+    for len in 0..snapshot.len() {
+        assert!(
+            matches!(
+                Machine::restore(&snapshot[..len]),
+                Err(SnapshotError::Truncated { found, .. }) if found == len
+            ),
+            "prefix of {} bytes accepted",
+            len
+        );
+    }
+}
+
+#[test]
+fn trailing_data() {
+    let mut snapshot = Machine::new(&[7]).snapshot();
+    snapshot.extend_from_slice(&[0, 0]);
+    assert_eq!(
+        Some(SnapshotError::TrailingData(2)),
+        Machine::restore(&snapshot).err()
+    );
+}
+
+#[test]
+fn corrupted() {
+    let snapshot = Machine::new(&[7]).snapshot();
+
+    let mut bad = snapshot.clone();
+    bad[0] = b'X';
+    assert_eq!(Some(SnapshotError::BadMagic), Machine::restore(&bad).err());
+
+    let mut bad = snapshot.clone();
+    bad[8] = 2;
+    assert_eq!(
+        Some(SnapshotError::UnsupportedVersion(2)),
+        Machine::restore(&bad).err()
+    );
+
+    // Flipping any bit of the state is detected
+    for offset in [22, 30, 100, snapshot.len() - 5] {
+        let mut bad = snapshot.clone();
+        bad[offset] ^= 1;
+        assert_eq!(
+            Some(SnapshotError::ChecksumMismatch),
+            Machine::restore(&bad).err()
+        );
+    }
+}
+
+#[test]
+fn invalid_geometry() {
+    let mut snapshot = Machine::new(&[7]).snapshot();
+    snapshot[10..14].copy_from_slice(&0u32.to_le_bytes());
+    assert_eq!(
+        Some(SnapshotError::InvalidGeometry {
+            nregs: 0,
+            memory_size: 4096
+        }),
+        Machine::restore(&snapshot).err()
+    );
+
+    // A huge memory size must be rejected without being allocated
+    let mut snapshot = Machine::new(&[7]).snapshot();
+    snapshot[14..22].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(matches!(
+        Machine::restore(&snapshot),
+        Err(SnapshotError::InvalidGeometry { .. })
+    ));
+    snapshot[14..22].copy_from_slice(&(1u64 << 32).to_le_bytes());
+    assert!(matches!(
+        Machine::restore(&snapshot),
+        Err(SnapshotError::Truncated { .. })
+    ));
+}
+
+/// Replace the checksum of a modified snapshot.
+fn reseal(snapshot: &mut Vec<u8>) {
+    snapshot.truncate(snapshot.len() - 4);
+    let checksum = snapshot.iter().fold(0x811c9dc5u32, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    });
+    snapshot.extend_from_slice(&checksum.to_le_bytes());
+}
+
+#[test]
+fn settings_are_kept() {
+    let code = assemble("loadimm r1 <- #0xe9\nout r1\nexit r1").unwrap();
+    let mut machine = MachineBuilder::new()
+        .isa(Isa::Extended)
+        .output_mode(OutputMode::Raw)
+        .program(&code)
+        .build()
+        .unwrap();
+    let mut out = Vec::new();
+    assert_eq!(0xe9, machine.run_on(&mut out).unwrap());
+    let restored = Machine::restore(&machine.snapshot()).unwrap();
+    assert_eq!(Isa::Extended, restored.isa());
+    assert_eq!(OutputMode::Raw, restored.output_mode());
+    assert_eq!(0xe9, restored.exit_status());
+
+    // Resuming before the extended `exit r1`
+    let mut machine = Machine::restore(&machine.snapshot()).unwrap();
+    machine.set_reg(0, 4).unwrap();
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(b"\xe9\xe9", &out[..]);
+}
+
+#[test]
+fn invalid_settings() {
+    let mut snapshot = Machine::new(&[7]).snapshot();
+    snapshot[31] = 2;
+    reseal(&mut snapshot);
+    assert_eq!(
+        Some(SnapshotError::InvalidSettings {
+            isa: 0,
+            output_mode: 2
+        }),
+        Machine::restore(&snapshot).err()
+    );
+}
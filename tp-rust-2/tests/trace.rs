@@ -0,0 +1,89 @@
+use interpreter::{Machine, MachineError, MachineErrorKind};
+
+fn run_traced(machine: &mut Machine) -> (Result<u32, MachineError>, String) {
+    let mut trace = Vec::new();
+    let result = machine.run_traced(&mut Vec::new(), &mut trace);
+    (result, String::from_utf8(trace).unwrap())
+}
+
+#[test]
+fn trace_function_call() {
+    let mut machine = Machine::new(include_bytes!("function.bin"));
+    let (result, trace) = run_traced(&mut machine);
+    result.unwrap();
+    let lines: Vec<&str> = trace.lines().collect();
+    assert_eq!(
+        vec![
+            "0000   loadimm r2 <- #4096          r2 = 0x00001000",
+            "0004   loadimm r3 <- #4             r3 = 0x00000004",
+            "0008   sub r2 <- r2 - r3            r2 = 0x00000ffc",
+            "0012   loadimm r3 <- #23            r3 = 0x00000017",
+            "0016   store [r2] <- r3             [4092] = 0x00000017",
+            "0019   loadimm r0 <- #24            r0 = 0x00000018",
+            "0024   loadimm r10 <- #42           r10 = 0x0000002a",
+        ],
+        lines[..7]
+    );
+    assert_eq!(
+        "0044   load r0 <- [r3]              r0 = 0x00000017",
+        lines[11]
+    );
+    assert_eq!("0023   exit", lines[12]);
+    assert_eq!(13, lines.len());
+}
+
+#[test]
+fn trace_move_if() {
+    // 0: move r1 <- r2 if r2 != 0
+    // 4: move r3 <- r2 if r3 != 0
+    // 8: out_number r1
+    // 10: exit
+    let mut machine = Machine::new(&[1, 1, 2, 2, 1, 3, 2, 3, 8, 1, 7]);
+    machine.set_reg(2, 42).unwrap();
+    let (_, trace) = run_traced(&mut machine);
+    assert_eq!(
+        "0000   move r1 <- r2 if r2 != 0     r1 = 0x0000002a\n\
+         0004   move r3 <- r2 if r3 != 0\n\
+         0008   out_number r1\n\
+         0010   exit\n",
+        trace
+    );
+}
+
+#[test]
+fn trace_error() {
+    // 0: sub r100 <- r0 - r0
+    let mut machine = Machine::new(&[5, 100, 0, 0]);
+    let (result, trace) = run_traced(&mut machine);
+    assert!(matches!(
+        result.unwrap_err().kind,
+        MachineErrorKind::RegisterOutOfBounds(100)
+    ));
+    assert_eq!(
+        "0000   sub r100 <- r0 - r0          error: register r100 does not exist\n",
+        trace
+    );
+
+    let mut machine = Machine::new(&[0xff]);
+    let (_, trace) = run_traced(&mut machine);
+    assert_eq!(
+        "0000   ???                          error: invalid opcode 255\n",
+        trace
+    );
+}
+
+#[test]
+fn trace_input() {
+    // 0: in_number r1, r2
+    // 3: exit
+    let mut machine = Machine::new(&[10, 1, 2, 7]);
+    let mut trace = Vec::new();
+    machine
+        .run_traced_with_io(&mut &b"12"[..], &mut Vec::new(), &mut trace)
+        .unwrap();
+    assert_eq!(
+        "0000   in_number r1, r2             r1 = 0x0000000c r2 = 0x00000000\n\
+         0003   exit\n",
+        String::from_utf8(trace).unwrap()
+    );
+}
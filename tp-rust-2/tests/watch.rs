@@ -0,0 +1,208 @@
+use interpreter::assembler::assemble;
+use interpreter::{Isa, Machine, MachineError, MachineErrorKind, WatchEvent, WatchHit, Watchpoint};
+
+fn hit(e: &MachineError) -> Option<WatchHit> {
+    match e.kind {
+        MachineErrorKind::Watchpoint(hit) => Some(hit),
+        _ => None,
+    }
+}
+
+#[test]
+fn write_to_memory() {
+    let code = assemble(
+        "
+        loadimm r1 <- #100
+        loadimm r2 <- #7
+        store [r1] <- r2
+        loadimm r2 <- #8
+        store [r1] <- r2
+        exit
+    ",
+    )
+    .unwrap();
+    let mut machine = Machine::new(&code);
+    machine.set_mem(100, &[3, 0, 0, 0]).unwrap();
+    let watchpoint = Watchpoint::Write {
+        start: 102,
+        end: 103,
+    };
+    assert!(machine.add_watchpoint(watchpoint));
+    assert!(!machine.add_watchpoint(watchpoint));
+
+    let e = machine.run().unwrap_err();
+    assert_eq!((Some(8), Some(2)), (e.ip, e.opcode));
+    assert_eq!(
+        Some(WatchHit {
+            watchpoint,
+            event: WatchEvent::Write {
+                address: 100,
+                old: 3,
+                new: 7
+            },
+        }),
+        hit(&e)
+    );
+    assert_eq!(
+        "watchpoint on write 0102..0103 hit: write [100] 0x00000003 -> 0x00000007 \
+         at address 8 (opcode 2)",
+        e.to_string()
+    );
+    // The store has been executed
+    assert_eq!(&[7, 0, 0, 0], &machine.memory()[100..104]);
+    assert_eq!(11, machine.regs()[0]);
+
+    // Running again resumes after the store
+    let e = machine.run().unwrap_err();
+    assert_eq!(Some(15), e.ip);
+    assert!(matches!(
+        hit(&e).unwrap().event,
+        WatchEvent::Write { old: 7, new: 8, .. }
+    ));
+    machine.run().unwrap();
+}
+
+#[test]
+fn reads_and_accesses() {
+    let code = assemble(
+        "
+        loadimm r1 <- #200
+        load r2 <- [r1]
+        store [r1] <- r2
+        exit
+    ",
+    )
+    .unwrap();
+    let mut machine = Machine::new(&code);
+    machine.set_mem(200, &[5, 0, 0, 0]).unwrap();
+    machine.add_watchpoint(Watchpoint::Read {
+        start: 200,
+        end: 204,
+    });
+    let e = machine.run().unwrap_err();
+    assert_eq!(Some(4), e.ip);
+    assert_eq!(
+        WatchEvent::Read {
+            address: 200,
+            value: 5
+        },
+        hit(&e).unwrap().event
+    );
+    // The store is not a read
+    machine.run().unwrap();
+
+    let mut machine = Machine::new(&code);
+    machine.add_watchpoint(Watchpoint::Access {
+        start: 203,
+        end: 210,
+    });
+    assert_eq!(Some(4), machine.run().unwrap_err().ip);
+    assert_eq!(Some(7), machine.run().unwrap_err().ip);
+    machine.run().unwrap();
+}
+
+#[test]
+fn register_changes() {
+    let code = assemble(
+        "
+        loadimm r3 <- #1
+        loadimm r3 <- #1
+        loadimm r4 <- #2
+        loadimm r3 <- #2
+        exit
+    ",
+    )
+    .unwrap();
+    let mut machine = Machine::new(&code);
+    machine.add_watchpoint(Watchpoint::Register(3));
+    let e = machine.run().unwrap_err();
+    assert_eq!(Some(0), e.ip);
+    assert_eq!(
+        "watchpoint on register r3 hit: r3 0x00000000 -> 0x00000001 at address 0 (opcode 4)",
+        e.to_string()
+    );
+    // Writing the same value is not a change
+    let e = machine.run().unwrap_err();
+    assert_eq!(Some(12), e.ip);
+    assert_eq!(
+        WatchEvent::Register {
+            reg: 3,
+            old: 1,
+            new: 2
+        },
+        hit(&e).unwrap().event
+    );
+    machine.run().unwrap();
+    assert_eq!(2, machine.regs()[4]);
+}
+
+#[test]
+fn byte_accesses() {
+    let code = assemble(
+        "
+        loadimm r1 <- #301
+        loadimm r2 <- #0x1234
+        storeb [r1] <- r2
+        loadb r3 <- [r1]
+        exit
+    ",
+    )
+    .unwrap();
+    let mut machine = Machine::new(&code);
+    machine.set_isa(Isa::Extended);
+    machine.set_mem(301, &[9]).unwrap();
+    machine.add_watchpoint(Watchpoint::Access {
+        start: 300,
+        end: 302,
+    });
+    let e = machine.run().unwrap_err();
+    assert_eq!(
+        WatchEvent::Write {
+            address: 301,
+            old: 9,
+            new: 0x34
+        },
+        hit(&e).unwrap().event
+    );
+    let e = machine.run().unwrap_err();
+    assert_eq!(
+        WatchEvent::Read {
+            address: 301,
+            value: 0x34
+        },
+        hit(&e).unwrap().event
+    );
+    machine.run().unwrap();
+    assert_eq!(0x34, machine.regs()[3]);
+}
+
+#[test]
+fn fast_engine_stops_too() {
+    let mut machine = Machine::new(include_bytes!("fact.bin"));
+    machine.set_reg(10, 5).unwrap();
+    machine.add_watchpoint(Watchpoint::Register(11));
+    let mut changes = Vec::new();
+    while let Err(e) = machine.run_fast() {
+        match hit(&e).unwrap().event {
+            WatchEvent::Register { new, .. } => changes.push(new),
+            event => panic!("unexpected {:?}", event),
+        }
+    }
+    assert_eq!(Some(&120), changes.last());
+    assert!(changes.contains(&20));
+}
+
+#[test]
+fn removed_watchpoint() {
+    let mut machine = Machine::new(include_bytes!("fact.bin"));
+    machine.set_reg(10, 3).unwrap();
+    let watchpoint = Watchpoint::Register(11);
+    machine.add_watchpoint(watchpoint);
+    machine.add_watchpoint(Watchpoint::Register(12));
+    assert!(machine.remove_watchpoint(watchpoint));
+    assert!(!machine.remove_watchpoint(watchpoint));
+    assert_eq!(&[Watchpoint::Register(12)], machine.watchpoints());
+    assert!(machine.remove_watchpoint(Watchpoint::Register(12)));
+    machine.run().unwrap();
+    assert_eq!(6, machine.regs()[11]);
+}